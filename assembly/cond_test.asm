@@ -5,6 +5,8 @@
 
 ; @Io3dev 2022
 
+; expect mem[0x1111]=0x01
+
 LXI SP, 0xFF
 JMP jifz
 
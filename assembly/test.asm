@@ -1 +1,4 @@
-mvi b, 10
\ No newline at end of file
+; expect B=0x0A
+
+mvi b, 10
+hlt
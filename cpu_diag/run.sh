@@ -0,0 +1,37 @@
+#!/bin/sh
+# Copies the exerciser binaries found in DIR into cpu_diag/, checks them
+# against SHA256SUMS and runs the tests for the ones that are there
+#
+#     cpu_diag/run.sh ~/Downloads/cpm
+#
+# Names are matched in upper or lower case, a file that doesn't match its
+# sum stops the run before anything is tested
+
+set -e
+dir=${1:?usage: cpu_diag/run.sh DIR}
+cd "$(dirname "$0")"
+
+tests=""
+while read -r sum name; do
+    lower=$(echo "$name" | tr 'A-Z' 'a-z')
+    for src in "$dir/$name" "$dir/$lower"; do
+        if [ -f "$src" ]; then
+            cp "$src" "$name"
+            echo "$sum  $name" | sha256sum -c -
+            case $name in
+                TST8080.COM) tests="$tests cpu::cpu_diag::tst8080" ;;
+                CPUTEST.COM) tests="$tests cpu::cpu_diag::cputest" ;;
+                8080EXM.COM) tests="$tests cpu::cpu_diag::exerciser" ;;
+                ZEXDOC.COM) tests="$tests cpu::cpu_diag::zexdoc" ;;
+                ZEXALL.COM) tests="$tests cpu::cpu_diag::zexall" ;;
+            esac
+            break
+        fi
+    done
+done < SHA256SUMS
+
+if [ -z "$tests" ]; then
+    echo "no exerciser binaries in $dir" >&2
+    exit 1
+fi
+cargo test --release --lib -- --ignored --exact $tests
@@ -0,0 +1,634 @@
+// A small two pass Intel 8080 assembler
+//
+// Understands the mnemonics used by the files in assembly/, labels, EQU,
+// ORG, DB, DW and DS, and simple expressions over numbers, labels and $
+
+#[cfg(test)]
+mod runner;
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+// Assembled program, bytes are contiguous starting at origin
+
+#[derive(Debug, Default)]
+pub struct Assembled {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub symbols: HashMap<String, u16>,
+}
+
+struct Line {
+    number: usize,
+    label: Option<String>,
+    mnemonic: Option<String>,
+    operands: Vec<String>,
+}
+
+pub fn assemble(source: &str) -> Result<Assembled, AsmError> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(i, text)| parse_line(i + 1, text))
+        .collect::<Result<Vec<Line>, AsmError>>()?;
+
+    let mut symbols = HashMap::new();
+
+    // Pass 1: work out where every label lands
+    let mut addr: u16 = 0;
+    for line in &lines {
+        let mnemonic = line.mnemonic.as_deref().unwrap_or("");
+
+        if mnemonic == "EQU" {
+            let name = line.label.clone().ok_or_else(|| error(line, "EQU without a name"))?;
+            let value = eval(operand(line, 0)?, &symbols, addr, line)?;
+            symbols.insert(name, value);
+            continue;
+        }
+
+        if mnemonic == "ORG" {
+            addr = eval(operand(line, 0)?, &symbols, addr, line)?;
+        }
+
+        if let Some(label) = &line.label {
+            if symbols.insert(label.clone(), addr).is_some() {
+                return Err(error(line, &format!("duplicate label {}", label)));
+            }
+        }
+
+        addr = addr.wrapping_add(size_of(line, &symbols, addr)?);
+    }
+
+    // Pass 2: emit the bytes
+    let mut image: Vec<(u16, u8)> = Vec::new();
+    let mut addr: u16 = 0;
+    for line in &lines {
+        let mnemonic = match line.mnemonic.as_deref() {
+            Some(m) => m,
+            None => continue,
+        };
+
+        match mnemonic {
+            "EQU" | "END" => {}
+            "ORG" => addr = eval(operand(line, 0)?, &symbols, addr, line)?,
+            _ => {
+                for b in encode(line, &symbols, addr)? {
+                    image.push((addr, b));
+                    addr = addr.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    let origin = image.iter().map(|&(a, _)| a).min().unwrap_or(0);
+    let end = image.iter().map(|&(a, _)| a as usize + 1).max().unwrap_or(origin as usize);
+    let mut bytes = vec![0; end - origin as usize];
+    for (a, b) in image {
+        bytes[(a - origin) as usize] = b;
+    }
+
+    Ok(Assembled { origin, bytes, symbols })
+}
+
+fn error(line: &Line, msg: &str) -> AsmError {
+    AsmError {
+        line: line.number,
+        msg: msg.to_string(),
+    }
+}
+
+fn operand(line: &Line, n: usize) -> Result<&str, AsmError> {
+    line.operands
+        .get(n)
+        .map(|s| s.as_str())
+        .ok_or_else(|| error(line, "missing operand"))
+}
+
+// Strips the comment and splits a line into label, mnemonic and operands
+
+fn parse_line(number: usize, text: &str) -> Result<Line, AsmError> {
+    let mut code = String::new();
+    let mut in_quote = false;
+    for ch in text.chars() {
+        if ch == '\'' {
+            in_quote = !in_quote;
+        }
+        if ch == ';' && !in_quote {
+            break;
+        }
+        code.push(ch);
+    }
+
+    let mut rest = code.trim();
+    let mut label = None;
+
+    if let Some(pos) = rest.find(':') {
+        if !rest[..pos].contains('\'') {
+            label = Some(rest[..pos].trim().to_uppercase());
+            rest = rest[pos + 1..].trim();
+        }
+    }
+
+    let (mut mnemonic, mut args) = match rest.find(char::is_whitespace) {
+        Some(pos) => (rest[..pos].to_uppercase(), rest[pos..].trim()),
+        None => (rest.to_uppercase(), ""),
+    };
+
+    // "NAME EQU value" with no colon after the name
+    if label.is_none() && !args.is_empty() {
+        let (second, tail) = match args.find(char::is_whitespace) {
+            Some(pos) => (args[..pos].to_uppercase(), args[pos..].trim()),
+            None => (args.to_uppercase(), ""),
+        };
+        if second == "EQU" {
+            label = Some(mnemonic);
+            mnemonic = second;
+            args = tail;
+        }
+    }
+
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut in_quote = false;
+    for ch in args.chars() {
+        if ch == '\'' {
+            in_quote = !in_quote;
+        }
+        if ch == ',' && !in_quote {
+            operands.push(current.trim().to_string());
+            current.clear();
+        } else {
+            current.push(ch);
+        }
+    }
+    if !current.trim().is_empty() {
+        operands.push(current.trim().to_string());
+    }
+
+    let line = Line {
+        number,
+        label,
+        mnemonic: if mnemonic.is_empty() { None } else { Some(mnemonic) },
+        operands,
+    };
+
+    if let Some(label) = &line.label {
+        if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            return Err(error(&line, &format!("bad label {:?}", label)));
+        }
+    }
+
+    Ok(line)
+}
+
+fn size_of(line: &Line, symbols: &HashMap<String, u16>, addr: u16) -> Result<u16, AsmError> {
+    let mnemonic = match line.mnemonic.as_deref() {
+        Some(m) => m,
+        None => return Ok(0),
+    };
+
+    let size = match mnemonic {
+        "ORG" | "END" | "EQU" => 0,
+        "DB" => {
+            let mut n = 0;
+            for op in &line.operands {
+                n += match string_literal(op) {
+                    Some(s) if s.len() != 1 => s.len() as u16,
+                    _ => 1,
+                };
+            }
+            n
+        }
+        "DW" => 2 * line.operands.len() as u16,
+        "DS" => eval(operand(line, 0)?, symbols, addr, line)?,
+        _ => match opcode_size(mnemonic) {
+            Some(n) => n,
+            None => return Err(error(line, &format!("unknown mnemonic {}", mnemonic))),
+        },
+    };
+
+    Ok(size)
+}
+
+fn opcode_size(mnemonic: &str) -> Option<u16> {
+    let size = match mnemonic {
+        "MVI" | "ADI" | "ACI" | "SUI" | "SBI" | "ANI" | "XRI" | "ORI" | "CPI" | "IN" | "OUT" => 2,
+        "LXI" | "LDA" | "STA" | "LHLD" | "SHLD" | "JMP" | "JNZ" | "JZ" | "JNC" | "JC" | "JPO"
+        | "JPE" | "JP" | "JM" | "CALL" | "CNZ" | "CZ" | "CNC" | "CC" | "CPO" | "CPE" | "CP"
+        | "CM" => 3,
+        _ => {
+            implied(mnemonic)?;
+            1
+        }
+    };
+    Some(size)
+}
+
+// Returns the base opcode of every one byte instruction, used both for sizing
+// and (for the ones without operands) encoding
+
+fn implied(mnemonic: &str) -> Option<u8> {
+    let op = match mnemonic {
+        "NOP" => 0x00,
+        "RLC" => 0x07,
+        "RRC" => 0x0F,
+        "RAL" => 0x17,
+        "RAR" => 0x1F,
+        "DAA" => 0x27,
+        "CMA" => 0x2F,
+        "STC" => 0x37,
+        "CMC" => 0x3F,
+        "HLT" => 0x76,
+        "RNZ" => 0xC0,
+        "RZ" => 0xC8,
+        "RET" => 0xC9,
+        "RNC" => 0xD0,
+        "RC" => 0xD8,
+        "RPO" => 0xE0,
+        "RPE" => 0xE8,
+        "RP" => 0xF0,
+        "RM" => 0xF8,
+        "XTHL" => 0xE3,
+        "PCHL" => 0xE9,
+        "XCHG" => 0xEB,
+        "DI" => 0xF3,
+        "SPHL" => 0xF9,
+        "EI" => 0xFB,
+
+        "MOV" => 0x40,
+        "ADD" => 0x80,
+        "ADC" => 0x88,
+        "SUB" => 0x90,
+        "SBB" => 0x98,
+        "ANA" => 0xA0,
+        "XRA" => 0xA8,
+        "ORA" => 0xB0,
+        "CMP" => 0xB8,
+        "INR" => 0x04,
+        "DCR" => 0x05,
+        "INX" => 0x03,
+        "DCX" => 0x0B,
+        "DAD" => 0x09,
+        "PUSH" => 0xC5,
+        "POP" => 0xC1,
+        "LDAX" => 0x0A,
+        "STAX" => 0x02,
+        "RST" => 0xC7,
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn encode(line: &Line, symbols: &HashMap<String, u16>, addr: u16) -> Result<Vec<u8>, AsmError> {
+    let mnemonic = line.mnemonic.as_deref().unwrap_or("");
+    let value = |n: usize| -> Result<u16, AsmError> { eval(operand(line, n)?, symbols, addr, line) };
+    let byte = |n: usize| -> Result<u8, AsmError> {
+        let v = value(n)?;
+        if v > 0xFF && v < 0xFF80 {
+            return Err(error(line, &format!("value 0x{:X} does not fit in a byte", v)));
+        }
+        Ok(v as u8)
+    };
+    let reg = |n: usize| -> Result<u8, AsmError> { register(operand(line, n)?).ok_or_else(|| error(line, "expected a register")) };
+    let pair = |n: usize, psw: bool| -> Result<u8, AsmError> {
+        match (operand(line, n)?.to_uppercase().as_str(), psw) {
+            ("B" | "BC", _) => Ok(0),
+            ("D" | "DE", _) => Ok(1),
+            ("H" | "HL", _) => Ok(2),
+            ("SP", false) => Ok(3),
+            ("PSW", true) => Ok(3),
+            _ => Err(error(line, "expected a register pair")),
+        }
+    };
+
+    let bytes = match mnemonic {
+        "DB" => {
+            let mut out = Vec::new();
+            for (n, op) in line.operands.iter().enumerate() {
+                match string_literal(op) {
+                    Some(s) if s.len() != 1 => out.extend(s.bytes()),
+                    _ => out.push(byte(n)?),
+                }
+            }
+            out
+        }
+        "DW" => {
+            let mut out = Vec::new();
+            for n in 0..line.operands.len() {
+                let v = value(n)?;
+                out.push((v & 0xff) as u8);
+                out.push((v >> 8) as u8);
+            }
+            out
+        }
+        "DS" => vec![0; value(0)? as usize],
+
+        "MOV" => {
+            let (d, s) = (reg(0)?, reg(1)?);
+            if d == 6 && s == 6 {
+                return Err(error(line, "MOV M, M is HLT"));
+            }
+            vec![0x40 | d << 3 | s]
+        }
+        "MVI" => vec![0x06 | reg(0)? << 3, byte(1)?],
+        "ADD" | "ADC" | "SUB" | "SBB" | "ANA" | "XRA" | "ORA" | "CMP" => {
+            vec![implied(mnemonic).unwrap() | reg(0)?]
+        }
+        "INR" | "DCR" => vec![implied(mnemonic).unwrap() | reg(0)? << 3],
+        "INX" | "DCX" | "DAD" => vec![implied(mnemonic).unwrap() | pair(0, false)? << 4],
+        "PUSH" | "POP" => vec![implied(mnemonic).unwrap() | pair(0, true)? << 4],
+        "LDAX" | "STAX" => match pair(0, false)? {
+            rp @ (0 | 1) => vec![implied(mnemonic).unwrap() | rp << 4],
+            _ => return Err(error(line, "LDAX/STAX only take B or D")),
+        },
+        "LXI" => {
+            let v = value(1)?;
+            vec![0x01 | pair(0, false)? << 4, (v & 0xff) as u8, (v >> 8) as u8]
+        }
+        "RST" => {
+            let n = value(0)?;
+            if n > 7 {
+                return Err(error(line, "RST vector must be 0-7"));
+            }
+            vec![0xC7 | (n as u8) << 3]
+        }
+
+        "ADI" | "ACI" | "SUI" | "SBI" | "ANI" | "XRI" | "ORI" | "CPI" | "IN" | "OUT" => {
+            let op = match mnemonic {
+                "ADI" => 0xC6,
+                "ACI" => 0xCE,
+                "SUI" => 0xD6,
+                "SBI" => 0xDE,
+                "ANI" => 0xE6,
+                "XRI" => 0xEE,
+                "ORI" => 0xF6,
+                "CPI" => 0xFE,
+                "IN" => 0xDB,
+                _ => 0xD3,
+            };
+            vec![op, byte(0)?]
+        }
+
+        "LDA" | "STA" | "LHLD" | "SHLD" | "JMP" | "JNZ" | "JZ" | "JNC" | "JC" | "JPO" | "JPE"
+        | "JP" | "JM" | "CALL" | "CNZ" | "CZ" | "CNC" | "CC" | "CPO" | "CPE" | "CP" | "CM" => {
+            let op = match mnemonic {
+                "LDA" => 0x3A,
+                "STA" => 0x32,
+                "LHLD" => 0x2A,
+                "SHLD" => 0x22,
+                "JMP" => 0xC3,
+                "JNZ" => 0xC2,
+                "JZ" => 0xCA,
+                "JNC" => 0xD2,
+                "JC" => 0xDA,
+                "JPO" => 0xE2,
+                "JPE" => 0xEA,
+                "JP" => 0xF2,
+                "JM" => 0xFA,
+                "CALL" => 0xCD,
+                "CNZ" => 0xC4,
+                "CZ" => 0xCC,
+                "CNC" => 0xD4,
+                "CC" => 0xDC,
+                "CPO" => 0xE4,
+                "CPE" => 0xEC,
+                "CP" => 0xF4,
+                _ => 0xFC,
+            };
+            let v = value(0)?;
+            vec![op, (v & 0xff) as u8, (v >> 8) as u8]
+        }
+
+        _ => match implied(mnemonic) {
+            Some(op) if line.operands.is_empty() => vec![op],
+            Some(_) => return Err(error(line, &format!("{} takes no operands", mnemonic))),
+            None => return Err(error(line, &format!("unknown mnemonic {}", mnemonic))),
+        },
+    };
+
+    Ok(bytes)
+}
+
+fn register(name: &str) -> Option<u8> {
+    let r = match name.to_uppercase().as_str() {
+        "B" => 0,
+        "C" => 1,
+        "D" => 2,
+        "E" => 3,
+        "H" => 4,
+        "L" => 5,
+        "M" => 6,
+        "A" => 7,
+        _ => return None,
+    };
+    Some(r)
+}
+
+fn string_literal(op: &str) -> Option<&str> {
+    if op.len() >= 2 && op.starts_with('\'') && op.ends_with('\'') {
+        Some(&op[1..op.len() - 1])
+    } else {
+        None
+    }
+}
+
+/*
+Expressions
+*/
+
+fn eval(text: &str, symbols: &HashMap<String, u16>, addr: u16, line: &Line) -> Result<u16, AsmError> {
+    let tokens = tokenize(text).map_err(|msg| error(line, &msg))?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        symbols,
+        addr,
+    };
+    let value = parser.expr().map_err(|msg| error(line, &msg))?;
+    if parser.pos != parser.tokens.len() {
+        return Err(error(line, &format!("trailing characters in {:?}", text)));
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(u16),
+    Ident(String),
+    Op(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch.is_whitespace() {
+            i += 1;
+        } else if ch == '\'' {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|&c| c == '\'')
+                .ok_or("unterminated character")?;
+            let body: String = chars[i + 1..i + 1 + end].iter().collect();
+            if body.chars().count() != 1 {
+                return Err(format!("bad character literal '{}'", body));
+            }
+            tokens.push(Token::Num(body.chars().next().unwrap() as u16));
+            i += end + 2;
+        } else if ch.is_ascii_alphanumeric() || ch == '_' || ch == '.' || (ch == '$' && i + 1 < chars.len() && chars[i + 1].is_ascii_hexdigit()) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.' || chars[i] == '$') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if ch.is_ascii_digit() || ch == '$' {
+                tokens.push(Token::Num(number(&word)?));
+            } else {
+                tokens.push(Token::Ident(word.to_uppercase()));
+            }
+        } else if "+-*/&|()$".contains(ch) {
+            tokens.push(Token::Op(ch));
+            i += 1;
+        } else {
+            return Err(format!("unexpected character {:?}", ch));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn number(word: &str) -> Result<u16, String> {
+    let lower = word.to_lowercase();
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(hex) = lower.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(hex) = lower.strip_suffix('h') {
+        (hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (bin, 2)
+    } else if let Some(bin) = lower.strip_suffix('b').filter(|b| b.chars().all(|c| c == '0' || c == '1')) {
+        (bin, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+
+    u32::from_str_radix(digits, radix)
+        .ok()
+        .filter(|&v| v <= 0xFFFF)
+        .map(|v| v as u16)
+        .ok_or_else(|| format!("bad number {}", word))
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: &'a HashMap<String, u16>,
+    addr: u16,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(c)) => Some(*c),
+            _ => None,
+        }
+    }
+
+    // expr := term (('+' | '-' | '&' | '|') term)*
+
+    fn expr(&mut self) -> Result<u16, String> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-' | '&' | '|')) = self.peek_op() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = match op {
+                '+' => value.wrapping_add(rhs),
+                '-' => value.wrapping_sub(rhs),
+                '&' => value & rhs,
+                _ => value | rhs,
+            };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<u16, String> {
+        let mut value = self.unary()?;
+        while let Some(op @ ('*' | '/')) = self.peek_op() {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            value = match op {
+                '*' => value.wrapping_mul(rhs),
+                _ => value.checked_div(rhs).ok_or("division by zero")?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<u16, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("missing value")?;
+        self.pos += 1;
+
+        match token {
+            Token::Num(n) => Ok(n),
+            Token::Ident(name) => match self.symbols.get(&name) {
+                Some(&v) => Ok(v),
+                None => Err(format!("unknown symbol {}", name)),
+            },
+            Token::Op('$') => Ok(self.addr),
+            Token::Op('-') => Ok(self.unary()?.wrapping_neg()),
+            Token::Op('(') => {
+                let v = self.expr()?;
+                if self.peek_op() != Some(')') {
+                    return Err("missing )".to_string());
+                }
+                self.pos += 1;
+                Ok(v)
+            }
+            Token::Op(c) => Err(format!("unexpected {:?}", c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod asm_test {
+    use super::*;
+
+    #[test]
+    fn encodes_operands() {
+        let prog = assemble("LXI SP, 0xFF\nMVI B, 10\nMOV A, M\nPUSH PSW\nRST 7\nDB 'OK$', 0DH\n").unwrap();
+        assert_eq!(
+            prog.bytes,
+            [0x31, 0xFF, 0x00, 0x06, 0x0A, 0x7E, 0xF5, 0xFF, b'O', b'K', b'$', 0x0D]
+        );
+    }
+
+    #[test]
+    fn forward_labels_and_org() {
+        let prog = assemble("ORG 100h\nSTART: JMP END1\nNOP\nEND1: JMP $\n").unwrap();
+        assert_eq!(prog.origin, 0x100);
+        assert_eq!(prog.bytes, [0xC3, 0x04, 0x01, 0x00, 0xC3, 0x04, 0x01]);
+        assert_eq!(prog.symbols["START"], 0x100);
+    }
+
+    #[test]
+    fn reports_line_of_error() {
+        let err = assemble("NOP\nFOO B\n").unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+}
@@ -0,0 +1,201 @@
+// Runs every program in assembly/ and checks the expectations written in its
+// comments, for example:
+//
+// ; expect mem[0x1111]=0x01
+// ; expect A=0x42
+// ; expect CY=1
+//
+// A program finishes when it halts or spins in a jump to itself
+
+use super::assemble;
+use crate::cpu::Cpu;
+use crate::EmuError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CYCLE_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, PartialEq)]
+enum Target {
+    Mem(u16),
+    Reg(String),
+}
+
+#[derive(Debug, PartialEq)]
+struct Expect {
+    target: Target,
+    value: u16,
+}
+
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Halted,
+    SelfJump,
+    CycleLimit,
+    // The cpu refused an instruction, only this program fails
+    Error(EmuError),
+}
+
+fn parse_value(text: &str) -> Option<u16> {
+    let text = text.trim().to_lowercase();
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_expectations(source: &str) -> Result<Vec<Expect>, String> {
+    let mut expects = Vec::new();
+
+    for line in source.lines() {
+        let comment = match line.trim().strip_prefix(';') {
+            Some(c) => c.trim(),
+            None => continue,
+        };
+        let body = match comment.strip_prefix("expect ") {
+            Some(b) => b,
+            None => continue,
+        };
+
+        let (lhs, rhs) = body.split_once('=').ok_or(format!("bad expectation {:?}", body))?;
+        let value = parse_value(rhs).ok_or(format!("bad value in {:?}", body))?;
+        let lhs = lhs.trim().to_uppercase();
+
+        let target = match lhs.strip_prefix("MEM[").and_then(|s| s.strip_suffix(']')) {
+            Some(addr) => Target::Mem(parse_value(addr).ok_or(format!("bad address in {:?}", body))?),
+            None => Target::Reg(lhs),
+        };
+
+        expects.push(Expect { target, value });
+    }
+
+    Ok(expects)
+}
+
+fn run(cpu: &mut Cpu) -> Stop {
+    for _ in 0..CYCLE_LIMIT {
+        if cpu.hlted {
+            return Stop::Halted;
+        }
+
+        let pc = cpu.regs.pc as usize;
+        let byte = |n: usize| cpu.memory[pc.wrapping_add(n) & 0xFFFF];
+        if byte(0) == 0xC3 && cpu.cmb_le(byte(1), byte(2)) as usize == pc {
+            return Stop::SelfJump;
+        }
+
+        if let Err(e) = cpu.cycle() {
+            return Stop::Error(e);
+        }
+    }
+
+    Stop::CycleLimit
+}
+
+fn actual(cpu: &Cpu, target: &Target) -> Result<u16, String> {
+    let value = match target {
+        Target::Mem(addr) => cpu.memory[*addr as usize] as u16,
+        Target::Reg(name) => match name.as_str() {
+            "A" => cpu.regs.a as u16,
+            "B" => cpu.regs.b as u16,
+            "C" => cpu.regs.c as u16,
+            "D" => cpu.regs.d as u16,
+            "E" => cpu.regs.e as u16,
+            "H" => cpu.regs.h as u16,
+            "L" => cpu.regs.l as u16,
+            "BC" => cpu.cmb_be(cpu.regs.b, cpu.regs.c),
+            "DE" => cpu.cmb_be(cpu.regs.d, cpu.regs.e),
+            "HL" => cpu.cmb_be(cpu.regs.h, cpu.regs.l),
+            "SP" => cpu.regs.sp,
+            "PC" => cpu.regs.pc,
+            "S" => cpu.flags.sign as u16,
+            "Z" => cpu.flags.zero as u16,
+            "P" => cpu.flags.parity as u16,
+            "CY" => cpu.flags.carry as u16,
+            "AC" => cpu.flags.aux_carry as u16,
+            _ => return Err(format!("unknown register {}", name)),
+        },
+    };
+    Ok(value)
+}
+
+fn check_program(path: &Path) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let expects = parse_expectations(&source)?;
+    let prog = assemble(&source).map_err(|e| e.to_string())?;
+
+    let mut cpu = Cpu::init(prog.origin, &prog.bytes);
+    cpu.regs.pc = prog.origin;
+
+    match run(&mut cpu) {
+        Stop::CycleLimit => return Err(format!("did not finish within {} instructions", CYCLE_LIMIT)),
+        Stop::Error(e) => return Err(e.to_string()),
+        Stop::Halted | Stop::SelfJump => {}
+    }
+
+    for expect in &expects {
+        let got = actual(&cpu, &expect.target)?;
+        if got != expect.value {
+            return Err(format!(
+                "expected {:?} = 0x{:X}, got 0x{:X}",
+                expect.target, expect.value, got
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn asm_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).expect("assembly directory is missing") {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(asm_files(&path));
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("asm")) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn assembly_programs() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assembly");
+    let files = asm_files(&dir);
+    assert!(!files.is_empty(), "no programs found in {}", dir.display());
+
+    let failures: Vec<String> = files
+        .iter()
+        .filter_map(|path| check_program(path).err().map(|e| format!("{}: {}", path.display(), e)))
+        .collect();
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn stops_at_top_of_memory_and_on_errors() {
+    // JMP 0xFFFF with its operand wrapped round to 0x0000
+    let mut cpu = Cpu::init(0, &[0xFF, 0xFF]);
+    cpu.memory[0xFFFF] = 0xC3;
+    cpu.regs.pc = 0xFFFF;
+    assert_eq!(run(&mut cpu), Stop::SelfJump);
+
+    // 0x08 is an undocumented NOP, refused in strict mode
+    let mut cpu = Cpu::init(0, &[0x08]);
+    cpu.strict = true;
+    assert_eq!(run(&mut cpu), Stop::Error(EmuError::UndocumentedOpcode { opcode: 0x08, addr: 0 }));
+}
+
+#[test]
+fn expectation_syntax() {
+    let expects = parse_expectations("; expect mem[0x1111]=0x01\n;expect A = 66\nNOP ; expect B=1\n").unwrap();
+    assert_eq!(
+        expects,
+        [
+            Expect { target: Target::Mem(0x1111), value: 1 },
+            Expect { target: Target::Reg("A".to_string()), value: 66 },
+        ]
+    );
+}
@@ -0,0 +1,268 @@
+// Command line parsing
+//
+// The first argument picks the subcommand, options can come in any order
+// after it and the one bare argument is the ROM or program path.
+
+use space_invaders::config::Settings;
+use space_invaders::cpu::Variant;
+use space_invaders::frontend::{AudioBackend, VideoBackend};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: space_invaders <command> [path] [options]
+
+commands:
+  run [romdir]         play in the terminal, q quits
+  headless [romdir]    run without video, --frames and --screenshot
+  cpm <file.com>       run a CP/M program
+  disasm <rom>         disassemble a ROM or program
+  debug [romdir]       debug the cabinet, --gdb PORT serves gdb instead
+  trace [romdir]       print every instruction as it runs
+  help                 show this
+
+romdir is a directory with invaders.h, .g, .f and .e or a single 8K ROM
+file, it defaults to the rom setting in the config file or roms/. Other
+games on the board use their own ROM names, see --machine
+
+options:
+  --config FILE        settings file, instead of space_invaders/config.ini
+                       in the user config directory
+  --preset NAME        DIP switch preset from the config file
+  --machine NAME       game on the board, invaders, invadpt2, invaddlx or
+                       lrescue
+  --ships N            DIP switch, ships per game, 3 to 6
+  --bonus-life N       DIP switch, bonus ship at 1000 or 1500 points
+  --coin-info on|off   DIP switch, coin info on the attract screen
+  --name-preset on|off DIP switch, Part II and Deluxe high score name entry
+  --cabinet NAME       upright or cocktail, cocktail turns the screen round
+                       for player two
+  --overlay NAME       none or classic
+  --scale N            screenshot scale, 1 to 8
+  --filter NAME        how screenshots and the terminal are scaled, nearest,
+                       scale2x, scale3x, epx or xbr
+  --terminal-scale N   draw the terminal screen bigger, 1 to 4
+  --volume N           0 to 100, 0 mutes
+  --strict on|off      stop on undocumented 8080 opcodes
+  --watchdog on|off    reset the game if it stops kicking the watchdog,
+                       resets are logged to stderr
+  --beam on|off        latch each line of the screen as the beam draws it
+  --crt FRONTEND.EFFECT=N
+                       CRT effect for terminal or screenshot, persistence,
+                       scanlines, glow or mask from 0 to 100
+  --speed X            speed multiplier, 1 is 60 frames a second
+  --video NAME         terminal or none
+  --audio NAME         none, bell or log
+  --record FILE        record the inputs to a movie
+  --playback FILE      play the inputs back from a movie
+  --frames N           stop after N frames
+  --screenshot FILE    save the last frame as a PNG
+  --gdb PORT           with debug, wait for gdb on localhost:PORT
+  --org ADDR           with disasm, load address in hex
+  --cpu NAME           with cpm, 8080, 8085 or z80
+  --limit N            with trace, stop after N instructions";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Headless,
+    Cpm,
+    Disasm,
+    Debug,
+    Trace,
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Args {
+    pub command: Command,
+    pub path: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    // Options that override the config file, by their config name
+    pub settings: Vec<(String, String)>,
+    pub speed: f64,
+    pub video: VideoBackend,
+    pub audio: AudioBackend,
+    pub record: Option<PathBuf>,
+    pub playback: Option<PathBuf>,
+    pub frames: Option<u64>,
+    pub screenshot: Option<PathBuf>,
+    pub gdb: Option<u16>,
+    pub org: u16,
+    pub cpu: Variant,
+    pub limit: Option<u64>,
+}
+
+impl Args {
+    fn new(command: Command) -> Self {
+        Args {
+            command,
+            path: None,
+            config: None,
+            settings: Vec::new(),
+            speed: 1.0,
+            video: VideoBackend::Terminal,
+            audio: AudioBackend::None,
+            record: None,
+            playback: None,
+            frames: None,
+            screenshot: None,
+            gdb: None,
+            org: 0,
+            cpu: Variant::I8080,
+            limit: None,
+        }
+    }
+
+    // Applies the setting options on top of settings from the config file,
+    // presets are left to the caller since they live in the config
+
+    pub fn apply(&self, settings: &mut Settings) -> Result<(), String> {
+        for (key, value) in self.settings.iter().filter(|(k, _)| k != "preset") {
+            settings.set(key, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn preset(&self) -> Option<&str> {
+        self.settings.iter().find(|(k, _)| k == "preset").map(|(_, v)| v.as_str())
+    }
+}
+
+fn number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} needs a number, got {}", option, value))
+}
+
+pub fn parse(args: &[String]) -> Result<Args, String> {
+    let command = match args.first().map(|s| s.as_str()) {
+        Some("run") => Command::Run,
+        Some("headless") => Command::Headless,
+        Some("cpm") => Command::Cpm,
+        Some("disasm") => Command::Disasm,
+        Some("debug") => Command::Debug,
+        Some("trace") => Command::Trace,
+        None | Some("help" | "-h" | "--help") => Command::Help,
+        Some(other) => return Err(format!("unknown command {}", other)),
+    };
+
+    let mut parsed = Args::new(command);
+    let mut rest = args.iter().skip(1);
+
+    while let Some(arg) = rest.next() {
+        if !arg.starts_with("--") {
+            if parsed.path.is_some() {
+                return Err(format!("unexpected argument {}", arg));
+            }
+            parsed.path = Some(PathBuf::from(arg));
+            continue;
+        }
+
+        let option = arg.as_str();
+        let value = rest.next().ok_or(format!("{} needs a value", option))?;
+
+        let setting = match option {
+            "--preset" | "--machine" | "--cabinet" | "--ships" | "--overlay" | "--scale" | "--volume" | "--strict"
+            | "--watchdog" | "--beam" | "--filter" => {
+                Some(&option[2..])
+            }
+            "--bonus-life" => Some("bonus_life"),
+            "--coin-info" => Some("coin_info"),
+            "--name-preset" => Some("name_preset"),
+            "--terminal-scale" => Some("terminal_scale"),
+            _ => None,
+        };
+        if let Some(key) = setting {
+            parsed.settings.push((key.to_string(), value.clone()));
+            continue;
+        }
+        if option == "--crt" {
+            let (effect, n) = value.split_once('=').ok_or(format!("--crt needs FRONTEND.EFFECT=N, got {}", value))?;
+            parsed.settings.push((format!("crt.{}", effect), n.to_string()));
+            continue;
+        }
+
+        match option {
+            "--config" => parsed.config = Some(PathBuf::from(value)),
+            "--speed" => {
+                parsed.speed = number(option, value)?;
+                if parsed.speed.is_nan() || parsed.speed <= 0.0 {
+                    return Err("--speed has to be more than 0".to_string());
+                }
+            }
+            "--video" => parsed.video = VideoBackend::parse(value)?,
+            "--audio" => parsed.audio = AudioBackend::parse(value)?,
+            "--record" => parsed.record = Some(PathBuf::from(value)),
+            "--playback" => parsed.playback = Some(PathBuf::from(value)),
+            "--frames" => parsed.frames = Some(number(option, value)?),
+            "--screenshot" => parsed.screenshot = Some(PathBuf::from(value)),
+            "--gdb" => parsed.gdb = Some(number(option, value)?),
+            "--org" => {
+                let digits = value.trim_start_matches("0x");
+                parsed.org = u16::from_str_radix(digits, 16).map_err(|_| format!("bad address {}", value))?;
+            }
+            "--cpu" => parsed.cpu = Variant::parse(value)?,
+            "--limit" => parsed.limit = Some(number(option, value)?),
+            _ => return Err(format!("unknown option {}", option)),
+        }
+    }
+
+    // Catch bad values now rather than after loading the config
+    parsed.apply(&mut Settings::default())?;
+
+    if matches!(command, Command::Cpm | Command::Disasm) && parsed.path.is_none() {
+        return Err("a file to load is needed".to_string());
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod cli_test {
+    use super::*;
+
+    fn args(line: &str) -> Result<Args, String> {
+        parse(&line.split_whitespace().map(String::from).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn headless_options() {
+        let a = args("headless roms --frames 120 --screenshot out.png --ships 5 --overlay none --preset hard").unwrap();
+        assert_eq!(a.command, Command::Headless);
+        assert_eq!(a.path, Some(PathBuf::from("roms")));
+        assert_eq!(a.frames, Some(120));
+        assert_eq!(a.screenshot, Some(PathBuf::from("out.png")));
+        assert_eq!(a.preset(), Some("hard"));
+
+        let mut settings = Settings::default();
+        a.apply(&mut settings).unwrap();
+        assert_eq!(settings.dips.ships, 5);
+        assert_eq!(settings.overlay, space_invaders::platform::video::Overlay::None);
+
+        let mut settings = Settings::default();
+        args("run --crt screenshot.scanlines=40").unwrap().apply(&mut settings).unwrap();
+        assert_eq!(settings.screenshot_crt.scanlines, 40);
+    }
+
+    #[test]
+    fn defaults_and_errors() {
+        assert_eq!(args("").unwrap().command, Command::Help);
+        assert_eq!(args("debug").unwrap().path, None);
+        assert_eq!(args("disasm a.bin --org 100").unwrap().org, 0x100);
+        assert_eq!(args("cpm zexdoc.com --cpu Z80").unwrap().cpu, Variant::Z80);
+        assert!(args("run --scale 0").is_err());
+
+        assert!(args("cpm").is_err());
+        assert!(args("run --ships 9").is_err());
+        assert!(args("run --speed 0").is_err());
+        assert!(args("run --video sdl").is_err());
+        assert!(args("cpm a.com --cpu 6502").is_err());
+        assert!(args("run --machine pong").is_err());
+        assert!(args("run --cabinet table").is_err());
+        assert!(args("run --watchdog maybe").is_err());
+        assert!(args("run --crt terminal.glow").is_err());
+        assert!(args("run --crt gui.glow=10").is_err());
+        assert!(args("run --filter hq2x").is_err());
+        assert!(args("run --terminal-scale 5").is_err());
+        assert!(args("run --frames").is_err());
+        assert!(args("fly").is_err());
+    }
+}
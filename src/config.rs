@@ -0,0 +1,419 @@
+// Settings file, INI style so it needs no parser dependency
+//
+//     [general]
+//     rom = roms
+//     overlay = classic
+//     scale = 2
+//     filter = scale2x
+//     volume = 80
+//     preset = easy
+//
+//     [keys]
+//     p1_fire = space
+//     quit = esc
+//
+//     [preset.easy]
+//     ships = 6
+//     bonus_life = 1000
+//
+//     [dips]
+//     cabinet = cocktail
+//
+//     [crt.screenshot]
+//     scanlines = 40
+//     glow = 20
+//
+//     [rom.invaders]
+//     overlay = none
+//
+// Settings are applied in the order general, keys, dips, the CRT effects for
+// each frontend, then the overrides for the ROM being run. A preset key pulls
+// in that preset's settings at the point it appears, a preset that ends up
+// including itself is an error.
+//
+// A # or ; starts a comment at the start of a line or after whitespace, so
+// p1_fire = ";" or p1_fire=; binds the key rather than commenting it out.
+
+use crate::frontend::crt::CrtSettings;
+use crate::frontend::scale::Filter;
+use crate::frontend::{parse_key, KeyMap};
+use crate::platform::io::{Button, Dips};
+use crate::platform::machine::{self, MachineDescription, INVADERS};
+use crate::platform::video::Overlay;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_ROM_DIR: &str = "roms";
+
+// Frontends that take CRT effects, as named in crt.<frontend> sections
+pub const CRT_FRONTENDS: [&str; 2] = ["terminal", "screenshot"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub rom: String,
+    // Which game on the board the ROM is
+    pub machine: &'static MachineDescription,
+    pub dips: Dips,
+    pub overlay: Overlay,
+    // Size multiplier for screenshots and any scaled output
+    pub scale: u32,
+    // How screenshots and the terminal are scaled up
+    pub filter: Filter,
+    // Size multiplier for the terminal
+    pub terminal_scale: u32,
+    // 0 to 100, 0 mutes
+    pub volume: u8,
+    pub keys: KeyMap,
+    // Stop on undocumented opcodes instead of running them
+    pub strict: bool,
+    // Let the watchdog reset a game that stops kicking it
+    pub watchdog: bool,
+    // Show each line as it was when the beam drew it
+    pub beam: bool,
+    // CRT effects for the terminal and for screenshots
+    pub terminal_crt: CrtSettings,
+    pub screenshot_crt: CrtSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            rom: DEFAULT_ROM_DIR.to_string(),
+            machine: &INVADERS,
+            dips: Dips::default(),
+            overlay: Overlay::Classic,
+            scale: 1,
+            filter: Filter::Nearest,
+            terminal_scale: 1,
+            volume: 100,
+            keys: KeyMap::default(),
+            strict: false,
+            watchdog: true,
+            beam: false,
+            terminal_crt: CrtSettings::default(),
+            screenshot_crt: CrtSettings::default(),
+        }
+    }
+}
+
+fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} needs a number, got {}", key, value))
+}
+
+fn switch(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("{} is on or off, not {}", key, value)),
+    }
+}
+
+impl Settings {
+    // Sets one setting by its config file name
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "rom" => self.rom = value.to_string(),
+            // Brings the machine's overlay along, an overlay setting after it wins
+            "machine" => {
+                self.machine = machine::find(value)?;
+                self.overlay = self.machine.overlay;
+            }
+            "overlay" => self.overlay = Overlay::parse(value)?,
+            "scale" => {
+                self.scale = number(key, value)?;
+                if !(1..=8).contains(&self.scale) {
+                    return Err(format!("scale must be 1 to 8, not {}", self.scale));
+                }
+            }
+            "filter" => self.filter = Filter::parse(value)?,
+            "terminal_scale" => {
+                self.terminal_scale = number(key, value)?;
+                if !(1..=4).contains(&self.terminal_scale) {
+                    return Err(format!("terminal scale must be 1 to 4, not {}", self.terminal_scale));
+                }
+            }
+            "volume" => {
+                self.volume = number(key, value)?;
+                if self.volume > 100 {
+                    return Err(format!("volume must be 0 to 100, not {}", self.volume));
+                }
+            }
+            "strict" => self.strict = switch(key, value)?,
+            "watchdog" => self.watchdog = switch(key, value)?,
+            "beam" => self.beam = switch(key, value)?,
+            "ships" => self.dips.ships = number(key, value)?,
+            "bonus_life" => self.dips.bonus_life = number(key, value)?,
+            "coin_info" => self.dips.coin_info = switch(key, value)?,
+            "name_preset" => self.dips.name_preset = switch(key, value)?,
+            // crt.<frontend>.<effect>
+            _ if key.starts_with("crt.") => {
+                let (frontend, effect) = key[4..].split_once('.').ok_or(format!("unknown setting {}", key))?;
+                let crt = match frontend {
+                    "terminal" => &mut self.terminal_crt,
+                    "screenshot" => &mut self.screenshot_crt,
+                    _ => return Err(format!("no crt effects for {}, expected terminal or screenshot", frontend)),
+                };
+                crt.set(effect, value)?;
+            }
+            "cabinet" => {
+                self.dips.cocktail = match value {
+                    "upright" => false,
+                    "cocktail" => true,
+                    _ => return Err(format!("cabinet is upright or cocktail, not {}", value)),
+                }
+            }
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        self.dips.validate()
+    }
+
+    // Binds a key, the setting is the button name or quit
+
+    pub fn bind(&mut self, button: &str, key: &str) -> Result<(), String> {
+        let key = parse_key(key).ok_or(format!("unknown key {}", key))?;
+        if button == "quit" {
+            self.keys.quit = key;
+            return Ok(());
+        }
+        let button = Button::from_name(button).ok_or(format!("unknown button {}", button))?;
+        self.keys.bind(key, button);
+        Ok(())
+    }
+}
+
+// The line up to a # or ; that starts a comment
+
+fn strip_comment(line: &str) -> &str {
+    let mut prev = ' ';
+    for (i, ch) in line.char_indices() {
+        if (ch == '#' || ch == ';') && prev.is_whitespace() {
+            return &line[..i];
+        }
+        prev = ch;
+    }
+    line
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+    // Section name to its settings in file order
+    sections: HashMap<String, Vec<(String, String)>>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section = "general".to_string();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or(format!("line {}: expected key = value", n + 1))?;
+            let value = value.trim().trim_matches('"');
+            config
+                .sections
+                .entry(section.clone())
+                .or_default()
+                .push((key.trim().to_string(), value.to_string()));
+        }
+
+        Ok(config)
+    }
+
+    // Reads the file at path, or the default config file if there is one
+
+    pub fn load(path: Option<&Path>) -> io::Result<Config> {
+        let text = match path {
+            Some(path) => fs::read_to_string(path)?,
+            None => match default_path().map(fs::read_to_string) {
+                Some(Ok(text)) => text,
+                _ => return Ok(Config::default()),
+            },
+        };
+        Config::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn apply(&self, section: &str, settings: &mut Settings) -> Result<(), String> {
+        self.apply_within(section, settings, &mut Vec::new())
+    }
+
+    // presets holds the presets being applied around this section
+
+    fn apply_within(&self, section: &str, settings: &mut Settings, presets: &mut Vec<String>) -> Result<(), String> {
+        for (key, value) in self.sections.get(section).into_iter().flatten() {
+            let result = match key.as_str() {
+                "preset" => self.include(value, settings, presets),
+                _ => settings.set(key, value),
+            };
+            result.map_err(|e| format!("[{}] {}", section, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn preset(&self, name: &str, settings: &mut Settings) -> Result<(), String> {
+        self.include(name, settings, &mut Vec::new())
+    }
+
+    fn include(&self, name: &str, settings: &mut Settings, presets: &mut Vec<String>) -> Result<(), String> {
+        let section = format!("preset.{}", name);
+        if !self.sections.contains_key(&section) {
+            return Err(format!("no preset {}", name));
+        }
+        if presets.iter().any(|p| p == name) {
+            return Err(format!("preset {} includes itself", name));
+        }
+
+        presets.push(name.to_string());
+        let result = self.apply_within(&section, settings, presets);
+        presets.pop();
+        result
+    }
+
+    // The default ROM path, which decides which per ROM overrides apply
+
+    pub fn rom(&self) -> Result<String, String> {
+        let mut settings = Settings::default();
+        self.apply("general", &mut settings)?;
+        Ok(settings.rom)
+    }
+
+    // Settings for running the ROM at rom, overrides are looked up by the
+    // last part of its path
+
+    pub fn settings(&self, rom: &str) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        self.apply("general", &mut settings)?;
+
+        for (button, key) in self.sections.get("keys").into_iter().flatten() {
+            settings.bind(button, key).map_err(|e| format!("[keys] {}", e))?;
+        }
+        self.apply("dips", &mut settings)?;
+
+        for frontend in CRT_FRONTENDS {
+            let section = format!("crt.{}", frontend);
+            for (key, value) in self.sections.get(&section).into_iter().flatten() {
+                let key = format!("{}.{}", section, key);
+                settings.set(&key, value).map_err(|e| format!("[{}] {}", section, e))?;
+            }
+        }
+
+        let name = Path::new(rom).file_name().map(|n| n.to_string_lossy().to_string());
+        if let Some(name) = name {
+            self.apply(&format!("rom.{}", name), &mut settings)?;
+        }
+
+        settings.rom = rom.to_string();
+        Ok(settings)
+    }
+}
+
+// config.ini in the platform's config directory
+
+pub fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("space_invaders").join("config.ini"))
+}
+
+#[cfg(test)]
+mod config_test {
+    use super::*;
+
+    const EXAMPLE: &str = "
+        # shared settings
+        [general]
+        rom = /games/invaders
+        scale = 2
+        filter = xbr
+        preset = easy
+
+        [keys]
+        p1_fire = x   ; not space
+        quit = esc
+
+        [preset.easy]
+        ships = 6
+        bonus_life = 1000
+
+        [crt.terminal]
+        persistence = 60
+
+        [rom.deluxe]
+        overlay = none
+        volume = 0
+        coin_info = off
+
+        [rom.lrescue]
+        machine = lrescue
+        cabinet = cocktail
+    ";
+
+    #[test]
+    fn layers_settings() {
+        let config = Config::parse(EXAMPLE).unwrap();
+        assert_eq!(config.rom().unwrap(), "/games/invaders");
+
+        let s = config.settings("/games/invaders").unwrap();
+        assert_eq!(s.scale, 2);
+        assert_eq!(s.filter, Filter::Xbr);
+        assert_eq!(s.dips, Dips { ships: 6, bonus_life: 1000, ..Default::default() });
+        assert_eq!(s.overlay, Overlay::Classic);
+        assert_eq!(s.keys.button(b'x'), Some(Button::P1Fire));
+        assert_eq!(s.keys.quit, 0x1B);
+        assert_eq!(s.terminal_crt.persistence, 60);
+        assert!(s.screenshot_crt.is_off());
+
+        let s = config.settings("roms/deluxe").unwrap();
+        assert_eq!(s.rom, "roms/deluxe");
+        assert_eq!(s.overlay, Overlay::None);
+        assert_eq!(s.volume, 0);
+        assert!(!s.dips.coin_info);
+
+        let s = config.settings("roms/lrescue").unwrap();
+        assert_eq!(s.machine.name, "lrescue");
+        assert_eq!(s.overlay, Overlay::None);
+        assert!(s.dips.cocktail);
+    }
+
+    #[test]
+    fn comments() {
+        let config = Config::parse("# rom = a\n  ; rom = b\nrom = /games/inv#1 ; old set\n[keys]\np1_fire = \";\"\np1_left=#\n").unwrap();
+        assert_eq!(config.rom().unwrap(), "/games/inv#1");
+
+        let s = config.settings("roms").unwrap();
+        assert_eq!(s.keys.button(b';'), Some(Button::P1Fire));
+        assert_eq!(s.keys.button(b'#'), Some(Button::P1Left));
+        assert_eq!(strip_comment("ships = 5\t# more"), "ships = 5\t");
+    }
+
+    #[test]
+    fn reports_bad_settings() {
+        let bad = |text: &str| Config::parse(text).and_then(|c| c.settings("roms").map(|_| ()));
+        assert_eq!(bad("ships = 9"), Err("[general] ships must be 3 to 6, not 9".to_string()));
+        assert_eq!(bad("[keys]\nfire = x"), Err("[keys] unknown button fire".to_string()));
+        assert_eq!(bad("cabinet = table"), Err("[general] cabinet is upright or cocktail, not table".to_string()));
+        assert_eq!(bad("[crt.terminal]\nglow = 200"), Err("[crt.terminal] glow must be 0 to 100, not 200".to_string()));
+        assert_eq!(bad("preset = nope"), Err("[general] no preset nope".to_string()));
+        assert_eq!(
+            bad("preset = a\n[preset.a]\npreset = b\n[preset.b]\npreset = a"),
+            Err("[general] [preset.a] [preset.b] preset a includes itself".to_string())
+        );
+        assert_eq!(bad("preset = a\npreset = a\n[preset.a]\nships = 5").map(|_| ()), Ok(()));
+        assert_eq!(bad("machine = pong"), Err("[general] unknown machine pong, expected invaders, invadpt2, invaddlx or lrescue".to_string()));
+        assert_eq!(bad("[general]\njunk"), Err("line 2: expected key = value".to_string()));
+    }
+}
@@ -0,0 +1,826 @@
+// CP/M 2.2 environment for running .COM programs
+//
+// The BDOS and BIOS entry points are trapped by address, so a program can reach
+// them through CALL 5, JMP 5, a RST or a PCHL just like on a real system.
+// Files live in the host directory Cpm::dir.
+
+use super::Cpu;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+// Memory layout, programs read the BDOS address at 0x0006 as the top of the TPA
+pub const TPA: u16 = 0x0100;
+pub const BDOS_ENTRY: u16 = 0xF800;
+pub const BIOS: u16 = 0xF900;
+const BIOS_ENTRIES: u16 = 17;
+
+const DEFAULT_FCB: u16 = 0x005C;
+const DEFAULT_DMA: u16 = 0x0080;
+const RECORD: usize = 128;
+
+// Index of the byte offset past addr, wrapping at the top of memory like the
+// cpu does
+
+fn at(addr: u16, offset: usize) -> usize {
+    addr.wrapping_add(offset as u16) as usize
+}
+
+#[derive(Default)]
+pub struct Cpm {
+    pub active: bool,
+
+    // Host directory used as drive A:
+    pub dir: PathBuf,
+
+    // Console input, stdin is used once this runs dry
+    pub input: VecDeque<u8>,
+
+    dma: u16,
+    disk: u8,
+    user: u8,
+    files: HashMap<String, File>,
+    search: VecDeque<[u8; 11]>,
+}
+
+// Host handles aren't copied, the clone opens files again as it uses them
+
+impl Clone for Cpm {
+    fn clone(&self) -> Self {
+        Cpm {
+            active: self.active,
+            dir: self.dir.clone(),
+            input: self.input.clone(),
+            dma: self.dma,
+            disk: self.disk,
+            user: self.user,
+            files: HashMap::new(),
+            search: self.search.clone(),
+        }
+    }
+}
+
+impl Cpu {
+    // Boots a .COM program, it starts at 0x0100 with the zero page set up
+
+    pub fn init_cpm(program: &[u8]) -> Cpu {
+        let mut c = Cpu::init(TPA, program);
+
+        // JMP WBOOT and JMP BDOS
+        let wboot = BIOS + 3;
+        c.memory[0..3].copy_from_slice(&[0xC3, (wboot & 0xff) as u8, (wboot >> 8) as u8]);
+        c.memory[5..8].copy_from_slice(&[0xC3, (BDOS_ENTRY & 0xff) as u8, (BDOS_ENTRY >> 8) as u8]);
+
+        // Every BIOS vector jumps to itself, it's trapped before it runs
+        for n in 0..BIOS_ENTRIES {
+            let addr = BIOS + n * 3;
+            c.memory[addr as usize..addr as usize + 3].copy_from_slice(&[0xC3, (addr & 0xff) as u8, (addr >> 8) as u8]);
+        }
+        c.memory[BDOS_ENTRY as usize] = 0xC9;
+
+        c.memory[DEFAULT_FCB as usize + 1..DEFAULT_FCB as usize + 12].copy_from_slice(b"           ");
+
+        // The zeroed word on top of the stack returns to the warm boot at 0x0000
+        c.regs.sp = BDOS_ENTRY - 2;
+        c.regs.pc = TPA;
+
+        c.cpm.active = true;
+        c.cpm.dma = DEFAULT_DMA;
+        c
+    }
+
+    // Runs the BDOS or BIOS if the cpu is about to execute one of their entry
+    // points, returns true if it did
+
+    pub(super) fn cpm_trap(&mut self) -> bool {
+        let pc = self.regs.pc;
+
+        if pc == BDOS_ENTRY {
+            self.bdos();
+        } else if (BIOS..BIOS + BIOS_ENTRIES * 3).contains(&pc) && (pc - BIOS).is_multiple_of(3) {
+            self.bios((pc - BIOS) / 3);
+        } else {
+            return false;
+        }
+
+        if !self.hlted {
+            self.ret();
+        }
+        true
+    }
+
+    fn bios(&mut self, entry: u16) {
+        match entry {
+            // BOOT and WBOOT end the program
+            0 | 1 => self.hlted = true,
+            // CONST
+            2 => self.regs.a = if self.cpm.input.is_empty() { 0 } else { 0xFF },
+            // CONIN
+            3 => self.regs.a = self.console_in().unwrap_or(0x1A),
+            // CONOUT
+            4 => self.console_out(self.regs.c),
+            // READER
+            7 => self.regs.a = 0x1A,
+            _ => self.regs.a = 0,
+        }
+    }
+
+    fn bdos(&mut self) {
+        let de = self.cmb_be(self.regs.d, self.regs.e);
+
+        let ret: u16 = match self.regs.c {
+            // System reset
+            0 => {
+                self.hlted = true;
+                0
+            }
+
+            // Console input with echo
+            1 => {
+                let ch = self.console_in().unwrap_or(0x1A);
+                self.console_out(ch);
+                ch as u16
+            }
+
+            // Console output
+            2 => {
+                self.console_out(self.regs.e);
+                0
+            }
+
+            // Reader input
+            3 => 0x1A,
+
+            // Punch and list output go nowhere
+            4 | 5 => 0,
+
+            // Direct console IO
+            6 => match self.regs.e {
+                0xFF => match self.cpm.input.pop_front() {
+                    Some(ch) => ch as u16,
+                    None => 0,
+                },
+                0xFE => self.console_status(),
+                ch => {
+                    self.console_out(ch);
+                    0
+                }
+            },
+
+            // Get and set IOBYTE
+            7 | 8 => 0,
+
+            // Print string terminated with $, at most once round memory if
+            // there's no $ anywhere
+            9 => {
+                for n in 0..0x10000 {
+                    let ch = self.memory[at(de, n)];
+                    if ch == b'$' {
+                        break;
+                    }
+                    self.console_out(ch);
+                }
+                0
+            }
+
+            // Read console buffer
+            10 => {
+                self.read_console_buffer(de);
+                0
+            }
+
+            // Console status
+            11 => self.console_status(),
+
+            // Version number, CP/M 2.2
+            12 => 0x0022,
+
+            // Reset disk system
+            13 => {
+                self.cpm.dma = DEFAULT_DMA;
+                self.cpm.disk = 0;
+                0
+            }
+
+            // Select disk
+            14 => {
+                self.cpm.disk = self.regs.e;
+                0
+            }
+
+            15 => self.open_file(de),
+            16 => self.close_file(de),
+            17 => self.search_first(de),
+            18 => self.search_next(),
+            19 => self.delete_file(de),
+            20 => self.read_record(de, false),
+            21 => self.write_record(de, false),
+            22 => self.make_file(de),
+            23 => self.rename_file(de),
+
+            // Login vector, only A: exists
+            24 => 0x0001,
+
+            // Current disk
+            25 => self.cpm.disk as u16,
+
+            // Set DMA address
+            26 => {
+                self.cpm.dma = de;
+                0
+            }
+
+            // Get and set user code
+            32 => {
+                if self.regs.e == 0xFF {
+                    self.cpm.user as u16
+                } else {
+                    self.cpm.user = self.regs.e & 0x0F;
+                    0
+                }
+            }
+
+            33 => self.read_record(de, true),
+            34 | 40 => self.write_record(de, true),
+            35 => self.file_size(de),
+
+            // Set random record
+            36 => {
+                let record = self.seq_record(de);
+                self.set_random_record(de, record);
+                0
+            }
+
+            _ => 0xFF,
+        };
+
+        // Results come back in HL, A and B mirror L and H
+        self.regs.l = (ret & 0xff) as u8;
+        self.regs.h = (ret >> 8) as u8;
+        self.regs.a = self.regs.l;
+        self.regs.b = self.regs.h;
+    }
+
+    /*
+    Console
+    */
+
+    fn console_out(&mut self, ch: u8) {
+        print!("{}", ch as char);
+        let _ = io::stdout().flush();
+        self.output.push(ch as char);
+    }
+
+    fn console_in(&mut self) -> Option<u8> {
+        if let Some(ch) = self.cpm.input.pop_front() {
+            return Some(ch);
+        }
+
+        let mut byte = [0];
+        match io::stdin().read(&mut byte) {
+            Ok(1) if byte[0] == b'\n' => Some(b'\r'),
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn console_status(&self) -> u16 {
+        if self.cpm.input.is_empty() {
+            0
+        } else {
+            0xFF
+        }
+    }
+
+    fn read_console_buffer(&mut self, buf: u16) {
+        let max = self.memory[buf as usize] as usize;
+        let mut len = 0;
+
+        while len < max {
+            match self.console_in() {
+                Some(b'\r') | Some(b'\n') | None => break,
+                Some(ch) => {
+                    self.console_out(ch);
+                    self.memory[at(buf, 2 + len)] = ch;
+                    len += 1;
+                }
+            }
+        }
+
+        self.memory[at(buf, 1)] = len as u8;
+        self.console_out(b'\r');
+        self.console_out(b'\n');
+    }
+
+    /*
+    Files
+    */
+
+    // The 8.3 name in a FCB, with the attribute bits stripped
+
+    fn fcb_name(&self, fcb: u16) -> [u8; 11] {
+        let mut name = [0; 11];
+        for (i, byte) in name.iter_mut().enumerate() {
+            *byte = (self.memory[at(fcb, 1 + i)] & 0x7F).to_ascii_uppercase();
+        }
+        name
+    }
+
+    fn host_name(name: &[u8; 11]) -> String {
+        let base = String::from_utf8_lossy(&name[..8]).trim_end().to_lowercase();
+        let ext = String::from_utf8_lossy(&name[8..]).trim_end().to_lowercase();
+        if ext.is_empty() {
+            base
+        } else {
+            format!("{}.{}", base, ext)
+        }
+    }
+
+    fn fcb_path(&self, fcb: u16) -> (String, PathBuf) {
+        let name = Cpu::host_name(&self.fcb_name(fcb));
+        let path = self.cpm.dir.join(&name);
+        (name, path)
+    }
+
+    fn seq_record(&self, fcb: u16) -> u32 {
+        let ex = (self.memory[at(fcb, 12)] & 0x1F) as u32;
+        let s2 = (self.memory[at(fcb, 14)] & 0x3F) as u32;
+        let cr = (self.memory[at(fcb, 32)] & 0x7F) as u32;
+        (s2 * 32 + ex) * 128 + cr
+    }
+
+    fn set_seq_record(&mut self, fcb: u16, record: u32) {
+        self.memory[at(fcb, 32)] = (record % 128) as u8;
+        self.memory[at(fcb, 12)] = ((record / 128) % 32) as u8;
+        self.memory[at(fcb, 14)] = (record / 4096) as u8;
+    }
+
+    fn random_record(&self, fcb: u16) -> u32 {
+        (0..3).fold(0, |record, n| record | (self.memory[at(fcb, 33 + n)] as u32) << (8 * n))
+    }
+
+    fn set_random_record(&mut self, fcb: u16, record: u32) {
+        for n in 0..3 {
+            self.memory[at(fcb, 33 + n)] = (record >> (8 * n)) as u8;
+        }
+    }
+
+    // Fills in RC, the number of records in the current extent
+
+    fn set_record_count(&mut self, fcb: u16, len: u64) {
+        let records = len.div_ceil(RECORD as u64) as u32;
+        let extent_start = self.seq_record(fcb) / 128 * 128;
+        let rc = records.saturating_sub(extent_start).min(128);
+        self.memory[at(fcb, 15)] = rc as u8;
+    }
+
+    fn open_file(&mut self, fcb: u16) -> u16 {
+        let (name, path) = self.fcb_path(fcb);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .or_else(|_| File::open(&path));
+
+        match file {
+            Ok(file) => {
+                let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+                self.cpm.files.insert(name, file);
+                self.memory[at(fcb, 32)] = 0;
+                self.set_record_count(fcb, len);
+                0
+            }
+            Err(_) => 0xFF,
+        }
+    }
+
+    fn close_file(&mut self, fcb: u16) -> u16 {
+        let (name, path) = self.fcb_path(fcb);
+        match self.cpm.files.remove(&name) {
+            Some(mut file) => {
+                let _ = file.flush();
+                0
+            }
+            None if path.exists() => 0,
+            None => 0xFF,
+        }
+    }
+
+    fn make_file(&mut self, fcb: u16) -> u16 {
+        let (name, path) = self.fcb_path(fcb);
+        match OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path) {
+            Ok(file) => {
+                self.cpm.files.insert(name, file);
+                self.set_seq_record(fcb, 0);
+                self.memory[at(fcb, 15)] = 0;
+                0
+            }
+            Err(_) => 0xFF,
+        }
+    }
+
+    fn delete_file(&mut self, fcb: u16) -> u16 {
+        let pattern = self.fcb_name(fcb);
+        let mut deleted = false;
+
+        for name in self.directory(&pattern) {
+            let host = Cpu::host_name(&name);
+            self.cpm.files.remove(&host);
+            deleted |= fs::remove_file(self.cpm.dir.join(host)).is_ok();
+        }
+
+        if deleted {
+            0
+        } else {
+            0xFF
+        }
+    }
+
+    fn rename_file(&mut self, fcb: u16) -> u16 {
+        let (old_name, old) = self.fcb_path(fcb);
+        let (_, new) = self.fcb_path(fcb.wrapping_add(16));
+        self.cpm.files.remove(&old_name);
+
+        match fs::rename(old, new) {
+            Ok(()) => 0,
+            Err(_) => 0xFF,
+        }
+    }
+
+    // Looks up an open file, files a program forgot to open are opened on demand
+
+    fn file(&mut self, fcb: u16) -> Option<&mut File> {
+        let (name, path) = self.fcb_path(fcb);
+        if !self.cpm.files.contains_key(&name) {
+            let file = OpenOptions::new().read(true).write(true).open(path).ok()?;
+            self.cpm.files.insert(name.clone(), file);
+        }
+        self.cpm.files.get_mut(&name)
+    }
+
+    fn read_record(&mut self, fcb: u16, random: bool) -> u16 {
+        let record = if random { self.random_record(fcb) } else { self.seq_record(fcb) };
+        let mut buf = [0x1A; RECORD];
+
+        let read = match self.file(fcb) {
+            Some(file) => {
+                let mut total = 0;
+                if file.seek(SeekFrom::Start(record as u64 * RECORD as u64)).is_ok() {
+                    while total < RECORD {
+                        match file.read(&mut buf[total..]) {
+                            Ok(0) | Err(_) => break,
+                            Ok(n) => total += n,
+                        }
+                    }
+                }
+                total
+            }
+            None => return 0xFF,
+        };
+
+        if read == 0 {
+            // End of file, or reading unwritten data for random access
+            return 1;
+        }
+
+        for (n, byte) in buf.iter().enumerate() {
+            self.memory[at(self.cpm.dma, n)] = *byte;
+        }
+
+        // Random reads leave the sequential position on the record read
+        if random {
+            self.set_seq_record(fcb, record);
+        } else {
+            self.set_seq_record(fcb, record + 1);
+        }
+        0
+    }
+
+    fn write_record(&mut self, fcb: u16, random: bool) -> u16 {
+        let record = if random { self.random_record(fcb) } else { self.seq_record(fcb) };
+        let buf: Vec<u8> = (0..RECORD).map(|n| self.memory[at(self.cpm.dma, n)]).collect();
+
+        let written = match self.file(fcb) {
+            Some(file) => file
+                .seek(SeekFrom::Start(record as u64 * RECORD as u64))
+                .and_then(|_| file.write_all(&buf))
+                .and_then(|_| file.metadata())
+                .map(|m| m.len()),
+            None => return 0xFF,
+        };
+
+        match written {
+            Ok(len) => {
+                if random {
+                    self.set_seq_record(fcb, record);
+                } else {
+                    self.set_seq_record(fcb, record + 1);
+                }
+                self.set_record_count(fcb, len);
+                0
+            }
+            // Disk full
+            Err(_) => 2,
+        }
+    }
+
+    fn file_size(&mut self, fcb: u16) -> u16 {
+        let (_, path) = self.fcb_path(fcb);
+        match fs::metadata(path) {
+            Ok(meta) => {
+                let records = meta.len().div_ceil(RECORD as u64) as u32;
+                self.set_random_record(fcb, records);
+                0
+            }
+            Err(_) => 0xFF,
+        }
+    }
+
+    // Host files that fit in 8.3 and match a pattern with ? wildcards
+
+    fn directory(&self, pattern: &[u8; 11]) -> Vec<[u8; 11]> {
+        let dir = if self.cpm.dir.as_os_str().is_empty() { PathBuf::from(".") } else { self.cpm.dir.clone() };
+        let mut names = Vec::new();
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return names,
+        };
+
+        for entry in entries.flatten() {
+            if !entry.path().is_file() {
+                continue;
+            }
+            let host = entry.file_name().to_string_lossy().to_uppercase();
+            let (base, ext) = host.split_once('.').unwrap_or((&host, ""));
+            if base.is_empty() || base.len() > 8 || ext.len() > 3 || ext.contains('.') {
+                continue;
+            }
+
+            let mut name = [b' '; 11];
+            name[..base.len()].copy_from_slice(base.as_bytes());
+            name[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+
+            if pattern.iter().zip(name.iter()).all(|(p, n)| *p == b'?' || p == n) {
+                names.push(name);
+            }
+        }
+
+        names.sort();
+        names
+    }
+
+    fn search_first(&mut self, fcb: u16) -> u16 {
+        let pattern = self.fcb_name(fcb);
+        self.cpm.search = self.directory(&pattern).into();
+        self.search_next()
+    }
+
+    // Writes the next match as a directory entry at the DMA address
+
+    fn search_next(&mut self) -> u16 {
+        match self.cpm.search.pop_front() {
+            Some(name) => {
+                for n in 0..32 {
+                    self.memory[at(self.cpm.dma, n)] = if (1..12).contains(&n) { name[n - 1] } else { 0 };
+                }
+                0
+            }
+            None => 0xFF,
+        }
+    }
+}
+
+#[cfg(test)]
+mod cpm_test {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn run(source: &str) -> Cpu {
+        let prog = assemble(source).unwrap();
+        assert_eq!(prog.origin, TPA);
+
+        let mut cpu = Cpu::init_cpm(&prog.bytes);
+        for _ in 0..100_000 {
+            if cpu.hlted {
+                break;
+            }
+            cpu.cycle().unwrap();
+        }
+        assert!(cpu.hlted, "program didn't warm boot");
+        cpu
+    }
+
+    #[test]
+    fn print_string_and_char() {
+        let cpu = run("
+            ORG 100H
+            MVI C, 9
+            LXI D, MSG
+            CALL 5
+            MVI C, 2
+            MVI E, '!'
+            CALL 5
+            RET
+        MSG: DB 'HELLO$'
+        ");
+        assert_eq!(cpu.output, "HELLO!");
+    }
+
+    #[test]
+    fn bdos_through_jump_and_bios() {
+        // Push our own return address and jump into the BDOS, then print
+        // through the BIOS CONOUT vector found from the warm boot jump
+        let cpu = run("
+            ORG 100H
+            LXI H, BACK
+            PUSH H
+            MVI C, 2
+            MVI E, 'A'
+            JMP 5
+        BACK:
+            LHLD 1
+            LXI D, 9
+            DAD D
+            MVI C, 'B'
+            LXI D, DONE
+            PUSH D
+            PCHL
+        DONE:
+            JMP 0
+        ");
+        assert_eq!(cpu.output, "AB");
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let dir = std::env::temp_dir().join(format!("cpm_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let prog = assemble("
+            ORG 100H
+            MVI C, 22
+            LXI D, FCB
+            CALL 5
+            MVI C, 26
+            LXI D, DATA
+            CALL 5
+            MVI C, 21
+            LXI D, FCB
+            CALL 5
+            MVI C, 16
+            LXI D, FCB
+            CALL 5
+            MVI C, 15
+            LXI D, FCB
+            CALL 5
+            MVI C, 26
+            LXI D, BUF
+            CALL 5
+            MVI C, 20
+            LXI D, FCB
+            CALL 5
+            STA RESULT
+            RET
+        FCB: DB 0, 'TEST    TXT', 0, 0, 0, 0
+             DS 20
+        RESULT: DB 0FFH
+        DATA: DB 'RECORD ONE'
+             DS 118
+        BUF: DS 128
+        ").unwrap();
+
+        let mut cpu = Cpu::init_cpm(&prog.bytes);
+        cpu.cpm.dir = dir.clone();
+        while !cpu.hlted {
+            cpu.cycle().unwrap();
+        }
+
+        let buf = prog.symbols["BUF"] as usize;
+        assert_eq!(cpu.memory[prog.symbols["RESULT"] as usize], 0);
+        assert_eq!(&cpu.memory[buf..buf + 10], b"RECORD ONE");
+        assert_eq!(fs::metadata(dir.join("test.txt")).unwrap().len(), 128);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Runs a program that ends in HLT, with setup poking memory first
+    fn run_with(source: &str, dir: &std::path::Path, setup: impl FnOnce(&mut Cpu)) -> Cpu {
+        let prog = assemble(source).unwrap();
+        let mut cpu = Cpu::init_cpm(&prog.bytes);
+        cpu.cpm.dir = dir.to_path_buf();
+        setup(&mut cpu);
+        for _ in 0..100_000 {
+            if cpu.hlted {
+                break;
+            }
+            cpu.cycle().unwrap();
+        }
+        assert!(cpu.hlted, "program didn't halt");
+        cpu
+    }
+
+    #[test]
+    fn console_buffers_wrap() {
+        // The string runs from 0xFFFE to the $ at 0x0000, the line buffer
+        // from 0xFFFD
+        let cpu = run_with("
+            ORG 100H
+            MVI C, 9
+            LXI D, 0FFFEH
+            CALL 5
+            MVI C, 10
+            LXI D, 0FFFDH
+            CALL 5
+            HLT
+        ", std::path::Path::new("."), |cpu| {
+            cpu.memory[0xFFFE..].copy_from_slice(b"AB");
+            cpu.memory[0x0000] = b'$';
+            cpu.memory[0xFFFD] = 5;
+            cpu.cpm.input.extend(b"HI\r");
+        });
+
+        assert_eq!(cpu.output, "ABHI\r\n");
+        assert_eq!((cpu.memory[0xFFFE], cpu.memory[0xFFFF], cpu.memory[0x0000]), (2, b'H', b'I'));
+    }
+
+    #[test]
+    fn print_string_without_terminator() {
+        // No $ anywhere, printing stops after going once round memory
+        let mut cpu = Cpu::init(0, &[]);
+        cpu.memory.fill(b'x');
+        cpu.regs.c = 9;
+        cpu.regs.d = 0x80;
+        cpu.bdos();
+        assert_eq!(cpu.output.len(), 0x10000);
+    }
+
+    // The zero page is overwritten as records wrap into it, so the BDOS is
+    // called at its entry point rather than through 0x0005
+    #[test]
+    fn records_wrap() {
+        let dir = std::env::temp_dir().join(format!("cpm_wrap_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let cpu = run_with("
+            ORG 100H
+            MVI C, 26
+            LXI D, 0FFC0H
+            CALL 0F800H
+            MVI C, 22
+            LXI D, FCB
+            CALL 0F800H
+            MVI C, 21
+            LXI D, FCB
+            CALL 0F800H
+            MVI C, 16
+            LXI D, FCB
+            CALL 0F800H
+            MVI C, 26
+            LXI D, 0FFF0H
+            CALL 0F800H
+            MVI C, 15
+            LXI D, FCB
+            CALL 0F800H
+            MVI C, 20
+            LXI D, FCB
+            CALL 0F800H
+            HLT
+        FCB: DB 0, 'WRAP    DAT', 0, 0, 0, 0
+             DS 20
+        ", &dir, |cpu| {
+            for n in 0..0x40 {
+                cpu.memory[0xFFC0 + n] = n as u8 + 1;
+            }
+            cpu.memory[0x08..0x40].fill(0xEE);
+        });
+
+        // Written from 0xFFC0 to 0x003F, read back from 0xFFF0 to 0x006F
+        let file = fs::read(dir.join("wrap.dat")).unwrap();
+        assert_eq!(file.len(), RECORD);
+        assert_eq!((file[0], file[0x3F], file[0x48], file[0x7F]), (1, 0x40, 0xEE, 0xEE));
+        for (n, byte) in file.iter().enumerate() {
+            assert_eq!(cpu.memory[at(0xFFF0, n)], *byte, "byte {}", n);
+        }
+
+        let cpu = run_with("
+            ORG 100H
+            MVI C, 26
+            LXI D, 0FFF8H
+            CALL 0F800H
+            MVI C, 17
+            LXI D, FCB
+            CALL 0F800H
+            HLT
+        FCB: DB 0, 'WRAP    DAT', 0, 0, 0, 0
+             DS 20
+        ", &dir, |_| {});
+
+        // The directory entry runs from 0xFFF8 to 0x0017
+        let entry: Vec<u8> = (0..32).map(|n| cpu.memory[at(0xFFF8, n)]).collect();
+        assert_eq!(&entry[1..12], b"WRAP    DAT");
+        assert_eq!(cpu.memory[0x0017], 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+// Runs the classic 8080 and Z80 exercisers under the CP/M harness
+//
+// The binaries aren't distributed with the emulator so the tests that need
+// them are ignored and a plain cargo test only runs test.com and checks the
+// expected results are in place. To run the rest either
+//
+//     cpu_diag/run.sh DIR
+//
+// which copies the binaries found in DIR into cpu_diag/, checks them against
+// cpu_diag/SHA256SUMS and runs their tests, or copy them in by hand under the
+// names in cpu_diag/readme.md and run
+//
+//     cargo test --release cpu_diag -- --include-ignored
+//
+// where a missing binary fails its test. 8080EXM is checked against the CRC
+// table in cpu_diag/8080EXM.crc, ZEXDOC and ZEXALL against the output of a
+// passing run in cpu_diag/zexdoc.txt and zexall.txt
+
+use super::cpm::BDOS_ENTRY;
+use super::{Cpu, Variant, CYCLES};
+use std::fs;
+use std::path::Path;
+
+fn load(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("cpu_diag").join(name);
+    fs::read(&path).unwrap_or_else(|e| panic!("can't read {}: {}", path.display(), e))
+}
+
+// Runs until the program warm boots or the cycle budget runs out
+
+fn run(program: &[u8], budget: u64) -> Cpu {
+    let mut cpu = Cpu::init_cpm(program);
+    let mut cycles: u64 = 0;
+
+    while !cpu.hlted {
+        cycles += CYCLES[cpu.memory[cpu.regs.pc as usize] as usize] as u64;
+        assert!(cycles <= budget, "out of cycles, output so far:\n{}", cpu.output);
+        cpu.cycle().unwrap();
+    }
+
+    cpu
+}
+
+// Same for the Z80, which only runs through step
+
+fn run_z80(program: &[u8], budget: u64) -> Cpu {
+    let mut cpu = Cpu::init_cpm(program);
+    cpu.variant = Variant::Z80;
+    let mut cycles: u64 = 0;
+
+    while !cpu.hlted {
+        cycles += cpu.step().unwrap() as u64;
+        assert!(cycles <= budget, "out of cycles, output so far:\n{}", cpu.output);
+    }
+
+    cpu
+}
+
+#[test]
+fn test_com() {
+    // MVI B, 10 followed by empty memory, the NOPs run up into the BDOS
+    // which is entered with C=0 and resets the system
+    let cpu = run(&load("test.com"), 1_000_000);
+    assert_eq!(cpu.instructions, 1 + (BDOS_ENTRY - 0x102) as usize);
+    assert_eq!(cpu.output, "");
+}
+
+#[test]
+#[ignore = "needs cpu_diag/8080PRE.COM"]
+fn preliminary() {
+    let cpu = run(&load("8080PRE.COM"), 10_000_000);
+    assert!(cpu.output.contains("8080 Preliminary tests complete"), "{}", cpu.output);
+}
+
+#[test]
+#[ignore = "needs cpu_diag/TST8080.COM"]
+fn tst8080() {
+    let cpu = run(&load("TST8080.COM"), 10_000_000);
+    assert!(cpu.output.contains("CPU IS OPERATIONAL"), "{}", cpu.output);
+}
+
+#[test]
+#[ignore = "needs cpu_diag/CPUTEST.COM"]
+fn cputest() {
+    let cpu = run(&load("CPUTEST.COM"), 500_000_000);
+    assert!(cpu.output.contains("CPU TESTS OK"), "{}", cpu.output);
+}
+
+// The CRC a real 8080 gives for each group, checked in next to the binary
+const EXM_CRCS: &str = include_str!("../../cpu_diag/8080EXM.crc");
+
+fn exm_crcs() -> Vec<(&'static str, &'static str)> {
+    EXM_CRCS
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|l| l.split_once("  "))
+        .map(|(crc, name)| (name, crc))
+        .collect()
+}
+
+// Each group prints "name....  PASS! crc is:xxxxxxxx" or an ERROR line,
+// builds that carry their own CRC table just print OK
+
+fn exm_results(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, result) = line.split_once("..")?;
+            let crc = match result.split_once("crc is:") {
+                Some((_, crc)) => crc.trim().to_string(),
+                None if result.trim_end().ends_with(" OK") => "OK".to_string(),
+                None => format!("ERROR {}", result.split_once("found:")?.1.trim()),
+            };
+            Some((name.trim().to_string(), crc))
+        })
+        .collect()
+}
+
+#[test]
+#[ignore = "needs cpu_diag/8080EXM.COM, slow"]
+fn exerciser() {
+    let cpu = run(&load("8080EXM.COM"), 50_000_000_000);
+    let results = exm_results(&cpu.output);
+
+    for (name, crc) in exm_crcs() {
+        match results.iter().find(|(n, _)| n == name) {
+            Some((_, got)) if got == "OK" => {}
+            Some((_, got)) => assert_eq!(got, crc, "group {}", name),
+            None => panic!("group {} didn't run, output:\n{}", name, cpu.output),
+        }
+    }
+    assert!(cpu.output.contains("Tests complete"), "{}", cpu.output);
+}
+
+#[test]
+fn exerciser_crc_table() {
+    let crcs = exm_crcs();
+    assert_eq!(crcs.len(), 25);
+    assert_eq!(crcs[0], ("dad <b,d,h,sp>", "14474ba6"));
+    assert!(crcs.iter().all(|(_, crc)| crc.len() == 8 && u32::from_str_radix(crc, 16).is_ok()));
+}
+
+#[test]
+fn exerciser_output_parsing() {
+    let out = "8080 instruction exerciser\r\ndad <b,d,h,sp>................  PASS! crc is:14474ba6\r\n\
+               aluop nn......................  ERROR **** crc expected:9e922f9e found:00000000\r\n\
+               <inr,dcr> a...................  OK\r\n";
+    let results = exm_results(out);
+    assert_eq!(results[0], ("dad <b,d,h,sp>".to_string(), "14474ba6".to_string()));
+    assert_eq!(results[1], ("aluop nn".to_string(), "ERROR 00000000".to_string()));
+    assert_eq!(results[2], ("<inr,dcr> a".to_string(), "OK".to_string()));
+}
+
+// ZEXDOC checks the documented flags and ZEXALL all of them, each group ends
+// its line with OK or an ERROR with the expected and found CRCs. The output
+// of a passing run is checked in next to the binaries
+const ZEXDOC_OUTPUT: &str = include_str!("../../cpu_diag/zexdoc.txt");
+const ZEXALL_OUTPUT: &str = include_str!("../../cpu_diag/zexall.txt");
+
+// CP/M ends its lines with \n\r, so the \r starts the next one
+fn output_lines(output: &str) -> Vec<&str> {
+    output.lines().map(str::trim).filter(|l| !l.is_empty()).collect()
+}
+
+fn zex(name: &str, expected: &str) {
+    let cpu = run_z80(&load(name), 100_000_000_000);
+    assert_eq!(output_lines(&cpu.output), output_lines(expected));
+}
+
+#[test]
+fn zex_expected_output() {
+    for expected in [ZEXDOC_OUTPUT, ZEXALL_OUTPUT] {
+        let lines = output_lines(expected);
+        assert_eq!(lines.len(), 69);
+        assert_eq!(lines.iter().filter(|l| l.ends_with("..  OK")).count(), 67);
+        assert_eq!(lines.last(), Some(&"Tests complete"));
+    }
+}
+
+#[test]
+#[ignore = "needs cpu_diag/ZEXDOC.COM, slow"]
+fn zexdoc() {
+    zex("ZEXDOC.COM", ZEXDOC_OUTPUT);
+}
+
+#[test]
+#[ignore = "needs cpu_diag/ZEXALL.COM, slow"]
+fn zexall() {
+    zex("ZEXALL.COM", ZEXALL_OUTPUT);
+}
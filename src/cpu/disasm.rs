@@ -0,0 +1,131 @@
+// Intel 8080 disassembler
+
+const REGS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "M", "A"];
+const PAIRS: [&str; 4] = ["B", "D", "H", "SP"];
+const CONDS: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"];
+const ALU_IMM: [&str; 8] = ["ADI", "ACI", "SUI", "SBI", "ANI", "XRI", "ORI", "CPI"];
+
+// Length in bytes of the instruction starting with opcode
+
+pub fn instruction_len(op: u8) -> u16 {
+    match op {
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => 2,
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => 2,
+        0xD3 | 0xDB => 2,
+        0x01 | 0x11 | 0x21 | 0x31 => 3,
+        0x22 | 0x2A | 0x32 | 0x3A => 3,
+        0xC3 | 0xCD => 3,
+        // Undocumented JMP and CALL
+        0xCB | 0xDD | 0xED | 0xFD => 3,
+        _ if op & 0xC7 == 0xC2 || op & 0xC7 == 0xC4 => 3,
+        _ => 1,
+    }
+}
+
+// Disassembles the instruction at addr, returns the text and its length
+
+pub fn disassemble(memory: &[u8], addr: u16) -> (String, u16) {
+    let byte = |n: u16| memory.get(addr.wrapping_add(n) as usize).copied().unwrap_or(0);
+    let op = byte(0);
+    let d8 = format!("0x{:02X}", byte(1));
+    let a16 = format!("0x{:04X}", (byte(2) as u16) << 8 | byte(1) as u16);
+
+    let dst = REGS[((op >> 3) & 7) as usize];
+    let src = REGS[(op & 7) as usize];
+    let rp = PAIRS[((op >> 4) & 3) as usize];
+    let cond = CONDS[((op >> 3) & 7) as usize];
+
+    let text = match op {
+        0x00 => "NOP".to_string(),
+        0x76 => "HLT".to_string(),
+        0x40..=0x7F => format!("MOV {}, {}", dst, src),
+        0x80..=0xBF => format!("{} {}", ALU[((op >> 3) & 7) as usize], src),
+
+        0x01 | 0x11 | 0x21 | 0x31 => format!("LXI {}, {}", rp, a16),
+        0x09 | 0x19 | 0x29 | 0x39 => format!("DAD {}", rp),
+        0x03 | 0x13 | 0x23 | 0x33 => format!("INX {}", rp),
+        0x0B | 0x1B | 0x2B | 0x3B => format!("DCX {}", rp),
+        0x02 | 0x12 => format!("STAX {}", rp),
+        0x0A | 0x1A => format!("LDAX {}", rp),
+        0x22 => format!("SHLD {}", a16),
+        0x2A => format!("LHLD {}", a16),
+        0x32 => format!("STA {}", a16),
+        0x3A => format!("LDA {}", a16),
+
+        _ if op & 0xC7 == 0x04 => format!("INR {}", dst),
+        _ if op & 0xC7 == 0x05 => format!("DCR {}", dst),
+        _ if op & 0xC7 == 0x06 => format!("MVI {}, {}", dst, d8),
+
+        0x07 => "RLC".to_string(),
+        0x0F => "RRC".to_string(),
+        0x17 => "RAL".to_string(),
+        0x1F => "RAR".to_string(),
+        0x27 => "DAA".to_string(),
+        0x2F => "CMA".to_string(),
+        0x37 => "STC".to_string(),
+        0x3F => "CMC".to_string(),
+
+        0xC3 => format!("JMP {}", a16),
+        0xCD => format!("CALL {}", a16),
+        0xC9 => "RET".to_string(),
+        _ if op & 0xC7 == 0xC2 => format!("J{} {}", cond, a16),
+        _ if op & 0xC7 == 0xC4 => format!("C{} {}", cond, a16),
+        _ if op & 0xC7 == 0xC0 => format!("R{}", cond),
+        _ if op & 0xC7 == 0xC7 => format!("RST {}", (op >> 3) & 7),
+        _ if op & 0xC7 == 0xC6 => format!("{} {}", ALU_IMM[((op >> 3) & 7) as usize], d8),
+
+        0xC5 | 0xD5 | 0xE5 => format!("PUSH {}", rp),
+        0xC1 | 0xD1 | 0xE1 => format!("POP {}", rp),
+        0xF5 => "PUSH PSW".to_string(),
+        0xF1 => "POP PSW".to_string(),
+
+        0xD3 => format!("OUT {}", d8),
+        0xDB => format!("IN {}", d8),
+        0xE3 => "XTHL".to_string(),
+        0xE9 => "PCHL".to_string(),
+        0xEB => "XCHG".to_string(),
+        0xF3 => "DI".to_string(),
+        0xF9 => "SPHL".to_string(),
+        0xFB => "EI".to_string(),
+
+        // Undocumented opcodes are marked with a *
+        0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => "*NOP".to_string(),
+        0xCB => format!("*JMP {}", a16),
+        0xD9 => "*RET".to_string(),
+        0xDD | 0xED | 0xFD => format!("*CALL {}", a16),
+
+        _ => format!("DB 0x{:02X}", op),
+    };
+
+    (text, instruction_len(op))
+}
+
+#[cfg(test)]
+mod disasm_test {
+    use super::*;
+
+    #[test]
+    fn decodes_operands() {
+        let mem = [0x31, 0x00, 0x24, 0x7E, 0xDA, 0x34, 0x12, 0xFE, 0x10, 0xDF];
+        assert_eq!(disassemble(&mem, 0), ("LXI SP, 0x2400".to_string(), 3));
+        assert_eq!(disassemble(&mem, 3), ("MOV A, M".to_string(), 1));
+        assert_eq!(disassemble(&mem, 4), ("JC 0x1234".to_string(), 3));
+        assert_eq!(disassemble(&mem, 7), ("CPI 0x10".to_string(), 2));
+        assert_eq!(disassemble(&mem, 9), ("RST 3".to_string(), 1));
+        assert_eq!(disassemble(&[0xED, 0x34, 0x12], 0), ("*CALL 0x1234".to_string(), 3));
+    }
+
+    #[test]
+    fn round_trips_through_assembler() {
+        for op in 0..=255u8 {
+            let mem = [op, 0x34, 0x12];
+            let (text, len) = disassemble(&mem, 0);
+            if text.starts_with('*') {
+                continue;
+            }
+            let prog = crate::asm::assemble(&text).unwrap();
+            assert_eq!(prog.bytes, mem[..len as usize], "{}", text);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-const parity_table: [u8; 256] = [
+const PARITY_TABLE: [u8; 256] = [
     1, 0, 0, 1, 0, 1, 1, 0, 0, 1, 1, 0, 1, 0, 0, 1,
     0, 1, 1, 0, 1, 0, 0, 1, 1, 0, 0, 1, 0, 1, 1, 0,
     0, 1, 1, 0, 1, 0, 0, 1, 1, 0, 0, 1, 0, 1, 1, 0,
@@ -16,7 +16,7 @@ const parity_table: [u8; 256] = [
     0, 1, 1, 0, 1, 0, 0, 1, 1, 0, 0, 1, 0, 1, 1, 0,
     1, 0, 0, 1, 0, 1, 1, 0, 0, 1, 1, 0, 1, 0, 0, 1
 ];
-#[derive(Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Flags {
     pub sign: bool,
     pub zero: bool,
@@ -35,53 +35,232 @@ impl Flags {
         res == 0
     }
 
-    // Calculates addition carry flag
+    // Calculates parity flag
+    // Looks the byte up in the table, set when the number of on bits is even
 
-    pub fn calc_add_cy(&self, res: u16) -> bool {
-        res > 0xff
+    pub fn calc_parity(&self, res: u8) -> bool {
+        PARITY_TABLE[res as usize] == 1
     }
 
-    // Calculate subtraction carry flag
+    // Sets S, Z and P from a result
 
-    pub fn calc_sub_cy(&self, res: u16) -> bool {
-        todo!();
+    pub fn set_all_flags(&mut self, res: u8) {
+        self.sign = self.calc_sign(res);
+        self.zero = self.calc_zero(res);
+        self.parity = self.calc_parity(res);
     }
 
-    // Calculates parity flag
-    // Cycles through the byte and checks if the number of bytes are even
+    /*
+    ALU, each op returns its result and sets the flags like the 8080 does
+    */
 
-    pub fn calc_parity(&self, res: u8) -> bool {
-        let mut num_of_on_bits: u8 = 0;
-        for i in 0..7 {
-            let bit = ((res >> i) & 1) == 1;
-            if bit == true {
-                num_of_on_bits += 1;
-            }
+    // ADD and ADC, AC is the carry out of bit 3
+
+    pub fn add(&mut self, a: u8, val: u8, carry: bool) -> u8 {
+        let res = a as u16 + val as u16 + carry as u16;
+        self.aux_carry = (a & 0x0F) + (val & 0x0F) + carry as u8 > 0x0F;
+        self.carry = res > 0xFF;
+        self.set_all_flags(res as u8);
+        res as u8
+    }
+
+    // SUB, SBB and CMP add the complement of the operand and the borrow, so
+    // AC is set when bit 4 didn't borrow and CY is the inverted carry
+
+    pub fn sub(&mut self, a: u8, val: u8, borrow: bool) -> u8 {
+        let res = self.add(a, !val, !borrow);
+        self.carry = !self.carry;
+        res
+    }
+
+    // ANA sets AC from bit 3 of either operand
+
+    pub fn and(&mut self, a: u8, val: u8) -> u8 {
+        let res = a & val;
+        self.carry = false;
+        self.aux_carry = (a | val) & 0x08 != 0;
+        self.set_all_flags(res);
+        res
+    }
+
+    pub fn xor(&mut self, a: u8, val: u8) -> u8 {
+        let res = a ^ val;
+        self.carry = false;
+        self.aux_carry = false;
+        self.set_all_flags(res);
+        res
+    }
+
+    pub fn or(&mut self, a: u8, val: u8) -> u8 {
+        let res = a | val;
+        self.carry = false;
+        self.aux_carry = false;
+        self.set_all_flags(res);
+        res
+    }
+
+    // INR and DCR leave CY alone
+
+    pub fn inr(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_add(1);
+        self.aux_carry = val & 0x0F == 0x0F;
+        self.set_all_flags(res);
+        res
+    }
+
+    pub fn dcr(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_sub(1);
+        self.aux_carry = val & 0x0F != 0;
+        self.set_all_flags(res);
+        res
+    }
+
+    // Decimal adjust, corrects A after adding two BCD numbers
+
+    pub fn daa(&mut self, a: u8) -> u8 {
+        let (lsb, msb) = (a & 0x0F, a >> 4);
+        let mut correction = 0;
+        let mut carry = self.carry;
+
+        if self.aux_carry || lsb > 9 {
+            correction |= 0x06;
+        }
+        if self.carry || msb > 9 || (msb >= 9 && lsb > 9) {
+            correction |= 0x60;
+            carry = true;
+        }
+
+        let res = self.add(a, correction, false);
+        self.carry = carry;
+        res
+    }
+
+    // The flags as PUSH PSW stores them, S Z 0 AC 0 P 1 CY from bit 7 down
+
+    pub fn to_psw(&self) -> u8 {
+        (self.sign as u8) << 7
+            | (self.zero as u8) << 6
+            | (self.aux_carry as u8) << 4
+            | (self.parity as u8) << 2
+            | 0x02
+            | self.carry as u8
+    }
+
+    // The fixed bits are ignored, POP PSW can't change them
+
+    pub fn from_psw(psw: u8) -> Flags {
+        Flags {
+            sign: psw & 0x80 != 0,
+            zero: psw & 0x40 != 0,
+            aux_carry: psw & 0x10 != 0,
+            parity: psw & 0x04 != 0,
+            carry: psw & 0x01 != 0,
         }
+    }
+}
+
+#[cfg(test)]
+mod flags_test {
+    use super::*;
 
-        // num_of_on_bits % 2 == 0
+    #[test]
+    fn psw_layout() {
+        let all = Flags { sign: true, zero: true, parity: true, carry: true, aux_carry: true };
+        assert_eq!(all.to_psw(), 0xD7);
+        assert_eq!(Flags::default().to_psw(), 0x02);
+        assert_eq!(Flags::from_psw(0xFF), all);
+        assert_eq!(Flags::from_psw(0x01), Flags { carry: true, ..Default::default() });
 
-        // 3 % 2 == 0
-        parity_table[res as usize] == 1
+        for psw in 0..=255u8 {
+            assert_eq!(Flags::from_psw(psw).to_psw(), psw & 0xD5 | 0x02);
+        }
     }
 
-    /*
-    Check if bit 4 of the result is the same as the accumulator, if not
-    we will set the aux carry flag
-    */
+    fn expected(res: i32, aux_carry: bool, carry: bool) -> Flags {
+        let r = res as u8;
+        Flags { sign: r >= 0x80, zero: r == 0, parity: r.count_ones().is_multiple_of(2), carry, aux_carry }
+    }
+
+    #[test]
+    fn add_and_sub_every_input() {
+        for a in 0..=255u8 {
+            for val in 0..=255u8 {
+                for c in [false, true] {
+                    // The flags going in shouldn't matter besides the carry argument
+                    let mut f = Flags::from_psw(!(c as u8));
+                    let sum = a as i32 + val as i32 + c as i32;
+                    assert_eq!(f.add(a, val, c), sum as u8);
+                    let aux = (a ^ val ^ sum as u8) & 0x10 != 0;
+                    assert_eq!(f, expected(sum, aux, sum > 0xFF), "{:02X} + {:02X} + {}", a, val, c);
+
+                    let mut f = Flags::from_psw(!(c as u8));
+                    let diff = a as i32 - val as i32 - c as i32;
+                    assert_eq!(f.sub(a, val, c), diff as u8);
+                    let no_borrow = (a ^ val ^ diff as u8) & 0x10 == 0;
+                    assert_eq!(f, expected(diff, no_borrow, diff < 0), "{:02X} - {:02X} - {}", a, val, c);
+                }
+            }
+        }
+    }
 
-    pub fn calc_aux(&self, res: u16, accu: u8) -> bool {
-        let b4 = ((res >> 4) & 1) as u8;
-        let a_b4 = (accu >> 4) & 1;
+    #[test]
+    fn logic_every_input() {
+        for a in 0..=255u8 {
+            for val in 0..=255u8 {
+                for c in [false, true] {
+                    let mut f = Flags { carry: c, aux_carry: c, ..Default::default() };
+                    assert_eq!(f.and(a, val), a & val);
+                    assert_eq!(f, expected((a & val) as i32, (a | val) & 0x08 != 0, false));
 
+                    assert_eq!(f.xor(a, val), a ^ val);
+                    assert_eq!(f, expected((a ^ val) as i32, false, false));
 
-        b4 != a_b4
-   
+                    f.carry = c;
+                    assert_eq!(f.or(a, val), a | val);
+                    assert_eq!(f, expected((a | val) as i32, false, false));
+                }
+            }
+        }
     }
 
-    pub fn set_all_flags(&mut self, res: u8) {
-        self.sign = self.calc_sign(res);
-        self.zero = self.calc_zero(res);
-        
+    #[test]
+    fn inr_and_dcr_keep_carry() {
+        for val in 0..=255u8 {
+            for c in [false, true] {
+                let mut f = Flags { carry: c, ..Default::default() };
+                assert_eq!(f.inr(val), val.wrapping_add(1));
+                assert_eq!(f, expected(val as i32 + 1, val & 0x0F == 0x0F, c));
+
+                assert_eq!(f.dcr(val), val.wrapping_sub(1));
+                assert_eq!(f, expected(val as i32 - 1, val & 0x0F != 0, c));
+            }
+        }
+    }
+
+    #[test]
+    fn manual_examples() {
+        // Examples from the 8080 programmer's manual, flags are S Z AC P CY
+        let cases = [
+            ("add", 0x2E, 0x6C, false, 0x9A, [1, 0, 1, 1, 0]),
+            ("adc", 0x42, 0x3D, true, 0x80, [1, 0, 1, 0, 0]),
+            ("sub", 0x3E, 0x3E, false, 0x00, [0, 1, 1, 1, 0]),
+            ("sbb", 0x04, 0x02, true, 0x01, [0, 0, 1, 0, 0]),
+            ("ana", 0xFC, 0x0F, false, 0x0C, [0, 0, 1, 1, 0]),
+            ("xra", 0x5C, 0x78, false, 0x24, [0, 0, 0, 1, 0]),
+            ("daa", 0x9B, 0x00, false, 0x01, [0, 0, 1, 0, 1]),
+        ];
+
+        for (op, a, val, carry, res, [s, z, ac, p, cy]) in cases {
+            let mut f = Flags { carry, ..Default::default() };
+            let got = match op {
+                "add" | "adc" => f.add(a, val, carry),
+                "sub" | "sbb" => f.sub(a, val, carry),
+                "ana" => f.and(a, val),
+                "xra" => f.xor(a, val),
+                _ => f.daa(a),
+            };
+            let flags = Flags { sign: s == 1, zero: z == 1, aux_carry: ac == 1, parity: p == 1, carry: cy == 1 };
+            assert_eq!((got, f), (res, flags), "{}", op);
+        }
     }
-}
\ No newline at end of file
+}
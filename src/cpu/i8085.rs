@@ -0,0 +1,252 @@
+// Intel 8085 additions, RIM/SIM, the extra interrupt inputs and serial lines
+//
+// RST 5.5 and 6.5 are level triggered so they're plain inputs the host sets
+// and clears, RST 7.5 and TRAP are edge triggered and latch until serviced.
+
+use super::{Cpu, Variant};
+use crate::EmuError;
+
+pub(super) const CYCLES_8085: [u8; 256] = [
+    //  0  1   2   3   4   5   6   7   8   9   A   B   C   D   E   F
+        4, 10, 7,  6,  4,  4,  7,  4,  10, 10, 7,  6,  4,  4,  7,  4,  // 0
+        7, 10, 7,  6,  4,  4,  7,  4,  10, 10, 7,  6,  4,  4,  7,  4,  // 1
+        4, 10, 16, 6,  4,  4,  7,  4,  10, 10, 16, 6,  4,  4,  7,  4,  // 2
+        4, 10, 13, 6,  10, 10, 10, 4,  10, 10, 13, 6,  4,  4,  7,  4,  // 3
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 4
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 5
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 6
+        7, 7,  7,  7,  7,  7,  5,  7,  4,  4,  4,  4,  4,  4,  7,  4,  // 7
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 8
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 9
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // A
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // B
+        6, 10, 7,  10, 9,  12, 7,  12, 6,  10, 7,  7,  9,  18, 7,  12, // C
+        6, 10, 7,  10, 9,  12, 7,  12, 6,  10, 7,  10, 9,  7,  7,  12, // D
+        6, 10, 7,  16, 9,  12, 7,  12, 6,  6,  7,  4,  9,  10, 7,  12, // E
+        6, 10, 7,  4,  9,  12, 7,  12, 6,  6,  7,  4,  9,  7,  7,  12  // F
+];
+
+// Vectors for TRAP, RST 5.5, 6.5 and 7.5
+pub const TRAP_VECTOR: u16 = 0x24;
+pub const RST55_VECTOR: u16 = 0x2C;
+pub const RST65_VECTOR: u16 = 0x34;
+pub const RST75_VECTOR: u16 = 0x3C;
+
+// What it takes the cpu to push pc and jump to a vector
+const INTERRUPT_CYCLES: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I8085 {
+    // Interrupt mask set by SIM, bit 0 masks RST 5.5, bit 1 6.5, bit 2 7.5
+    pub mask: u8,
+
+    pub rst55: bool,
+    pub rst65: bool,
+    // Latched on a rising edge, cleared when serviced or by SIM
+    pub rst75: bool,
+    pub trap: bool,
+
+    // Serial input and output lines
+    pub sid: bool,
+    pub sod: bool,
+
+    // Interrupt enable from before a TRAP, reported by the next RIM
+    ie_before_trap: Option<bool>,
+}
+
+impl Default for I8085 {
+    // Reset masks all three RST inputs
+    fn default() -> Self {
+        I8085 {
+            mask: 0x07,
+            rst55: false,
+            rst65: false,
+            rst75: false,
+            trap: false,
+            sid: false,
+            sod: false,
+            ie_before_trap: None,
+        }
+    }
+}
+
+impl Cpu {
+    // Latches a rising edge on RST 7.5
+
+    pub fn rst75(&mut self) {
+        self.i8085.rst75 = true;
+    }
+
+    // Latches a TRAP, which can't be masked or disabled
+
+    pub fn trap(&mut self) {
+        self.i8085.trap = true;
+    }
+
+    // RIM, reads SID, the pending and masked interrupts and the enable flag
+
+    pub(super) fn rim(&mut self) {
+        let s = &mut self.i8085;
+        let ie = s.ie_before_trap.take().unwrap_or(self.int_enabled);
+
+        self.regs.a = (s.sid as u8) << 7
+            | (s.rst75 as u8) << 6
+            | (s.rst65 as u8) << 5
+            | (s.rst55 as u8) << 4
+            | (ie as u8) << 3
+            | s.mask & 0x07;
+    }
+
+    // SIM, bit 3 enables setting the mask from bits 0-2, bit 4 clears the
+    // RST 7.5 latch and bit 6 enables writing bit 7 to SOD
+
+    pub(super) fn sim(&mut self) {
+        let a = self.regs.a;
+        let s = &mut self.i8085;
+
+        if a & 0x08 != 0 {
+            s.mask = a & 0x07;
+        }
+        if a & 0x10 != 0 {
+            s.rst75 = false;
+        }
+        if a & 0x40 != 0 {
+            s.sod = a & 0x80 != 0;
+        }
+    }
+
+    // Takes the highest priority pending interrupt, returns the cycles it
+    // took or None if there wasn't one
+
+    pub(super) fn service_8085(&mut self) -> Result<Option<u32>, EmuError> {
+        let s = self.i8085;
+
+        let vector = if s.trap {
+            self.i8085.trap = false;
+            self.i8085.ie_before_trap = Some(self.int_enabled);
+            TRAP_VECTOR
+        } else if !self.int_enabled {
+            return Ok(None);
+        } else if s.rst75 && s.mask & 0x04 == 0 {
+            self.i8085.rst75 = false;
+            RST75_VECTOR
+        } else if s.rst65 && s.mask & 0x02 == 0 {
+            RST65_VECTOR
+        } else if s.rst55 && s.mask & 0x01 == 0 {
+            RST55_VECTOR
+        } else {
+            return Ok(None);
+        };
+
+        self.int_enabled = false;
+        self.hlted = false;
+        self.push_pc(self.regs.pc)?;
+        self.regs.pc = vector;
+        Ok(Some(INTERRUPT_CYCLES))
+    }
+}
+
+impl Variant {
+    pub(super) fn cycles(self) -> &'static [u8; 256] {
+        match self {
+            Variant::I8080 => &super::CYCLES,
+            Variant::I8085 => &CYCLES_8085,
+            Variant::Z80 => &super::z80::CYCLES_Z80,
+        }
+    }
+
+    // Cycles on top of the table when a conditional jump, call or return
+    // is taken
+
+    pub(super) fn jump_taken(self) -> u8 {
+        match self {
+            Variant::I8080 | Variant::Z80 => 0,
+            Variant::I8085 => 3,
+        }
+    }
+
+    pub(super) fn call_taken(self) -> u8 {
+        match self {
+            Variant::I8080 => 6,
+            Variant::I8085 => 9,
+            Variant::Z80 => 7,
+        }
+    }
+
+    pub(super) fn ret_taken(self) -> u8 {
+        6
+    }
+}
+
+#[cfg(test)]
+mod i8085_test {
+    use super::*;
+
+    fn cpu(prog: &[u8]) -> Cpu {
+        let mut cpu = Cpu::init(0, prog);
+        cpu.variant = Variant::I8085;
+        cpu.regs.sp = 0x2400;
+        cpu
+    }
+
+    #[test]
+    fn rim_and_sim() {
+        // MVI A, 0xCD; SIM; MVI A, 0; RIM
+        let mut cpu = cpu(&[0x3E, 0xCD, 0x30, 0x3E, 0x00, 0x20]);
+        cpu.i8085.sid = true;
+        cpu.i8085.rst65 = true;
+        cpu.rst75();
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+
+        // SIM set the mask to 5, set SOD and left RST 7.5 latched
+        assert!(cpu.i8085.sod);
+        assert_eq!(cpu.i8085.mask, 0x05);
+        assert_eq!(cpu.regs.a, 0x80 | 0x40 | 0x20 | 0x05);
+    }
+
+    #[test]
+    fn interrupt_priority_and_masks() {
+        // EI; NOP...
+        let mut cpu = cpu(&[0xFB, 0x00, 0x00, 0x00]);
+        cpu.step().unwrap();
+
+        cpu.i8085.rst55 = true;
+        cpu.i8085.rst65 = true;
+        assert_eq!(cpu.step().unwrap(), 4);
+        assert_eq!(cpu.regs.pc, 0x0002);
+
+        cpu.i8085.mask = 0x01;
+        assert_eq!(cpu.step().unwrap(), 12);
+        assert_eq!(cpu.regs.pc, RST65_VECTOR);
+        assert!(!cpu.int_enabled);
+
+        // TRAP ignores the disabled interrupts, RIM sees the old enable
+        cpu.trap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.pc, TRAP_VECTOR);
+        cpu.memory[TRAP_VECTOR as usize] = 0x20;
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.a & 0x08, 0);
+    }
+
+    #[test]
+    fn cycle_table_and_8080_default() {
+        // MOV A, B; CALL 0x0010 and at 0x0010 RNZ
+        let mut prog = [0u8; 0x11];
+        prog[..4].copy_from_slice(&[0x78, 0xCD, 0x10, 0x00]);
+        prog[0x10] = 0xC0;
+
+        let mut cpu = cpu(&prog);
+        assert_eq!(cpu.step().unwrap(), 4);
+        assert_eq!(cpu.step().unwrap(), 18);
+        assert_eq!(cpu.step().unwrap(), 12);
+
+        // On the 8080 RIM is an undocumented NOP
+        let mut cpu = Cpu::init(0, &[0x78, 0x20]);
+        assert_eq!(cpu.step().unwrap(), 5);
+        assert_eq!(cpu.step().unwrap(), 4);
+        assert_eq!(cpu.regs.pc, 2);
+    }
+}
@@ -1,11 +1,24 @@
 mod flags;
+pub mod disasm;
+pub mod i8085;
+pub mod z80;
+
+#[cfg(feature = "cpm")]
+pub mod cpm;
+
+#[cfg(all(test, feature = "cpm"))]
+mod cpu_diag;
 
 use crate::platform::io::IO;
+use crate::EmuError;
 
+use std::fmt;
+use std::ops::Range;
 use std::mem;
-use flags::Flags;
+pub use flags::Flags;
 
-const MEMORY: usize = 0xFA00;
+// The full 64K address space, so addresses wrap like on the chip
+const MEMORY: usize = 0x10000;
 
 const CYCLES: [u8; 256] = [
     //  0  1   2   3   4   5   6   7   8  9   A   B   C   D   E  F
@@ -27,6 +40,18 @@ const CYCLES: [u8; 256] = [
         5, 10, 10, 4,  11, 11, 7,  11, 5, 5,  10, 4,  11, 17, 7, 11  // F
 ];
 
+// Which cpu to emulate, the 8085 adds a few instructions and interrupts
+// and has its own timings, the Z80 runs through its own decoder
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    I8080,
+    I8085,
+    Z80,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Registers {
     pub pc: u16,
     pub a: u8,
@@ -57,6 +82,7 @@ impl Registers {
     }
 }
 
+#[derive(Clone)]
 pub struct Cpu {
     pub regs: Registers,
 
@@ -72,9 +98,28 @@ pub struct Cpu {
 
     cycle: u8,
 
-    io: IO,
+    // Cycles on top of CYCLES when a conditional call or return is taken
+    extra_cycles: u8,
+
+    pub io: IO,
+
+    pub int_enabled: bool,
+
+    // Address ranges that are ROM, pushing the stack into one is an error
+    pub rom: Vec<Range<usize>>,
+
+    pub variant: Variant,
+    // Report undocumented opcodes as errors instead of running them
+    pub strict: bool,
+    // Interrupt inputs and serial lines, only used by the 8085
+    pub i8085: i8085::I8085,
+    // Extra registers and interrupt state, only used by the Z80
+    pub z80: z80::Z80,
 
     pub output: String,
+
+    #[cfg(feature = "cpm")]
+    pub cpm: cpm::Cpm,
 }
 
 impl Cpu {
@@ -96,6 +141,7 @@ impl Cpu {
             },
 
             cycle: 0,
+            extra_cycles: 0,
 
             flags: Flags {
                 sign: false,
@@ -114,33 +160,43 @@ impl Cpu {
 
             io: IO::default(),
 
+            int_enabled: false,
+
+            rom: Vec::new(),
+
+            variant: Variant::I8080,
+            strict: false,
+            i8085: i8085::I8085::default(),
+            z80: z80::Z80::default(),
+
+            #[cfg(feature = "cpm")]
+            cpm: cpm::Cpm::default(),
+
         };
 
-        c.load_into_memory(program, pc as usize);
+        // Meant for programs known to fit, anything past the end of memory
+        // is dropped. Use load_into_memory to find out
+        let fits = program.len().min(MEMORY.saturating_sub(pc as usize));
+        let _ = c.load_into_memory(&program[..fits], pc as usize);
         c
     }
 
     // Load array of bytes into memory
 
-    pub fn load_into_memory(&mut self, bytes: &[u8], address: usize) -> Result<(), &'static str> {
-        if bytes.len() == 0 {
-            return Err("File is empty");
+    pub fn load_into_memory(&mut self, bytes: &[u8], address: usize) -> Result<(), EmuError> {
+        if address + bytes.len() > MEMORY {
+            return Err(EmuError::LoadOutOfRange { addr: address, len: bytes.len() });
         }
 
-        println!("CPU Loaded program to address 0x{:X}", address);
-
-        for i in 0..bytes.len() {
-            self.memory[i + address] = bytes[i];
-            // println!("HEX DUMP: 0x{:X}", bytes[i]);
-        }
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
 
         Ok(())
     }
 
     // Cycle with cycle durations
-    pub fn cycle_d(&mut self) {
+    pub fn cycle_d(&mut self) -> Result<(), EmuError> {
         if self.cycle > CYCLES[self.memory[self.regs.pc as usize] as usize] {
-            self.cycle();
+            self.cycle()?;
            self.cycle = 0;
         //    println!("SDF");
         } else {
@@ -148,14 +204,73 @@ impl Cpu {
             // println!("UA");
         }
 
+        Ok(())
+    }
+
+    // Runs one instruction and returns how many clock cycles it took, a halted
+    // cpu idles until an interrupt wakes it
+
+    pub fn step(&mut self) -> Result<u32, EmuError> {
+        if self.variant == Variant::Z80 {
+            return self.step_z80();
+        }
+
+        if self.variant == Variant::I8085 {
+            if let Some(cycles) = self.service_8085()? {
+                return Ok(cycles);
+            }
+        }
+
+        if self.hlted {
+            return Ok(4);
+        }
+
+        let op = self.memory[self.regs.pc as usize];
+        self.extra_cycles = 0;
+        self.cycle()?;
+        Ok(self.variant.cycles()[op as usize] as u32 + self.extra_cycles as u32)
+    }
+
+    // Executes RST n, ignored while interrupts are disabled
+
+    pub fn interrupt(&mut self, n: u16) -> Result<(), EmuError> {
+        if self.variant == Variant::Z80 {
+            return self.interrupt_z80(n);
+        }
+
+        if self.int_enabled {
+            self.int_enabled = false;
+            self.hlted = false;
+            self.push_pc(self.regs.pc)?;
+            self.regs.pc = n * 8;
+        }
+        Ok(())
+    }
+
+    // What pulling the RESET line does, pc back to 0 and interrupts off. The
+    // other registers and memory are left as they were
+
+    pub fn reset(&mut self) {
+        self.regs.pc = 0;
+        self.int_enabled = false;
+        self.hlted = false;
     }
 
     // Cycle the cpu once
 
-    pub fn cycle(&mut self) {
+    pub fn cycle(&mut self) -> Result<(), EmuError> {
+        if self.variant == Variant::Z80 {
+            return self.step_z80().map(|_| ());
+        }
+
+        #[cfg(feature = "cpm")]
+        if self.cpm.active && self.cpm_trap() {
+            return Ok(());
+        }
+
         let mut advance = 1;
         self.instructions += 1;
-        self.immediate = [self.memory[self.regs.pc as usize + 1], self.memory[self.regs.pc as usize + 2]];
+        self.immediate = [self.memory[self.regs.pc.wrapping_add(1) as usize], self.memory[self.regs.pc.wrapping_add(2) as usize]];
 
         match self.memory[self.regs.pc as usize] {
 
@@ -260,14 +375,14 @@ impl Cpu {
 
             // LHLD and SHLD
             0x2A => {
-                self.regs.l = self.memory[self.cmb_le(self.immediate[0], self.immediate[1]) as usize] as u8;
-                self.regs.h = self.memory[((self.cmb_le(self.immediate[0], self.immediate[1])) + 1) as usize] as u8;
+                self.regs.l = self.memory[self.cmb_le(self.immediate[0], self.immediate[1]) as usize];
+                self.regs.h = self.memory[self.cmb_le(self.immediate[0], self.immediate[1]).wrapping_add(1) as usize];
                 advance = 3;
             }
 
             0x22 => {
                 self.mem_write(self.cmb_le(self.immediate[0], self.immediate[1]) as usize, self.regs.l);
-                self.mem_write(((self.cmb_le(self.immediate[0], self.immediate[1])) + 1).into(), self.regs.h);
+                self.mem_write(self.cmb_le(self.immediate[0], self.immediate[1]).wrapping_add(1).into(), self.regs.h);
                 advance = 3;
             }
 
@@ -307,13 +422,21 @@ impl Cpu {
 
             0x29 => {
                 let hl32: u32= self.cmb_be(self.regs.h, self.regs.l).into();
-                let de32: u32 = self.cmb_be(self.regs.b, self.regs.c).into();
                 let res: u32 = hl32 + hl32;
                 self.regs.h = ((res & 0xff00) >> 8) as u8;
                 self.regs.l = (res & 0xff) as u8;
                 self.flags.carry = (res & 0xffff0000) > 0;
             }
 
+            0x39 => {
+                let hl32: u32= self.cmb_be(self.regs.h, self.regs.l).into();
+                let sp32: u32 = self.regs.sp.into();
+                let res: u32 = hl32 + sp32;
+                self.regs.h = ((res & 0xff00) >> 8) as u8;
+                self.regs.l = (res & 0xff) as u8;
+                self.flags.carry = (res & 0xffff0000) > 0;
+            }
+
             // 0x32 => {
             //     println!("{}", self.cmb_le(self.immediate[0], self.immediate[1]));
             // }
@@ -336,45 +459,58 @@ impl Cpu {
 
             0xc3 => {self.jmp_immediate(); advance = 0},
 
-            0xCA => {self.jmp_if(self.flags.zero    == true,  &mut advance)}
-            0xC2 => {self.jmp_if(self.flags.zero    == false, &mut advance)}
-            0xD2 => {self.jmp_if(self.flags.carry   == false, &mut advance)}
-            0xDA => {self.jmp_if(self.flags.carry   == true,  &mut advance)}
-            0xE2 => {self.jmp_if(self.flags.parity  == false, &mut advance)}
-            0xEA => {self.jmp_if(self.flags.parity  == true,  &mut advance)}
-            0xF2 => {self.jmp_if(self.flags.sign    == false, &mut advance)}
-            0xFA => {self.jmp_if(self.flags.sign    == true,  &mut advance)}
-
-            0xCD => self.call_imm(&mut advance),
-            0xC4 => self.call_if(self.flags.zero    == false, &mut advance),
-            0xD4 => self.call_if(self.flags.carry   == false, &mut advance),
-            0xE4 => self.call_if(self.flags.parity  == false, &mut advance),
-            0xF4 => self.call_if(self.flags.sign    == false, &mut advance),
-            0xCC => self.call_if(self.flags.zero    == true,  &mut advance),
-            0xDC => self.call_if(self.flags.carry   == true,  &mut advance),
-            0xEC => self.call_if(self.flags.parity  == true,  &mut advance),
-            0xFC => self.call_if(self.flags.sign    == true,  &mut advance),
-
-
-            0xC9 => {self.ret(); advance = 3},
-            0xC8 => {self.ret_if(self.flags.zero == true, &mut advance)}
-            0xD8 => {self.ret_if(self.flags.carry == true, &mut advance)}
-            0xE8 => {self.ret_if(self.flags.parity == true, &mut advance)}
-            0xF8 => {self.ret_if(self.flags.sign == true, &mut advance)}
-            0xC0 => {self.ret_if(self.flags.zero == false, &mut advance)}
-            0xD0 => {self.ret_if(self.flags.carry == false, &mut advance)}
-            0xE0 => {self.ret_if(self.flags.parity == false, &mut advance)}
-            0xF0 => {self.ret_if(self.flags.sign == false, &mut advance)}
+            0xCA => {self.jmp_if(self.flags.zero, &mut advance)}
+            0xC2 => {self.jmp_if(!self.flags.zero, &mut advance)}
+            0xD2 => {self.jmp_if(!self.flags.carry, &mut advance)}
+            0xDA => {self.jmp_if(self.flags.carry, &mut advance)}
+            0xE2 => {self.jmp_if(!self.flags.parity, &mut advance)}
+            0xEA => {self.jmp_if(self.flags.parity, &mut advance)}
+            0xF2 => {self.jmp_if(!self.flags.sign, &mut advance)}
+            0xFA => {self.jmp_if(self.flags.sign, &mut advance)}
+
+            0xCD => self.call_imm(&mut advance)?,
+            0xC4 => self.call_if(!self.flags.zero, &mut advance)?,
+            0xD4 => self.call_if(!self.flags.carry, &mut advance)?,
+            0xE4 => self.call_if(!self.flags.parity, &mut advance)?,
+            0xF4 => self.call_if(!self.flags.sign, &mut advance)?,
+            0xCC => self.call_if(self.flags.zero, &mut advance)?,
+            0xDC => self.call_if(self.flags.carry, &mut advance)?,
+            0xEC => self.call_if(self.flags.parity, &mut advance)?,
+            0xFC => self.call_if(self.flags.sign, &mut advance)?,
+
+
+            0xC9 => {self.ret(); advance = 0},
+            0xC8 => {self.ret_if(self.flags.zero, &mut advance)}
+            0xD8 => {self.ret_if(self.flags.carry, &mut advance)}
+            0xE8 => {self.ret_if(self.flags.parity, &mut advance)}
+            0xF8 => {self.ret_if(self.flags.sign, &mut advance)}
+            0xC0 => {self.ret_if(!self.flags.zero, &mut advance)}
+            0xD0 => {self.ret_if(!self.flags.carry, &mut advance)}
+            0xE0 => {self.ret_if(!self.flags.parity, &mut advance)}
+            0xF0 => {self.ret_if(!self.flags.sign, &mut advance)}
+
+            // PCHL
+            0xE9 => {self.regs.pc = self.cmb_be(self.regs.h, self.regs.l); advance = 0}
+
+            // RST
+            0xC7 => {self.rst(0)?; advance = 0}
+            0xCF => {self.rst(1)?; advance = 0}
+            0xD7 => {self.rst(2)?; advance = 0}
+            0xDF => {self.rst(3)?; advance = 0}
+            0xE7 => {self.rst(4)?; advance = 0}
+            0xEF => {self.rst(5)?; advance = 0}
+            0xF7 => {self.rst(6)?; advance = 0}
+            0xFF => {self.rst(7)?; advance = 0}
 
             /*
             Math
             */
 
             0xC6 => {self.add(self.immediate[0]); advance = 2}
-            0xCE => {self.add(self.immediate[0] + self.flags.carry as u8); advance = 2}
+            0xCE => {self.adc(self.immediate[0]); advance = 2}
 
             0xD6 => {self.sub(self.immediate[0]); advance = 2}
-            0xDE => {self.sub(self.immediate[0] + self.flags.carry as u8); advance = 2}
+            0xDE => {self.sbb(self.immediate[0]); advance = 2}
 
             0x80 => self.add(self.regs.b),
             0x81 => self.add(self.regs.c),
@@ -384,14 +520,14 @@ impl Cpu {
             0x85 => self.add(self.regs.l),
             0x86 => self.add(self.get_m()),
             0x87 => self.add(self.regs.a),
-            0x88 => self.add(self.regs.b + (self.flags.carry as u8)),
-            0x89 => self.add(self.regs.c + (self.flags.carry as u8)),
-            0x8A => self.add(self.regs.d + (self.flags.carry as u8)),
-            0x8B => self.add(self.regs.e + (self.flags.carry as u8)),
-            0x8C => self.add(self.regs.h + (self.flags.carry as u8)),
-            0x8D => self.add(self.regs.l + (self.flags.carry as u8)),
-            0x8E => self.add(self.get_m() + (self.flags.carry as u8)),
-            0x8F => self.add(self.regs.a + (self.flags.carry as u8)),
+            0x88 => self.adc(self.regs.b),
+            0x89 => self.adc(self.regs.c),
+            0x8A => self.adc(self.regs.d),
+            0x8B => self.adc(self.regs.e),
+            0x8C => self.adc(self.regs.h),
+            0x8D => self.adc(self.regs.l),
+            0x8E => self.adc(self.get_m()),
+            0x8F => self.adc(self.regs.a),
 
 
             0x90 => self.sub(self.regs.b),
@@ -402,17 +538,17 @@ impl Cpu {
             0x95 => self.sub(self.regs.l),
             0x96 => self.sub(self.get_m()),
             0x97 => self.sub(self.regs.a),
-            0x98 => self.sub(self.regs.b + (self.flags.carry as u8)),
-            0x99 => self.sub(self.regs.c + (self.flags.carry as u8)),
-            0x9A => self.sub(self.regs.d + (self.flags.carry as u8)),
-            0x9B => self.sub(self.regs.e + (self.flags.carry as u8)),
-            0x9C => self.sub(self.regs.h + (self.flags.carry as u8)),
-            0x9D => self.sub(self.regs.l + (self.flags.carry as u8)),
-            0x9E => self.sub(self.get_m() + (self.flags.carry as u8)),
-            0x9F => self.sub(self.regs.a + (self.flags.carry as u8)),
+            0x98 => self.sbb(self.regs.b),
+            0x99 => self.sbb(self.regs.c),
+            0x9A => self.sbb(self.regs.d),
+            0x9B => self.sbb(self.regs.e),
+            0x9C => self.sbb(self.regs.h),
+            0x9D => self.sbb(self.regs.l),
+            0x9E => self.sbb(self.get_m()),
+            0x9F => self.sbb(self.regs.a),
             
 
-            0xE6 => {self.ani(); advance = 2}
+            0xE6 => {self.ana(self.immediate[0]); advance = 2}
             0xA0 => self.ana(self.regs.b),
             0xA1 => self.ana(self.regs.c),
             0xA2 => self.ana(self.regs.d),
@@ -423,7 +559,7 @@ impl Cpu {
             0xA7 => self.ana(self.regs.a),
 
 
-            0xF6 => {self.ori(); advance = 2}
+            0xF6 => {self.ora(self.immediate[0]); advance = 2}
             0xB0 => self.ora(self.regs.b),
             0xB1 => self.ora(self.regs.c),
             0xB2 => self.ora(self.regs.d),
@@ -433,7 +569,7 @@ impl Cpu {
             0xB6 => self.ora(self.get_m()),
             0xB7 => self.ora(self.regs.a),
 
-            0xEE => {self.xri(); advance = 2}
+            0xEE => {self.xra(self.immediate[0]); advance = 2}
 
             0x04 => self.regs.b = self.inr(self.regs.b),
             0x0C => self.regs.c = self.inr(self.regs.c),
@@ -454,58 +590,65 @@ impl Cpu {
 
             // INX and DCX
 
-            0x03 => self.regs.set_bc(self.cmb_be(self.regs.b, self.regs.c) + 1),
-            0x13 => self.regs.set_de(self.cmb_be(self.regs.d, self.regs.e) + 1),
-            0x23 => self.regs.set_hl(self.cmb_be(self.regs.h, self.regs.l) + 1),
-            0x33 => self.regs.sp += 1,
-            0x0B => self.regs.set_bc(self.cmb_be(self.regs.b, self.regs.c) - 1),
-            0x1B => self.regs.set_de(self.cmb_be(self.regs.d, self.regs.e) - 1),
-            0x2B => self.regs.set_hl(self.cmb_be(self.regs.h, self.regs.l) - 1),
-            0x3B => self.regs.sp -= 2,
+            0x03 => self.regs.set_bc(self.cmb_be(self.regs.b, self.regs.c).wrapping_add(1)),
+            0x13 => self.regs.set_de(self.cmb_be(self.regs.d, self.regs.e).wrapping_add(1)),
+            0x23 => self.regs.set_hl(self.cmb_be(self.regs.h, self.regs.l).wrapping_add(1)),
+            0x33 => self.regs.sp = self.regs.sp.wrapping_add(1),
+            0x0B => self.regs.set_bc(self.cmb_be(self.regs.b, self.regs.c).wrapping_sub(1)),
+            0x1B => self.regs.set_de(self.cmb_be(self.regs.d, self.regs.e).wrapping_sub(1)),
+            0x2B => self.regs.set_hl(self.cmb_be(self.regs.h, self.regs.l).wrapping_sub(1)),
+            0x3B => self.regs.sp = self.regs.sp.wrapping_sub(1),
 
             /*
             Stack Functions
             */
 
-            0xC5 => self.push_regs(self.regs.b, self.regs.c),
-            0xD5 => self.push_regs(self.regs.d, self.regs.e),
-            0xE5 => self.push_regs(self.regs.h, self.regs.l),
+            0xC5 => self.push_regs(self.regs.b, self.regs.c)?,
+            0xD5 => self.push_regs(self.regs.d, self.regs.e)?,
+            0xE5 => self.push_regs(self.regs.h, self.regs.l)?,
             
             0xC1 => self.pop_into_bc(),
             0xD1 => self.pop_into_de(),
             0xE1 => self.pop_into_hl(),
 
+            // XTHL
+            0xE3 => {
+                let (sp, sp1) = (self.regs.sp as usize, self.regs.sp.wrapping_add(1) as usize);
+                let (l, h) = (self.memory[sp], self.memory[sp1]);
+                self.mem_write(sp, self.regs.l);
+                self.mem_write(sp1, self.regs.h);
+                self.regs.l = l;
+                self.regs.h = h;
+            }
+
+            // SPHL
+            0xF9 => self.regs.sp = self.cmb_be(self.regs.h, self.regs.l),
+
             0xF1 => {
-                self.regs.a = self.memory[(self.regs.sp + 1) as usize];
-                let psw: u8 = self.memory[self.regs.sp as usize];
-                self.flags.zero = (0x01 == (psw & 0x01));
-                self.flags.sign = (0x02 == (psw & 0x02));
-                self.flags.parity = (0x04 == (psw & 0x04));
-                self.flags.carry =  (0x05 == (psw & 0x08));
-                self.flags.aux_carry = (0x10 == (psw & 0x10));
-                self.regs.sp += 2;
+                self.regs.a = self.memory[self.regs.sp.wrapping_add(1) as usize];
+                self.flags = Flags::from_psw(self.memory[self.regs.sp as usize]);
+                self.regs.sp = self.regs.sp.wrapping_add(2);
 
             }
 
             0xf5 => {
-                self.mem_write((self.regs.sp - 1) as usize, self.regs.a);
-                let psw: u8 = (self.flags.zero as u8) | ((self.flags.sign as u8) << 1) | 
-                ((self.flags.parity as u8) << 2) | ((self.flags.carry as u8) << 3) | ((self.flags.aux_carry as u8) << 4);
-                self.mem_write((self.regs.sp - 2) as usize, psw);
-                self.regs.sp = self.regs.sp - 2;
+                self.check_stack()?;
+                self.mem_write(self.regs.sp.wrapping_sub(1) as usize, self.regs.a);
+                self.mem_write(self.regs.sp.wrapping_sub(2) as usize, self.flags.to_psw());
+                self.regs.sp = self.regs.sp.wrapping_sub(2);
             }
 
             // Logical
 
-            0xFE => {self.cmp(self.immediate[0].into()); advance = 2}
-            0xB8 => self.cmp(self.regs.b.into()),
-            0xB9 => self.cmp(self.regs.c.into()),
-            0xBA => self.cmp(self.regs.d.into()),
-            0xBB => self.cmp(self.regs.e.into()),
-            0xBC => self.cmp(self.regs.h.into()),
-            0xBD => self.cmp(self.regs.l.into()),
-            0xBF => self.cmp(self.regs.a.into()),
-            0xBE => self.cmp(self.get_m().into()),
+            0xFE => {self.cmp(self.immediate[0]); advance = 2}
+            0xB8 => self.cmp(self.regs.b),
+            0xB9 => self.cmp(self.regs.c),
+            0xBA => self.cmp(self.regs.d),
+            0xBB => self.cmp(self.regs.e),
+            0xBC => self.cmp(self.regs.h),
+            0xBD => self.cmp(self.regs.l),
+            0xBF => self.cmp(self.regs.a),
+            0xBE => self.cmp(self.get_m()),
 
             0xEB => {mem::swap(&mut self.regs.h, &mut self.regs.d); mem::swap(&mut self.regs.l, &mut self.regs.e);}
             
@@ -522,9 +665,32 @@ impl Cpu {
             0x0F => {
                 let x: u8 = self.regs.a;
                 self.regs.a = ((x & 1) << 7) | (x >> 1);
-                self.flags.carry = (1 == (x&1));
+                self.flags.carry = 1 == (x&1);
             }
 
+            // RLC
+            0x07 => {
+                let x: u8 = self.regs.a;
+                self.regs.a = x.rotate_left(1);
+                self.flags.carry = 1 == (x >> 7);
+            }
+
+            // RAL
+            0x17 => {
+                let x: u8 = self.regs.a;
+                self.regs.a = (x << 1) | self.flags.carry as u8;
+                self.flags.carry = 1 == (x >> 7);
+            }
+
+            // RAR
+            0x1F => {
+                let x: u8 = self.regs.a;
+                self.regs.a = ((self.flags.carry as u8) << 7) | (x >> 1);
+                self.flags.carry = 1 == (x & 1);
+            }
+
+            0x27 => self.daa(),
+
             /*
             IO Instructions 
             */
@@ -533,10 +699,13 @@ impl Cpu {
                 // todo!();
                 // (self.in_routine)(4);
                 // self.io.
-                self.io.cpu_write(self.immediate[0], self.regs.a);
+                self.io.cpu_write(self.immediate[0], self.regs.a)?;
                 // self.i
                 advance = 2},
-            0xFB => {},
+            0xDB => {self.regs.a = self.io.cpu_read(self.immediate[0])?; advance = 2}
+
+            0xF3 => self.int_enabled = false,
+            0xFB => self.int_enabled = true,
             
             /*
             MISC Instructions
@@ -544,22 +713,54 @@ impl Cpu {
             
             // ENABLE CFLAG
             0x37 => self.flags.carry = true,
-            // DISABLE CFLAG
-            0x3F => self.flags.carry = false,
+            // CMC, Compliment CFLAG
+            0x3F => self.flags.carry = !self.flags.carry,
 
             // CMA, Compliment A
             0x2F => self.regs.a = !self.regs.a,
 
             0x76 => self.hlted = true,
 
+            0x20 if self.variant == Variant::I8085 => self.rim(),
+            0x30 if self.variant == Variant::I8085 => self.sim(),
+
+            /*
+            Undocumented 8080 opcodes, aliases of the instructions whose
+            unused opcode bits they leave out
+            */
+
+            0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 if self.variant == Variant::I8080 => {
+                self.undocumented()?;
+            }
+            0xCB if self.variant == Variant::I8080 => {self.undocumented()?; self.jmp_immediate(); advance = 0}
+            0xD9 if self.variant == Variant::I8080 => {self.undocumented()?; self.ret(); advance = 0}
+            0xDD | 0xED | 0xFD if self.variant == Variant::I8080 => {
+                self.undocumented()?;
+                self.call_imm(&mut advance)?;
+            }
+
             
             
 
-            _ => unimplemented!("Opcode 0x{:X}", self.memory[self.regs.pc as usize]),
-            // _ => unimplemented!(),
+            opcode => {
+                self.instructions -= 1;
+                return Err(EmuError::IllegalOpcode { opcode, addr: self.regs.pc });
+            }
         }
 
-        self.regs.pc += advance;
+        self.regs.pc = self.regs.pc.wrapping_add(advance);
+        Ok(())
+    }
+
+    // Fails in strict mode, before the opcode has done anything
+
+    fn undocumented(&mut self) -> Result<(), EmuError> {
+        if self.strict {
+            self.instructions -= 1;
+            let opcode = self.memory[self.regs.pc as usize];
+            return Err(EmuError::UndocumentedOpcode { opcode, addr: self.regs.pc });
+        }
+        Ok(())
     }
 
     pub fn debug(&self) {
@@ -586,7 +787,8 @@ impl Cpu {
 
 
     fn jmp_if(&mut self, condition: bool, advance: *mut u16) {
-        if (condition == true) {
+        if condition {
+            self.extra_cycles = self.variant.jump_taken();
             self.jmp_immediate();
             unsafe {
                 *advance = 0;
@@ -598,14 +800,16 @@ impl Cpu {
         }
     }
 
-    fn call_if(&mut self, condition: bool, advance: *mut u16) {
-        if (condition == true) {
-            self.call_imm(advance);
+    fn call_if(&mut self, condition: bool, advance: *mut u16) -> Result<(), EmuError> {
+        if condition {
+            self.extra_cycles = self.variant.call_taken();
+            self.call_imm(advance)?;
         } else {
             unsafe {
                 *advance = 3;
             }
         }
+        Ok(())
     }
 
     fn jmp_immediate(&mut self) {
@@ -613,16 +817,17 @@ impl Cpu {
     }
 
     fn ret(&mut self) {
-        self.regs.pc = self.cmb_le(self.memory[self.regs.sp as usize], self.memory[(self.regs.sp + 1) as usize]);
+        self.regs.pc = self.cmb_le(self.memory[self.regs.sp as usize], self.memory[self.regs.sp.wrapping_add(1) as usize]);
 
-        self.regs.sp += 2;
+        self.regs.sp = self.regs.sp.wrapping_add(2);
     }
 
     fn ret_if(&mut self, condition: bool, advance: *mut u16) {
-        if condition == true {
+        if condition {
+            self.extra_cycles = self.variant.ret_taken();
             self.ret();
             unsafe {
-                *advance = 3;
+                *advance = 0;
             }
         } else {
             unsafe {
@@ -631,87 +836,67 @@ impl Cpu {
         }
     }
 
-    fn push_regs(&mut self, reg1: u8, reg2: u8) {
-        self.mem_write((self.regs.sp - 2) as usize, reg2);
-        self.mem_write((self.regs.sp - 1) as usize, reg1);
-        self.regs.sp -= 2;
+    fn push_regs(&mut self, reg1: u8, reg2: u8) -> Result<(), EmuError> {
+        self.check_stack()?;
+        self.mem_write(self.regs.sp.wrapping_sub(2) as usize, reg2);
+        self.mem_write(self.regs.sp.wrapping_sub(1) as usize, reg1);
+        self.regs.sp = self.regs.sp.wrapping_sub(2);
+        Ok(())
     }
 
     fn pop_into_bc(&mut self) {
         self.regs.c = self.memory[self.regs.sp as usize];
-        self.regs.b = self.memory[(self.regs.sp + 1) as usize];
-        self.regs.sp += 2;
+        self.regs.b = self.memory[self.regs.sp.wrapping_add(1) as usize];
+        self.regs.sp = self.regs.sp.wrapping_add(2);
     }
 
     fn pop_into_de(&mut self) {
         self.regs.e = self.memory[self.regs.sp as usize];
-        self.regs.d = self.memory[(self.regs.sp + 1) as usize];
-        self.regs.sp += 2;
+        self.regs.d = self.memory[self.regs.sp.wrapping_add(1) as usize];
+        self.regs.sp = self.regs.sp.wrapping_add(2);
     }
 
     fn pop_into_hl(&mut self) {
         self.regs.l = self.memory[self.regs.sp as usize];
-        self.regs.h = self.memory[(self.regs.sp + 1) as usize];
-        self.regs.sp += 2;
+        self.regs.h = self.memory[self.regs.sp.wrapping_add(1) as usize];
+        self.regs.sp = self.regs.sp.wrapping_add(2);
     }
 
-    // Normal CPU Call
-    
-    fn nm_call(&mut self) {
-        self.mem_write((self.regs.sp - 1) as usize, ((self.regs.pc >> 8) & 0xff) as u8);
-        self.mem_write((self.regs.sp - 2) as usize, (self.regs.pc & 0xff) as u8);
-
-        self.regs.sp -= 2;
+    // Pushes the address of the next instruction and jumps
 
+    fn call_imm(&mut self, advance: *mut u16) -> Result<(), EmuError> {
+        self.push_pc(self.regs.pc.wrapping_add(3))?;
         self.jmp_immediate();
+        unsafe {
+            *advance = 0;
+        }
+        Ok(())
     }
 
-    // Call with CP\M Support
-
-    fn cpm_call(&mut self, advance: *mut u16) {
-        const BDOS: u16 = 5;
-
-        const WRITESTR: u8 = 9;
-
-        let addr = self.cmb_le(self.immediate[0], self.immediate[1]);
+    fn rst(&mut self, n: u16) -> Result<(), EmuError> {
+        self.push_pc(self.regs.pc.wrapping_add(1))?;
+        self.regs.pc = n * 8;
+        Ok(())
+    }
 
-        if addr == BDOS {
-            if self.regs.c == WRITESTR {
-                let string_addr = self.cmb_be(self.regs.d, self.regs.e);
-                let mut c = 0;
-                while self.memory[(string_addr + c) as usize] != '$' as u8 {
-                    print!("{}", self.memory[(string_addr + c) as usize] as char);
-                    self.output.push(self.memory[(string_addr + c) as usize] as char);
-                    c += 1;
-                }
-            }
+    // Fails if pushing a word would write into ROM
 
-            unsafe {
-                *advance = 3;
-            }
-            
-        } else {
-            self.nm_call();
-            unsafe {
-                *advance = 0;
-            }
+    fn check_stack(&self) -> Result<(), EmuError> {
+        let sp = self.regs.sp.wrapping_sub(2);
+        let bytes = [sp as usize, self.regs.sp.wrapping_sub(1) as usize];
+        if self.rom.iter().any(|range| bytes.iter().any(|byte| range.contains(byte))) {
+            return Err(EmuError::StackOverflow { sp, pc: self.regs.pc });
         }
+        Ok(())
     }
 
-    fn call_imm(&mut self, advance: *mut u16) {
-        #[cfg(not(feature = "cpm"))]
-        {
-            self.nm_call();
-            unsafe {
-                *advance = 0;
-            }
-        }
+    fn push_pc(&mut self, pc: u16) -> Result<(), EmuError> {
+        self.check_stack()?;
+        self.mem_write(self.regs.sp.wrapping_sub(1) as usize, ((pc >> 8) & 0xff) as u8);
+        self.mem_write(self.regs.sp.wrapping_sub(2) as usize, (pc & 0xff) as u8);
 
-        #[cfg(feature = "cpm")]
-        {
-            // Add --features cpm to your cargo build arguments to include CP\M functionality
-            self.cpm_call(advance);
-        }
+        self.regs.sp = self.regs.sp.wrapping_sub(2);
+        Ok(())
     }
 
     pub fn cmb_le(&self, val1: u8, val2: u8) -> u16 {
@@ -730,138 +915,50 @@ impl Cpu {
     Arithmitic instructions
     */
 
-    fn ani(&mut self) {
-        let res = (self.regs.a & self.immediate[0]) as u16;
-        self.flags.carry = self.flags.calc_add_cy(res);
-        self.flags.zero = self.flags.calc_zero(res as u8);
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.parity = self.flags.calc_parity(res as u8);
-        self.regs.a = (self.regs.a & self.immediate[0]) as u8
-    }
+    // The flags are worked out in Flags, these just feed it A and the carry
 
-    fn ana(&mut self, val: u8) {
-        let res = (self.regs.a & val) as u16;
-        self.flags.carry = self.flags.calc_add_cy(res);
-        self.flags.zero = self.flags.calc_zero(res as u8);
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.parity = self.flags.calc_parity(res as u8);
-        self.regs.a = (self.regs.a & val) as u8
+    fn add(&mut self, val: u8) {
+        self.regs.a = self.flags.add(self.regs.a, val, false);
     }
 
-    fn ori(&mut self) {
-        let res = (self.regs.a | self.immediate[0]) as u16;
-        self.flags.carry = self.flags.calc_add_cy(res);
-        self.flags.zero = self.flags.calc_zero(res as u8);
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.parity = self.flags.calc_parity(res as u8);
-        self.regs.a = res as u8;
+    fn adc(&mut self, val: u8) {
+        self.regs.a = self.flags.add(self.regs.a, val, self.flags.carry);
     }
 
-
-    fn ora(&mut self, val: u8) {
-        let res = (self.regs.a | val) as u16;
-        self.flags.carry = self.flags.calc_add_cy(res);
-        self.flags.zero = self.flags.calc_zero(res as u8);
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.parity = self.flags.calc_parity(res as u8);
-        self.flags.aux_carry = self.flags.calc_aux(res, self.regs.a);
-        self.regs.a = res as u8;
+    fn sub(&mut self, val: u8) {
+        self.regs.a = self.flags.sub(self.regs.a, val, false);
     }
 
-    fn xri(&mut self) {
-        let res = (self.regs.a ^ self.immediate[0]) as u16;
-        self.flags.carry = self.flags.calc_add_cy(res);
-        self.flags.zero = self.flags.calc_zero(res as u8);
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.parity = self.flags.calc_parity(res as u8);
-        self.regs.a = res as u8;
+    fn sbb(&mut self, val: u8) {
+        self.regs.a = self.flags.sub(self.regs.a, val, self.flags.carry);
     }
 
-    fn xra(&mut self, val: u8) {
-        let res = (self.regs.a ^ val) as u16;
-        self.flags.carry = self.flags.calc_add_cy(res);
-        self.flags.zero = self.flags.calc_zero(res as u8);
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.parity = self.flags.calc_parity(res as u8);
-        self.regs.a = res as u8;
+    fn ana(&mut self, val: u8) {
+        self.regs.a = self.flags.and(self.regs.a, val);
     }
 
-    fn add(&mut self, val: u8) {
-        let res: u16 = ((self.regs.a as u16) + val as u16) as u16;
-        self.flags.carry = self.flags.calc_add_cy(res);
-        self.flags.zero = self.flags.calc_zero(res as u8);
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.parity = self.flags.calc_parity(res as u8);
-        self.flags.aux_carry = self.flags.calc_aux(res, self.regs.a);
-        self.regs.a = res as u8;
+    fn ora(&mut self, val: u8) {
+        self.regs.a = self.flags.or(self.regs.a, val);
     }
 
-    fn inr(&mut self, reg: u8) -> u8 {
-        let res: u16 = ((reg as u16) + 1 as u16) as u16;
-        self.flags.carry = self.flags.calc_add_cy(res);
-        self.flags.zero = self.flags.calc_zero(res as u8);
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.parity = self.flags.calc_parity(res as u8);
-        self.flags.aux_carry = self.flags.calc_aux(res, self.regs.a);
-        res as u8
-        
+    fn xra(&mut self, val: u8) {
+        self.regs.a = self.flags.xor(self.regs.a, val);
     }
 
-
-
-    // Returns add but with setting flags
-
-    fn get_add(&mut self, val: u16) -> u8 {
-        let res: u16 = ((self.regs.a as u16) + val as u16) as u16;
-        self.flags.carry = self.flags.calc_add_cy(res);
-        self.flags.zero = self.flags.calc_zero(res as u8);
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.parity = self.flags.calc_parity(res as u8);
-        self.flags.aux_carry = self.flags.calc_aux(res, self.regs.a);
-
-        return res as u8;
+    fn inr(&mut self, reg: u8) -> u8 {
+        self.flags.inr(reg)
     }
 
-    fn sub(&mut self, val: u8) {
-        let r1 = (self.regs.a as u16).wrapping_sub(val as u16);
-
-        // self.flags.carry = (self.regs.a as u16) > 0xff;
-        // self.flags.carry = !(r1 & 0x100) == 1;
-        self.flags.carry = (r1 > 0xff);
-        self.flags.zero = (r1 & 0xff) == 0;
-        self.flags.sign = self.flags.calc_sign(r1 as u8);
-        self.flags.parity = self.flags.calc_parity(r1 as u8);
-        self.regs.a = r1 as u8;
-        // println!("{}", self.flags.carry);
-        // i8080_set_flag(cpu, FLAG_C, !(res16 & 0x100));
+    fn dcr(&mut self, reg: u8) -> u8 {
+        self.flags.dcr(reg)
     }
 
-    fn get_sub(&mut self, val: u8) -> u8 {
-        let r1 = (self.regs.a as u16).wrapping_sub(val as u16);
-
-        // self.flags.carry = (self.regs.a as u16) > 0xff;
-        // self.flags.carry = !(r1 & 0x100) == 1;
-        self.flags.carry = (r1 > 0xff);
-        self.flags.zero = (r1 & 0xff) == 0;
-        self.flags.sign = self.flags.calc_sign(r1 as u8);
-        self.flags.parity = self.flags.calc_parity(r1 as u8);
-        r1 as u8
+    fn daa(&mut self) {
+        self.regs.a = self.flags.daa(self.regs.a);
     }
 
-    fn dcr(&mut self, reg: u8) -> u8 {
-        let r1 = (reg as u16).wrapping_sub(1 as u16);
-
-        // self.flags.carry = (self.regs.a as u16) > 0xff;
-        // self.flags.carry = !(r1 & 0x100) == 1;
-        self.flags.carry = (r1 > 0xff);
-        self.flags.zero = (r1 & 0xff) == 0;
-        self.flags.sign = self.flags.calc_sign(r1 as u8);
-        self.flags.parity = self.flags.calc_parity(r1 as u8);
-        r1 as u8
-    }
-    
     fn set_m(&mut self, val: u8) {
-        self.mem_write((self.cmb_be(self.regs.h, self.regs.l) as usize) as usize, val);
+        self.mem_write(self.cmb_be(self.regs.h, self.regs.l) as usize, val);
     }
 
     fn get_m(&self) -> u8 {
@@ -876,53 +973,87 @@ impl Cpu {
 
     // Logical
 
-    fn cmp(&mut self, val: u16) {
-        // let res: u16 = (self.regs.a as u16) - val as u16;
-        let res: u16 = (self.regs.a as u16).wrapping_sub(val as u16);
-        // let
-        self.flags.zero = res == 0;
-        self.flags.sign = self.flags.calc_sign(res as u8);
-        self.flags.carry = (self.regs.a < val as u8);
+    fn cmp(&mut self, val: u8) {
+        self.flags.sub(self.regs.a, val, false);
     }
 
     // Communication with the outside machine/emulator
 
-    // Return list of registers with corrosponding names
-
-    pub fn get_regs(&self) -> ([&str; 9], [u8; 7], [u16; 2]) {
-        let regs = [
-            self.regs.a,
-            self.regs.b,
-            self.regs.c,
-            self.regs.d,
-            self.regs.e,
-            self.regs.h,
-            self.regs.l,
-        ];
-
-        let regs16 = [self.regs.pc, self.regs.sp];
+    // Snapshot of the registers and flags
 
-        let names = [
-            "A",
-            "B",
-            "C",
-            "D",
-            "E",
-            "H",
-            "L",
-            "PC",
-            "SP",
-        ];
-
-        (names, regs, regs16)
+    pub fn get_regs(&self) -> RegisterView {
+        RegisterView {
+            regs: self.regs,
+            flags: self.flags,
+            int_enabled: self.int_enabled,
+            hlted: self.hlted,
+        }
     }
 
+    // Sets a register, register pair or flag by name, used by the debugger
+
+    pub fn set_reg(&mut self, name: &str, value: u16) -> Result<(), String> {
+        let byte = value as u8;
+        let flag = value != 0;
+
+        match name.to_uppercase().as_str() {
+            "A" => self.regs.a = byte,
+            "B" => self.regs.b = byte,
+            "C" => self.regs.c = byte,
+            "D" => self.regs.d = byte,
+            "E" => self.regs.e = byte,
+            "H" => self.regs.h = byte,
+            "L" => self.regs.l = byte,
+            "BC" => self.regs.set_bc(value),
+            "DE" => self.regs.set_de(value),
+            "HL" => self.regs.set_hl(value),
+            "PC" => self.regs.pc = value,
+            "SP" => self.regs.sp = value,
+            "S" => self.flags.sign = flag,
+            "Z" => self.flags.zero = flag,
+            "P" => self.flags.parity = flag,
+            "CY" => self.flags.carry = flag,
+            "AC" => self.flags.aux_carry = flag,
+            "IE" => self.int_enabled = flag,
+            _ => return Err(format!("unknown register {}", name)),
+        }
 
+        Ok(())
+    }
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterView {
+    pub regs: Registers,
+    pub flags: Flags,
+    pub int_enabled: bool,
+    pub hlted: bool,
+}
 
-
-
-
+impl fmt::Display for RegisterView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = &self.regs;
+        let flag = |set: bool, name: &'static str| if set { name } else { "-" };
+
+        writeln!(
+            f,
+            "A={:02X} B={:02X} C={:02X} D={:02X} E={:02X} H={:02X} L={:02X}",
+            r.a, r.b, r.c, r.d, r.e, r.h, r.l
+        )?;
+        write!(
+            f,
+            "PC={:04X} SP={:04X} FLAGS={}{}{}{}{}{}{}",
+            r.pc,
+            r.sp,
+            flag(self.flags.sign, "S"),
+            flag(self.flags.zero, "Z"),
+            flag(self.flags.aux_carry, "A"),
+            flag(self.flags.parity, "P"),
+            flag(self.flags.carry, "C"),
+            if self.int_enabled { " EI" } else { "" },
+            if self.hlted { " HLT" } else { "" },
+        )
+    }
 }
 
 #[cfg(test)]
@@ -934,7 +1065,7 @@ mod cpu_test {
         let prog = [0x01, 0x34, 0x12];
         // LXI B, 0x1234
         let mut cpu = Cpu::init(0x0, &prog);
-        cpu.cycle();
+        cpu.cycle().unwrap();
 
         assert_eq!(cpu.regs.b, 0x12);
         assert_eq!(cpu.regs.c, 0x34);
@@ -946,7 +1077,7 @@ mod cpu_test {
         let prog = [0x11, 0x34, 0x12];
         // LXI B, 0x1234
         let mut cpu = Cpu::init(0x0, &prog);
-        cpu.cycle();
+        cpu.cycle().unwrap();
 
         assert_eq!(cpu.regs.d, 0x12);
         assert_eq!(cpu.regs.e, 0x34);
@@ -958,7 +1089,7 @@ mod cpu_test {
         let prog = [0x21, 0x34, 0x12];
         // LXI B, 0x1234
         let mut cpu = Cpu::init(0x0, &prog);
-        cpu.cycle();
+        cpu.cycle().unwrap();
 
         assert_eq!(cpu.regs.h, 0x12);
         assert_eq!(cpu.regs.l, 0x34);
@@ -980,11 +1111,78 @@ mod cpu_test {
         
         
         let mut cpu = Cpu::init(0x0, &prog);
-        for i in 0..2 {
-            cpu.cycle();
+        for _ in 0..2 {
+            cpu.cycle().unwrap();
         }
 
         
         assert_eq!(cpu.memory[0x8000], 0xff);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn illegal_opcode_and_load_errors() {
+        // 0x08 is an undocumented NOP on the 8080 and nothing on the 8085
+        let mut cpu = Cpu::init(0x0, &[0x00, 0x08]);
+        cpu.variant = Variant::I8085;
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.cycle(), Err(EmuError::IllegalOpcode { opcode: 0x08, addr: 0x0001 }));
+        assert_eq!(cpu.regs.pc, 0x0001);
+
+        let err = cpu.load_into_memory(&[0; 16], MEMORY - 8);
+        assert_eq!(err, Err(EmuError::LoadOutOfRange { addr: MEMORY - 8, len: 16 }));
+    }
+
+    #[test]
+    fn push_pop_psw() {
+        // STC; PUSH PSW; POP B; MVI C, 0xC4; PUSH B; POP PSW
+        let prog = [0x37, 0xF5, 0xC1, 0x0E, 0xC4, 0xC5, 0xF1];
+        let mut cpu = Cpu::init(0x0, &prog);
+        cpu.regs.sp = 0x2400;
+        cpu.regs.a = 0x42;
+        for _ in 0..3 {
+            cpu.cycle().unwrap();
+        }
+        assert_eq!((cpu.regs.b, cpu.regs.c), (0x42, 0x03));
+
+        for _ in 0..3 {
+            cpu.cycle().unwrap();
+        }
+        assert_eq!(cpu.flags, Flags { sign: true, zero: true, parity: true, ..Default::default() });
+        assert_eq!(cpu.regs.sp, 0x2400);
+    }
+
+    #[test]
+    fn pop_psw_carry() {
+        // Carry is bit 0 of the flags byte, bit 3 is a fixed 0 that POP PSW
+        // ignores
+        for (psw, carry) in [(0x01, true), (0x08, false), (0xFE, false), (0x09, true)] {
+            let mut cpu = Cpu::init(0x0, &[0xF1]);
+            cpu.regs.sp = 0x2400;
+            cpu.memory[0x2400] = psw;
+            cpu.cycle().unwrap();
+            assert_eq!(cpu.flags.carry, carry, "flags byte 0x{:02X}", psw);
+        }
+    }
+
+    #[test]
+    fn undocumented_opcodes() {
+        // NOP alias, JMP 0x0010 alias, at 0x0010 CALL 0x0020 alias and at
+        // 0x0020 RET alias
+        let mut prog = [0u8; 0x21];
+        prog[..4].copy_from_slice(&[0x08, 0xCB, 0x10, 0x00]);
+        prog[0x10..0x13].copy_from_slice(&[0xDD, 0x20, 0x00]);
+        prog[0x20] = 0xD9;
+
+        let mut cpu = Cpu::init(0x0, &prog);
+        cpu.regs.sp = 0x2400;
+        let cycles: Vec<u32> = (0..4).map(|_| cpu.step().unwrap()).collect();
+        assert_eq!(cycles, [4, 10, 17, 10]);
+        assert_eq!(cpu.regs.pc, 0x0013);
+        assert_eq!(cpu.regs.sp, 0x2400);
+
+        let mut cpu = Cpu::init(0x0, &prog);
+        cpu.strict = true;
+        assert_eq!(cpu.step(), Err(EmuError::UndocumentedOpcode { opcode: 0x08, addr: 0x0000 }));
+        assert_eq!((cpu.regs.pc, cpu.instructions), (0, 0));
+    }
+}
@@ -0,0 +1,1195 @@
+// Zilog Z80 mode, runs on the same Registers and Flags as the 8080 core
+//
+// The extra registers live in Z80, along with the N flag and the undocumented
+// bits 3 and 5 of F that Flags doesn't have. Opcodes are decoded from their
+// x/y/z fields and a DD or FD prefix swaps HL for IX or IY.
+
+use super::{Cpu, Variant};
+use crate::EmuError;
+
+pub(super) const CYCLES_Z80: [u8; 256] = [
+    //  0  1   2   3   4   5   6   7   8   9   A   B   C   D   E   F
+        4, 10, 7,  6,  4,  4,  7,  4,  4,  11, 7,  6,  4,  4,  7,  4,  // 0
+        8, 10, 7,  6,  4,  4,  7,  4,  12, 11, 7,  6,  4,  4,  7,  4,  // 1
+        7, 10, 16, 6,  4,  4,  7,  4,  7,  11, 16, 6,  4,  4,  7,  4,  // 2
+        7, 10, 13, 6,  11, 11, 10, 4,  7,  11, 13, 6,  4,  4,  7,  4,  // 3
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 4
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 5
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 6
+        7, 7,  7,  7,  7,  7,  4,  7,  4,  4,  4,  4,  4,  4,  7,  4,  // 7
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 8
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 9
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // A
+        4, 4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // B
+        5, 10, 10, 10, 10, 11, 7,  11, 5,  10, 10, 4,  10, 17, 7,  11, // C
+        5, 10, 10, 11, 10, 11, 7,  11, 5,  4,  10, 11, 10, 4,  7,  11, // D
+        5, 10, 10, 19, 10, 11, 7,  11, 5,  4,  10, 4,  10, 4,  7,  11, // E
+        5, 10, 10, 4,  10, 11, 7,  11, 5,  6,  10, 4,  10, 4,  7,  11  // F
+];
+
+pub const NMI_VECTOR: u16 = 0x66;
+
+// Bits of F
+const FLAG_C: u8 = 0x01;
+const FLAG_N: u8 = 0x02;
+const FLAG_PV: u8 = 0x04;
+const FLAG_X: u8 = 0x08;
+const FLAG_H: u8 = 0x10;
+const FLAG_Y: u8 = 0x20;
+const FLAG_Z: u8 = 0x40;
+const FLAG_S: u8 = 0x80;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Z80 {
+    // The alternate set swapped in by EX AF,AF' and EXX
+    pub af_alt: u16,
+    pub bc_alt: u16,
+    pub de_alt: u16,
+    pub hl_alt: u16,
+
+    pub ix: u16,
+    pub iy: u16,
+
+    // Interrupt vector base and memory refresh counter
+    pub i: u8,
+    pub r: u8,
+
+    // Interrupt mode 0, 1 or 2, Cpu::int_enabled is IFF1
+    pub im: u8,
+    pub iff2: bool,
+
+    // Flags that Flags doesn't have, N and bits 3 and 5
+    pub subtract: bool,
+    pub xy: u8,
+
+    // Latched on a falling edge of the NMI line
+    pub nmi: bool,
+
+    // Internal address latch, it leaks into the flags of BIT n,(HL)
+    memptr: u16,
+
+    // Set by EI, interrupts aren't taken until the instruction after it has
+    // run so a handler can end with EI; RETI
+    ei_delay: bool,
+}
+
+// Which register pair stands in for HL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Index {
+    Hl,
+    Ix,
+    Iy,
+}
+
+// Sign, zero and the undocumented bits from a result
+fn szxy(r: u8) -> u8 {
+    r & (FLAG_S | FLAG_X | FLAG_Y) | if r == 0 { FLAG_Z } else { 0 }
+}
+
+fn parity(r: u8) -> u8 {
+    if r.count_ones() & 1 == 0 { FLAG_PV } else { 0 }
+}
+
+// Instructions with an (HL) operand, which DD and FD turn into (IX+d)
+fn uses_memory(op: u8) -> bool {
+    let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+    match x {
+        0 => y == 6 && (4..=6).contains(&z),
+        1 => (y == 6 || z == 6) && op != 0x76,
+        2 => z == 6,
+        _ => false,
+    }
+}
+
+impl Cpu {
+    // Latches a non-maskable interrupt, taken before the next instruction
+
+    pub fn nmi(&mut self) {
+        self.z80.nmi = true;
+    }
+
+    // F as the Z80 lays it out
+
+    pub fn z80_f(&self) -> u8 {
+        (self.flags.sign as u8) << 7
+            | (self.flags.zero as u8) << 6
+            | self.z80.xy & (FLAG_X | FLAG_Y)
+            | (self.flags.aux_carry as u8) << 4
+            | (self.flags.parity as u8) << 2
+            | (self.z80.subtract as u8) << 1
+            | self.flags.carry as u8
+    }
+
+    pub fn set_z80_f(&mut self, f: u8) {
+        self.flags.sign = f & FLAG_S != 0;
+        self.flags.zero = f & FLAG_Z != 0;
+        self.flags.aux_carry = f & FLAG_H != 0;
+        self.flags.parity = f & FLAG_PV != 0;
+        self.flags.carry = f & FLAG_C != 0;
+        self.z80.subtract = f & FLAG_N != 0;
+        self.z80.xy = f & (FLAG_X | FLAG_Y);
+    }
+
+    pub(super) fn step_z80(&mut self) -> Result<u32, EmuError> {
+        #[cfg(feature = "cpm")]
+        if self.cpm.active && self.cpm_trap() {
+            return Ok(10);
+        }
+
+        if self.z80.nmi {
+            self.z80.nmi = false;
+            self.z80.iff2 = self.int_enabled;
+            self.int_enabled = false;
+            self.hlted = false;
+            self.refresh();
+            self.push_word(self.regs.pc)?;
+            self.regs.pc = NMI_VECTOR;
+            return Ok(11);
+        }
+
+        // A halted Z80 keeps running NOPs, which still refresh memory
+        if self.hlted {
+            self.refresh();
+            return Ok(4);
+        }
+
+        self.instructions += 1;
+        self.z80.ei_delay = false;
+        let mut op = self.fetch_opcode();
+        let mut idx = Index::Hl;
+        let mut cycles = 0;
+
+        if op == 0xDD || op == 0xFD {
+            // A prefix followed by another prefix does nothing
+            let next = self.read_byte(self.regs.pc);
+            if next == 0xDD || next == 0xFD {
+                return Ok(4);
+            }
+            idx = if op == 0xDD { Index::Ix } else { Index::Iy };
+            op = self.fetch_opcode();
+            cycles = 4;
+        }
+
+        cycles += match op {
+            0xCB => self.z80_cb(idx),
+            // ED ignores an index prefix
+            0xED => self.z80_ed()?,
+            _ => self.z80_op(op, idx)?,
+        };
+        Ok(cycles)
+    }
+
+    // Takes a maskable interrupt, n is the RST the device puts on the bus
+
+    pub(super) fn interrupt_z80(&mut self, n: u16) -> Result<(), EmuError> {
+        if !self.int_enabled || self.z80.ei_delay {
+            return Ok(());
+        }
+
+        self.int_enabled = false;
+        self.z80.iff2 = false;
+        self.hlted = false;
+        self.refresh();
+        self.push_word(self.regs.pc)?;
+
+        self.regs.pc = match self.z80.im {
+            0 => n * 8,
+            1 => 0x38,
+            // The data bus byte picks an entry in the table at I * 256
+            _ => {
+                let vector = (self.z80.i as u16) << 8 | (0xC7 | (n as u8) << 3) as u16;
+                self.read_word(vector)
+            }
+        };
+        self.z80.memptr = self.regs.pc;
+        Ok(())
+    }
+
+    /*
+    Memory and register access
+    */
+
+    fn read_byte(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write_byte(&mut self, addr: u16, val: u8) {
+        self.memory[addr as usize] = val;
+    }
+
+    fn read_word(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.read_byte(addr), self.read_byte(addr.wrapping_add(1))])
+    }
+
+    fn write_word(&mut self, addr: u16, val: u16) {
+        let [lo, hi] = val.to_le_bytes();
+        self.write_byte(addr, lo);
+        self.write_byte(addr.wrapping_add(1), hi);
+    }
+
+    fn refresh(&mut self) {
+        self.z80.r = (self.z80.r & 0x80) | (self.z80.r.wrapping_add(1) & 0x7F);
+    }
+
+    fn fetch_opcode(&mut self) -> u8 {
+        self.refresh();
+        self.fetch()
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let byte = self.read_byte(self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        byte
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let lo = self.fetch();
+        let hi = self.fetch();
+        u16::from_le_bytes([lo, hi])
+    }
+
+    fn push_word(&mut self, val: u16) -> Result<(), EmuError> {
+        self.check_stack()?;
+        self.regs.sp = self.regs.sp.wrapping_sub(2);
+        self.write_word(self.regs.sp, val);
+        Ok(())
+    }
+
+    fn pop_word(&mut self) -> u16 {
+        let val = self.read_word(self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(2);
+        val
+    }
+
+    fn bc(&self) -> u16 {
+        self.cmb_be(self.regs.b, self.regs.c)
+    }
+
+    fn de(&self) -> u16 {
+        self.cmb_be(self.regs.d, self.regs.e)
+    }
+
+    fn hl(&self) -> u16 {
+        self.cmb_be(self.regs.h, self.regs.l)
+    }
+
+    fn af(&self) -> u16 {
+        (self.regs.a as u16) << 8 | self.z80_f() as u16
+    }
+
+    fn set_af(&mut self, val: u16) {
+        self.regs.a = (val >> 8) as u8;
+        self.set_z80_f(val as u8);
+    }
+
+    fn index(&self, idx: Index) -> u16 {
+        match idx {
+            Index::Hl => self.hl(),
+            Index::Ix => self.z80.ix,
+            Index::Iy => self.z80.iy,
+        }
+    }
+
+    fn set_index(&mut self, idx: Index, val: u16) {
+        match idx {
+            Index::Hl => self.regs.set_hl(val),
+            Index::Ix => self.z80.ix = val,
+            Index::Iy => self.z80.iy = val,
+        }
+    }
+
+    // BC, DE, HL and SP
+    fn rp(&self, p: u8, idx: Index) -> u16 {
+        match p {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.index(idx),
+            _ => self.regs.sp,
+        }
+    }
+
+    fn set_rp(&mut self, p: u8, idx: Index, val: u16) {
+        match p {
+            0 => self.regs.set_bc(val),
+            1 => self.regs.set_de(val),
+            2 => self.set_index(idx, val),
+            _ => self.regs.sp = val,
+        }
+    }
+
+    // BC, DE, HL and AF for PUSH and POP
+    fn rp2(&self, p: u8, idx: Index) -> u16 {
+        if p == 3 { self.af() } else { self.rp(p, idx) }
+    }
+
+    fn set_rp2(&mut self, p: u8, idx: Index, val: u16) {
+        if p == 3 { self.set_af(val) } else { self.set_rp(p, idx, val) }
+    }
+
+    // B, C, D, E, H, L, (HL) and A, H and L are halves of the index register
+    // and (HL) is whatever addr points at
+
+    fn get_r(&self, r: u8, idx: Index, addr: u16) -> u8 {
+        match r {
+            0 => self.regs.b,
+            1 => self.regs.c,
+            2 => self.regs.d,
+            3 => self.regs.e,
+            4 => (self.index(idx) >> 8) as u8,
+            5 => self.index(idx) as u8,
+            6 => self.read_byte(addr),
+            _ => self.regs.a,
+        }
+    }
+
+    fn set_r(&mut self, r: u8, idx: Index, addr: u16, val: u8) {
+        match r {
+            0 => self.regs.b = val,
+            1 => self.regs.c = val,
+            2 => self.regs.d = val,
+            3 => self.regs.e = val,
+            4 => self.set_index(idx, (val as u16) << 8 | self.index(idx) & 0xFF),
+            5 => self.set_index(idx, self.index(idx) & 0xFF00 | val as u16),
+            6 => self.write_byte(addr, val),
+            _ => self.regs.a = val,
+        }
+    }
+
+    // NZ, Z, NC, C, PO, PE, P and M
+    fn condition(&self, cc: u8) -> bool {
+        match cc {
+            0 => !self.flags.zero,
+            1 => self.flags.zero,
+            2 => !self.flags.carry,
+            3 => self.flags.carry,
+            4 => !self.flags.parity,
+            5 => self.flags.parity,
+            6 => !self.flags.sign,
+            _ => self.flags.sign,
+        }
+    }
+
+    fn jr(&mut self, d: i8) {
+        self.regs.pc = self.regs.pc.wrapping_add(d as u16);
+        self.z80.memptr = self.regs.pc;
+    }
+
+    fn z80_ret(&mut self) {
+        self.regs.pc = self.pop_word();
+        self.z80.memptr = self.regs.pc;
+    }
+
+    /*
+    Instructions without a CB or ED prefix
+    */
+
+    fn z80_op(&mut self, op: u8, idx: Index) -> Result<u32, EmuError> {
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+        let mut cycles = CYCLES_Z80[op as usize] as u32;
+
+        // With (IX+d) as an operand H and L mean themselves again
+        let indexed = idx != Index::Hl && uses_memory(op);
+        let addr = if indexed {
+            cycles += if op == 0x36 { 5 } else { 8 };
+            let d = self.fetch() as i8;
+            self.z80.memptr = self.index(idx).wrapping_add(d as u16);
+            self.z80.memptr
+        } else {
+            self.hl()
+        };
+        let regs = if indexed { Index::Hl } else { idx };
+
+        match (x, z) {
+            (0, 0) => match y {
+                0 => {}
+                // EX AF,AF'
+                1 => {
+                    let af = self.af();
+                    self.set_af(self.z80.af_alt);
+                    self.z80.af_alt = af;
+                }
+                // DJNZ
+                2 => {
+                    let d = self.fetch() as i8;
+                    self.regs.b = self.regs.b.wrapping_sub(1);
+                    if self.regs.b != 0 {
+                        self.jr(d);
+                        cycles += 5;
+                    }
+                }
+                3 => {
+                    let d = self.fetch() as i8;
+                    self.jr(d);
+                }
+                _ => {
+                    let d = self.fetch() as i8;
+                    if self.condition(y - 4) {
+                        self.jr(d);
+                        cycles += 5;
+                    }
+                }
+            },
+
+            (0, 1) if q == 0 => {
+                let nn = self.fetch_word();
+                self.set_rp(p, idx, nn);
+            }
+            (0, 1) => {
+                let res = self.add16(self.index(idx), self.rp(p, idx));
+                self.set_index(idx, res);
+            }
+
+            (0, 2) => match (q, p) {
+                (0, 0) => self.store_a(self.bc()),
+                (0, 1) => self.store_a(self.de()),
+                (0, 2) => {
+                    let nn = self.fetch_word();
+                    self.write_word(nn, self.index(idx));
+                    self.z80.memptr = nn.wrapping_add(1);
+                }
+                (0, _) => {
+                    let nn = self.fetch_word();
+                    self.store_a(nn);
+                }
+                (_, 0) => self.load_a(self.bc()),
+                (_, 1) => self.load_a(self.de()),
+                (_, 2) => {
+                    let nn = self.fetch_word();
+                    let val = self.read_word(nn);
+                    self.set_index(idx, val);
+                    self.z80.memptr = nn.wrapping_add(1);
+                }
+                _ => {
+                    let nn = self.fetch_word();
+                    self.load_a(nn);
+                }
+            },
+
+            // INC and DEC of a pair
+            (0, 3) => {
+                let rr = self.rp(p, idx);
+                let val = if q == 0 { rr.wrapping_add(1) } else { rr.wrapping_sub(1) };
+                self.set_rp(p, idx, val);
+            }
+
+            (0, 4) => {
+                let res = self.inc8(self.get_r(y, regs, addr));
+                self.set_r(y, regs, addr, res);
+            }
+            (0, 5) => {
+                let res = self.dec8(self.get_r(y, regs, addr));
+                self.set_r(y, regs, addr, res);
+            }
+            (0, 6) => {
+                let n = self.fetch();
+                self.set_r(y, regs, addr, n);
+            }
+            (0, _) => self.accumulator_op(y),
+
+            (1, _) if op == 0x76 => self.hlted = true,
+            (1, _) => {
+                let val = self.get_r(z, regs, addr);
+                self.set_r(y, regs, addr, val);
+            }
+
+            (2, _) => self.alu8(y, self.get_r(z, regs, addr)),
+
+            (3, 0) => {
+                if self.condition(y) {
+                    self.z80_ret();
+                    cycles += 6;
+                }
+            }
+
+            (3, 1) => match (q, p) {
+                (0, _) => {
+                    let val = self.pop_word();
+                    self.set_rp2(p, idx, val);
+                }
+                (_, 0) => self.z80_ret(),
+                // EXX
+                (_, 1) => {
+                    let (bc, de, hl) = (self.bc(), self.de(), self.hl());
+                    self.regs.set_bc(self.z80.bc_alt);
+                    self.regs.set_de(self.z80.de_alt);
+                    self.regs.set_hl(self.z80.hl_alt);
+                    self.z80.bc_alt = bc;
+                    self.z80.de_alt = de;
+                    self.z80.hl_alt = hl;
+                }
+                (_, 2) => self.regs.pc = self.index(idx),
+                _ => self.regs.sp = self.index(idx),
+            },
+
+            (3, 2) => {
+                let nn = self.fetch_word();
+                self.z80.memptr = nn;
+                if self.condition(y) {
+                    self.regs.pc = nn;
+                }
+            }
+
+            (3, 3) => match y {
+                0 => {
+                    let nn = self.fetch_word();
+                    self.z80.memptr = nn;
+                    self.regs.pc = nn;
+                }
+                // OUT (n),A
+                2 => {
+                    let n = self.fetch();
+                    self.io.cpu_write(n, self.regs.a)?;
+                    self.z80.memptr = (self.regs.a as u16) << 8 | n.wrapping_add(1) as u16;
+                }
+                // IN A,(n)
+                3 => {
+                    let n = self.fetch();
+                    self.z80.memptr = ((self.regs.a as u16) << 8 | n as u16).wrapping_add(1);
+                    self.regs.a = self.io.cpu_read(n)?;
+                }
+                // EX (SP),HL
+                4 => {
+                    let sp = self.regs.sp;
+                    let val = self.read_word(sp);
+                    self.write_word(sp, self.index(idx));
+                    self.set_index(idx, val);
+                    self.z80.memptr = val;
+                }
+                // EX DE,HL, never IX or IY
+                5 => {
+                    let de = self.de();
+                    self.regs.set_de(self.hl());
+                    self.regs.set_hl(de);
+                }
+                6 => {
+                    self.int_enabled = false;
+                    self.z80.iff2 = false;
+                }
+                _ => {
+                    self.int_enabled = true;
+                    self.z80.iff2 = true;
+                    self.z80.ei_delay = true;
+                }
+            },
+
+            (3, 4) => {
+                let nn = self.fetch_word();
+                self.z80.memptr = nn;
+                if self.condition(y) {
+                    self.push_word(self.regs.pc)?;
+                    self.regs.pc = nn;
+                    cycles += 7;
+                }
+            }
+
+            (3, 5) if q == 0 => self.push_word(self.rp2(p, idx))?,
+            // CALL nn, the other opcodes in this column are prefixes
+            (3, 5) => {
+                let nn = self.fetch_word();
+                self.z80.memptr = nn;
+                self.push_word(self.regs.pc)?;
+                self.regs.pc = nn;
+            }
+
+            (3, 6) => {
+                let n = self.fetch();
+                self.alu8(y, n);
+            }
+
+            // RST
+            _ => {
+                self.push_word(self.regs.pc)?;
+                self.regs.pc = y as u16 * 8;
+                self.z80.memptr = self.regs.pc;
+            }
+        }
+
+        Ok(cycles)
+    }
+
+    fn load_a(&mut self, addr: u16) {
+        self.regs.a = self.read_byte(addr);
+        self.z80.memptr = addr.wrapping_add(1);
+    }
+
+    fn store_a(&mut self, addr: u16) {
+        self.write_byte(addr, self.regs.a);
+        self.z80.memptr = (self.regs.a as u16) << 8 | addr.wrapping_add(1) & 0xFF;
+    }
+
+    /*
+    CB prefix, rotates, shifts and bit operations
+    */
+
+    fn z80_cb(&mut self, idx: Index) -> u32 {
+        // DD CB d op, the displacement comes before the opcode
+        let (addr, op) = if idx == Index::Hl {
+            (self.hl(), self.fetch_opcode())
+        } else {
+            let d = self.fetch() as i8;
+            self.z80.memptr = self.index(idx).wrapping_add(d as u16);
+            (self.z80.memptr, self.fetch())
+        };
+
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let memory = idx != Index::Hl || z == 6;
+        let val = if memory { self.read_byte(addr) } else { self.get_r(z, Index::Hl, addr) };
+
+        let res = match x {
+            0 => Some(self.rotate(y, val)),
+            1 => {
+                let xy = if memory { (self.z80.memptr >> 8) as u8 } else { val };
+                self.bit(y, val, xy);
+                None
+            }
+            2 => Some(val & !(1 << y)),
+            _ => Some(val | 1 << y),
+        };
+
+        // The indexed forms also copy the result into a register
+        if let Some(res) = res {
+            if memory {
+                self.write_byte(addr, res);
+            }
+            if z != 6 {
+                self.set_r(z, Index::Hl, addr, res);
+            }
+        }
+
+        match (memory, x == 1, idx) {
+            (false, _, _) => 8,
+            (true, true, Index::Hl) => 12,
+            (true, false, Index::Hl) => 15,
+            (true, true, _) => 16,
+            _ => 19,
+        }
+    }
+
+    // RLC, RRC, RL, RR, SLA, SRA, SLL and SRL
+
+    fn rotate(&mut self, op: u8, val: u8) -> u8 {
+        let carry = self.flags.carry as u8;
+        let (res, out) = match op {
+            0 => (val.rotate_left(1), val >> 7),
+            1 => (val.rotate_right(1), val & 1),
+            2 => (val << 1 | carry, val >> 7),
+            3 => (val >> 1 | carry << 7, val & 1),
+            4 => (val << 1, val >> 7),
+            5 => (val >> 1 | val & 0x80, val & 1),
+            6 => (val << 1 | 1, val >> 7),
+            _ => (val >> 1, val & 1),
+        };
+        self.set_z80_f(szxy(res) | parity(res) | out);
+        res
+    }
+
+    // BIT takes bits 3 and 5 from xy, which depends on the addressing mode
+
+    fn bit(&mut self, n: u8, val: u8, xy: u8) {
+        let set = val & (1 << n);
+        let mut f = self.z80_f() & FLAG_C | FLAG_H | xy & (FLAG_X | FLAG_Y) | set & FLAG_S;
+        if set == 0 {
+            f |= FLAG_Z | FLAG_PV;
+        }
+        self.set_z80_f(f);
+    }
+
+    /*
+    ED prefix
+    */
+
+    fn z80_ed(&mut self) -> Result<u32, EmuError> {
+        let op = self.fetch_opcode();
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+
+        let cycles = match (x, z) {
+            // IN r,(C), IN (C) only sets the flags
+            (1, 0) => {
+                let val = self.io.cpu_read(self.regs.c)?;
+                self.z80.memptr = self.bc().wrapping_add(1);
+                self.set_z80_f(self.z80_f() & FLAG_C | szxy(val) | parity(val));
+                if y != 6 {
+                    self.set_r(y, Index::Hl, 0, val);
+                }
+                12
+            }
+            // OUT (C),r, OUT (C),0 in place of (HL)
+            (1, 1) => {
+                let val = if y == 6 { 0 } else { self.get_r(y, Index::Hl, 0) };
+                self.io.cpu_write(self.regs.c, val)?;
+                self.z80.memptr = self.bc().wrapping_add(1);
+                12
+            }
+            (1, 2) => {
+                let res = if q == 0 {
+                    self.sbc16(self.hl(), self.rp(p, Index::Hl))
+                } else {
+                    self.adc16(self.hl(), self.rp(p, Index::Hl))
+                };
+                self.regs.set_hl(res);
+                15
+            }
+            (1, 3) => {
+                let nn = self.fetch_word();
+                if q == 0 {
+                    self.write_word(nn, self.rp(p, Index::Hl));
+                } else {
+                    let val = self.read_word(nn);
+                    self.set_rp(p, Index::Hl, val);
+                }
+                self.z80.memptr = nn.wrapping_add(1);
+                20
+            }
+            // NEG
+            (1, 4) => {
+                let a = self.regs.a;
+                self.regs.a = 0;
+                self.alu8(2, a);
+                8
+            }
+            // RETN and RETI
+            (1, 5) => {
+                self.int_enabled = self.z80.iff2;
+                self.z80_ret();
+                14
+            }
+            (1, 6) => {
+                self.z80.im = [0, 0, 1, 2][(y & 3) as usize];
+                8
+            }
+            (1, 7) => match y {
+                0 => {
+                    self.z80.i = self.regs.a;
+                    9
+                }
+                1 => {
+                    self.z80.r = self.regs.a;
+                    9
+                }
+                // LD A,I and LD A,R
+                2 | 3 => {
+                    let val = if y == 2 { self.z80.i } else { self.z80.r };
+                    self.regs.a = val;
+                    let iff2 = if self.z80.iff2 { FLAG_PV } else { 0 };
+                    self.set_z80_f(self.z80_f() & FLAG_C | szxy(val) | iff2);
+                    9
+                }
+                4 | 5 => {
+                    self.rotate_digit(y == 5);
+                    18
+                }
+                _ => 8,
+            },
+            (2, 0..=3) if y >= 4 => self.block(y, z)?,
+            // Everything else is a two byte NOP
+            _ => 8,
+        };
+
+        Ok(cycles)
+    }
+
+    // RRD and RLD rotate a BCD digit between A and (HL)
+
+    fn rotate_digit(&mut self, left: bool) {
+        let hl = self.hl();
+        let (a, val) = (self.regs.a, self.read_byte(hl));
+
+        let (a, val) = if left {
+            (a & 0xF0 | val >> 4, val << 4 | a & 0x0F)
+        } else {
+            (a & 0xF0 | val & 0x0F, val >> 4 | a << 4)
+        };
+
+        self.regs.a = a;
+        self.write_byte(hl, val);
+        self.z80.memptr = hl.wrapping_add(1);
+        self.set_z80_f(self.z80_f() & FLAG_C | szxy(a) | parity(a));
+    }
+
+    // LDI, CPI, INI and OUTI, y picks the direction and whether it repeats
+
+    fn block(&mut self, y: u8, z: u8) -> Result<u32, EmuError> {
+        let step: u16 = if y & 1 == 0 { 1 } else { 0xFFFF };
+        let hl = self.hl();
+
+        let again = match z {
+            // LDI, bits 3 and 5 come from the byte plus A
+            0 => {
+                let val = self.read_byte(hl);
+                let de = self.de();
+                self.write_byte(de, val);
+                self.regs.set_hl(hl.wrapping_add(step));
+                self.regs.set_de(de.wrapping_add(step));
+                let bc = self.bc().wrapping_sub(1);
+                self.regs.set_bc(bc);
+
+                let n = val.wrapping_add(self.regs.a);
+                let pv = if bc != 0 { FLAG_PV } else { 0 };
+                self.set_z80_f(self.z80_f() & (FLAG_S | FLAG_Z | FLAG_C) | n & FLAG_X | (n << 4) & FLAG_Y | pv);
+                bc != 0
+            }
+            // CPI, like CP but carry is left alone
+            1 => {
+                let val = self.read_byte(hl);
+                let a = self.regs.a;
+                let res = a.wrapping_sub(val);
+                let h = (a ^ val ^ res) & FLAG_H;
+                self.regs.set_hl(hl.wrapping_add(step));
+                let bc = self.bc().wrapping_sub(1);
+                self.regs.set_bc(bc);
+                self.z80.memptr = self.z80.memptr.wrapping_add(step);
+
+                let n = res.wrapping_sub(h >> 4);
+                let pv = if bc != 0 { FLAG_PV } else { 0 };
+                let f = self.z80_f() & FLAG_C | FLAG_N | h | szxy(res) & (FLAG_S | FLAG_Z);
+                self.set_z80_f(f | n & FLAG_X | (n << 4) & FLAG_Y | pv);
+                bc != 0 && res != 0
+            }
+            2 => {
+                let val = self.io.cpu_read(self.regs.c)?;
+                self.z80.memptr = self.bc().wrapping_add(step);
+                self.write_byte(hl, val);
+                self.regs.set_hl(hl.wrapping_add(step));
+                self.regs.b = self.regs.b.wrapping_sub(1);
+                self.block_io_flags(val, val as u16 + self.regs.c.wrapping_add(step as u8) as u16);
+                self.regs.b != 0
+            }
+            _ => {
+                let val = self.read_byte(hl);
+                self.regs.b = self.regs.b.wrapping_sub(1);
+                self.io.cpu_write(self.regs.c, val)?;
+                self.regs.set_hl(hl.wrapping_add(step));
+                self.z80.memptr = self.bc().wrapping_add(step);
+                self.block_io_flags(val, val as u16 + self.regs.l as u16);
+                self.regs.b != 0
+            }
+        };
+
+        // The repeating forms run again from the top until they're done
+        if y >= 6 && again {
+            self.regs.pc = self.regs.pc.wrapping_sub(2);
+            if z < 2 {
+                self.z80.memptr = self.regs.pc.wrapping_add(1);
+            }
+            return Ok(21);
+        }
+        Ok(16)
+    }
+
+    fn block_io_flags(&mut self, val: u8, k: u16) {
+        let b = self.regs.b;
+        let mut f = szxy(b) | parity((k as u8 & 7) ^ b) | (val >> 6) & FLAG_N;
+        if k > 0xFF {
+            f |= FLAG_H | FLAG_C;
+        }
+        self.set_z80_f(f);
+    }
+
+    /*
+    Arithmetic
+    */
+
+    // ADD, ADC, SUB, SBC, AND, XOR, OR and CP
+
+    fn alu8(&mut self, op: u8, val: u8) {
+        let a = self.regs.a;
+        let carry = self.flags.carry as u16;
+
+        let f = match op {
+            0 | 1 => {
+                let res = a as u16 + val as u16 + if op == 1 { carry } else { 0 };
+                let r = res as u8;
+                self.regs.a = r;
+                szxy(r) | (a ^ val ^ r) & FLAG_H | ((a ^ r) & (val ^ r) & 0x80) >> 5 | (res >> 8) as u8
+            }
+            // CP takes bits 3 and 5 from the operand
+            2 | 3 | 7 => {
+                let res = (a as u16).wrapping_sub(val as u16).wrapping_sub(if op == 3 { carry } else { 0 });
+                let r = res as u8;
+                let xy = if op == 7 { val } else { r };
+                if op != 7 {
+                    self.regs.a = r;
+                }
+                szxy(r) & (FLAG_S | FLAG_Z)
+                    | xy & (FLAG_X | FLAG_Y)
+                    | (a ^ val ^ r) & FLAG_H
+                    | ((a ^ val) & (a ^ r) & 0x80) >> 5
+                    | FLAG_N
+                    | (res >> 8) as u8 & FLAG_C
+            }
+            4 => {
+                self.regs.a = a & val;
+                szxy(self.regs.a) | parity(self.regs.a) | FLAG_H
+            }
+            5 => {
+                self.regs.a = a ^ val;
+                szxy(self.regs.a) | parity(self.regs.a)
+            }
+            _ => {
+                self.regs.a = a | val;
+                szxy(self.regs.a) | parity(self.regs.a)
+            }
+        };
+
+        self.set_z80_f(f);
+    }
+
+    fn inc8(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_add(1);
+        let mut f = self.z80_f() & FLAG_C | szxy(res);
+        if val & 0x0F == 0x0F {
+            f |= FLAG_H;
+        }
+        if val == 0x7F {
+            f |= FLAG_PV;
+        }
+        self.set_z80_f(f);
+        res
+    }
+
+    fn dec8(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_sub(1);
+        let mut f = self.z80_f() & FLAG_C | szxy(res) | FLAG_N;
+        if val & 0x0F == 0 {
+            f |= FLAG_H;
+        }
+        if val == 0x80 {
+            f |= FLAG_PV;
+        }
+        self.set_z80_f(f);
+        res
+    }
+
+    // 16 bit ADD leaves S, Z and P/V alone, bits 3 and 5 come from the high byte
+
+    fn add16(&mut self, a: u16, b: u16) -> u16 {
+        let res = a as u32 + b as u32;
+        let r = res as u16;
+        let f = self.z80_f() & (FLAG_S | FLAG_Z | FLAG_PV)
+            | (r >> 8) as u8 & (FLAG_X | FLAG_Y)
+            | ((a ^ b ^ r) >> 8) as u8 & FLAG_H
+            | (res >> 16) as u8;
+        self.set_z80_f(f);
+        self.z80.memptr = a.wrapping_add(1);
+        r
+    }
+
+    fn adc16(&mut self, a: u16, b: u16) -> u16 {
+        let res = a as u32 + b as u32 + self.flags.carry as u32;
+        let r = res as u16;
+        self.set_z80_f(
+            Self::flags16(r)
+                | ((a ^ b ^ r) >> 8) as u8 & FLAG_H
+                | (((a ^ r) & (b ^ r) & 0x8000) >> 13) as u8
+                | (res >> 16) as u8,
+        );
+        self.z80.memptr = a.wrapping_add(1);
+        r
+    }
+
+    fn sbc16(&mut self, a: u16, b: u16) -> u16 {
+        let res = (a as u32).wrapping_sub(b as u32).wrapping_sub(self.flags.carry as u32);
+        let r = res as u16;
+        self.set_z80_f(
+            Self::flags16(r)
+                | ((a ^ b ^ r) >> 8) as u8 & FLAG_H
+                | (((a ^ b) & (a ^ r) & 0x8000) >> 13) as u8
+                | FLAG_N
+                | (res >> 16) as u8 & FLAG_C,
+        );
+        self.z80.memptr = a.wrapping_add(1);
+        r
+    }
+
+    fn flags16(r: u16) -> u8 {
+        (r >> 8) as u8 & (FLAG_S | FLAG_X | FLAG_Y) | if r == 0 { FLAG_Z } else { 0 }
+    }
+
+    // RLCA, RRCA, RLA, RRA, DAA, CPL, SCF and CCF
+
+    fn accumulator_op(&mut self, y: u8) {
+        let a = self.regs.a;
+        let f = self.z80_f();
+        let carry = f & FLAG_C;
+        let kept = f & (FLAG_S | FLAG_Z | FLAG_PV);
+
+        let (a, f) = match y {
+            0 => (a.rotate_left(1), kept | a >> 7),
+            1 => (a.rotate_right(1), kept | a & 1),
+            2 => (a << 1 | carry, kept | a >> 7),
+            3 => (a >> 1 | carry << 7, kept | a & 1),
+            4 => return self.z80_daa(),
+            5 => (!a, f & (FLAG_S | FLAG_Z | FLAG_PV | FLAG_C) | FLAG_H | FLAG_N),
+            6 => (a, kept | FLAG_C),
+            _ => (a, kept | carry << 4 | carry ^ FLAG_C),
+        };
+
+        self.regs.a = a;
+        self.set_z80_f(f & !(FLAG_X | FLAG_Y) | a & (FLAG_X | FLAG_Y));
+    }
+
+    fn z80_daa(&mut self) {
+        let a = self.regs.a;
+        let f = self.z80_f();
+        let mut diff = 0;
+        let mut carry = f & FLAG_C;
+
+        if f & FLAG_H != 0 || a & 0x0F > 9 {
+            diff = 0x06;
+        }
+        if carry != 0 || a > 0x99 {
+            diff |= 0x60;
+            carry = FLAG_C;
+        }
+
+        let (res, half) = if f & FLAG_N != 0 {
+            (a.wrapping_sub(diff), f & FLAG_H != 0 && a & 0x0F < 6)
+        } else {
+            (a.wrapping_add(diff), a & 0x0F > 9)
+        };
+
+        self.regs.a = res;
+        let h = if half { FLAG_H } else { 0 };
+        self.set_z80_f(szxy(res) | parity(res) | f & FLAG_N | h | carry);
+    }
+}
+
+impl Variant {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "8080" | "i8080" => Ok(Variant::I8080),
+            "8085" | "i8085" => Ok(Variant::I8085),
+            "z80" => Ok(Variant::Z80),
+            _ => Err(format!("unknown cpu {}, expected 8080, 8085 or z80", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod z80_test {
+    use super::*;
+
+    fn cpu(prog: &[u8]) -> Cpu {
+        let mut cpu = Cpu::init(0, prog);
+        cpu.variant = Variant::Z80;
+        cpu.regs.sp = 0x2400;
+        cpu
+    }
+
+    fn run(cpu: &mut Cpu, steps: usize) -> u32 {
+        (0..steps).map(|_| cpu.step().unwrap()).sum()
+    }
+
+    #[test]
+    fn index_registers_and_exchanges() {
+        // LD IX,0x1000; LD (IX+5),0x42; LD B,(IX+5); INC IXH; EXX; EX AF,AF'
+        let mut cpu = cpu(&[
+            0xDD, 0x21, 0x00, 0x10, 0xDD, 0x36, 0x05, 0x42, 0xDD, 0x46, 0x05, 0xDD, 0x24, 0xD9, 0x08,
+        ]);
+        cpu.regs.a = 0x99;
+        assert_eq!(run(&mut cpu, 4), 14 + 19 + 19 + 8);
+        assert_eq!(cpu.memory[0x1005], 0x42);
+        assert_eq!(cpu.regs.b, 0x42);
+        assert_eq!(cpu.z80.ix, 0x1100);
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.regs.b, 0);
+        assert_eq!(cpu.z80.bc_alt, 0x4200);
+        assert_eq!(cpu.regs.a, 0);
+        assert_eq!(cpu.z80.af_alt >> 8, 0x99);
+    }
+
+    #[test]
+    fn block_instructions() {
+        // LD HL,0x100; LD DE,0x200; LD BC,3; LDIR; LD HL,0x200; LD BC,3; LD A,0xBB; CPIR
+        let mut prog = vec![
+            0x21, 0x00, 0x01, 0x11, 0x00, 0x02, 0x01, 0x03, 0x00, 0xED, 0xB0,
+            0x21, 0x00, 0x02, 0x01, 0x03, 0x00, 0x3E, 0xBB, 0xED, 0xB1,
+        ];
+        prog.resize(0x100, 0);
+        prog.extend([0xAA, 0xBB, 0xCC]);
+
+        let mut cpu = cpu(&prog);
+        assert_eq!(run(&mut cpu, 6), 10 * 3 + 21 * 2 + 16);
+        assert_eq!(&cpu.memory[0x200..0x203], &[0xAA, 0xBB, 0xCC]);
+        assert_eq!(cpu.bc(), 0);
+        assert!(!cpu.flags.parity);
+
+        // CPIR stops on the match with BC left at 1
+        run(&mut cpu, 5);
+        assert_eq!(cpu.hl(), 0x202);
+        assert_eq!(cpu.bc(), 1);
+        assert!(cpu.flags.zero && cpu.flags.parity);
+    }
+
+    #[test]
+    fn flags_and_undocumented_bits() {
+        // LD A,0x7F; ADD A,1; CP 0x28, which overflows; BIT 7,A; DAA after 0x15 + 0x27
+        let mut cpu = cpu(&[0x3E, 0x7F, 0xC6, 0x01, 0xFE, 0x28, 0xCB, 0x7F, 0x3E, 0x15, 0xC6, 0x27, 0x27]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.z80_f(), FLAG_S | FLAG_H | FLAG_PV);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.z80_f(), FLAG_Y | FLAG_X | FLAG_H | FLAG_PV | FLAG_N);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.z80_f(), FLAG_S | FLAG_H);
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.regs.a, 0x42);
+        assert!(!cpu.flags.carry && !cpu.z80.subtract);
+    }
+
+    #[test]
+    fn ei_waits_an_instruction() {
+        // EI; EI; NOP; NOP in mode 1, each EI holds interrupts off until the
+        // instruction after it is done
+        let mut cpu = cpu(&[0xFB, 0xFB, 0x00, 0x00]);
+        cpu.z80.im = 1;
+        for pc in 1..=2 {
+            run(&mut cpu, 1);
+            cpu.interrupt(7).unwrap();
+            assert_eq!(cpu.regs.pc, pc);
+            assert!(cpu.int_enabled);
+        }
+
+        run(&mut cpu, 1);
+        cpu.interrupt(7).unwrap();
+        assert_eq!(cpu.regs.pc, 0x38);
+        assert_eq!(cpu.read_word(0x23FE), 3);
+    }
+
+    #[test]
+    fn interrupt_modes_and_nmi() {
+        // EI; IM 2; HALT with the mode 2 table at 0x3000
+        let mut cpu = cpu(&[0xFB, 0xED, 0x5E, 0x76]);
+        cpu.z80.i = 0x30;
+        cpu.memory[0x30D7..0x30D9].copy_from_slice(&[0x34, 0x12]);
+        run(&mut cpu, 3);
+        assert!(cpu.hlted);
+
+        // RST 2 puts 0xD7 on the bus
+        cpu.interrupt(2).unwrap();
+        assert_eq!(cpu.regs.pc, 0x1234);
+        assert!(!cpu.hlted && !cpu.int_enabled);
+
+        cpu.int_enabled = true;
+        cpu.z80.im = 1;
+        cpu.interrupt(2).unwrap();
+        assert_eq!(cpu.regs.pc, 0x38);
+
+        // NMI ignores the disabled interrupts and RETN brings IFF1 back
+        cpu.int_enabled = true;
+        cpu.z80.iff2 = true;
+        cpu.memory[NMI_VECTOR as usize..NMI_VECTOR as usize + 2].copy_from_slice(&[0xED, 0x45]);
+        cpu.nmi();
+        assert_eq!(cpu.step().unwrap(), 11);
+        assert_eq!(cpu.regs.pc, NMI_VECTOR);
+        assert!(!cpu.int_enabled);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.pc, 0x38);
+        assert!(cpu.int_enabled);
+    }
+}
@@ -0,0 +1,333 @@
+// Breakpoints and watchpoints shared by the REPL and the GDB stub
+//
+// Watchpoints work by decoding the instruction about to run and working out
+// which addresses and ports it touches, so the cpu itself needs no hooks.
+
+pub mod repl;
+
+use crate::cpu::disasm::instruction_len;
+use crate::cpu::Cpu;
+use crate::platform::InvadersCabinet;
+use crate::EmuError;
+
+// Anything the debugger can single step
+
+pub trait Debuggable {
+    fn cpu(&self) -> &Cpu;
+    fn cpu_mut(&mut self) -> &mut Cpu;
+    fn step(&mut self) -> Result<(), EmuError>;
+}
+
+impl Debuggable for Cpu {
+    fn cpu(&self) -> &Cpu {
+        self
+    }
+
+    fn cpu_mut(&mut self) -> &mut Cpu {
+        self
+    }
+
+    fn step(&mut self) -> Result<(), EmuError> {
+        Cpu::step(self).map(|_| ())
+    }
+}
+
+impl Debuggable for InvadersCabinet {
+    fn cpu(&self) -> &Cpu {
+        InvadersCabinet::cpu(self)
+    }
+
+    fn cpu_mut(&mut self) -> &mut Cpu {
+        InvadersCabinet::cpu_mut(self)
+    }
+
+    fn step(&mut self) -> Result<(), EmuError> {
+        InvadersCabinet::step(self).map(|_| ())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, other: Access) -> bool {
+        self == Access::ReadWrite || self == other || other == Access::ReadWrite
+    }
+}
+
+// Watches the inclusive range start..=end
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub access: Access,
+}
+
+// IN is a read and OUT is a write
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortWatch {
+    pub port: u8,
+    pub access: Access,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Step,
+    Breakpoint(u16),
+    Watch { addr: u16, access: Access },
+    Port { port: u8, access: Access },
+    Halted,
+    Limit,
+    // The instruction failed, the cpu is left where it was
+    Fault(EmuError),
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<u16>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub port_watches: Vec<PortWatch>,
+}
+
+// Works out the memory the next instruction reads and writes, not counting
+// the instruction fetch
+
+pub fn memory_accesses(cpu: &Cpu) -> Vec<(u16, Access)> {
+    let pc = cpu.regs.pc;
+    let op = cpu.memory[pc as usize];
+    let imm = cpu.cmb_le(
+        cpu.memory[pc.wrapping_add(1) as usize],
+        cpu.memory[pc.wrapping_add(2) as usize],
+    );
+    let hl = cpu.cmb_be(cpu.regs.h, cpu.regs.l);
+    let sp = cpu.regs.sp;
+
+    let pair = |addr: u16, access: Access| vec![(addr, access), (addr.wrapping_add(1), access)];
+    let push = || pair(sp.wrapping_sub(2), Access::Write);
+    let pop = || pair(sp, Access::Read);
+
+    match op {
+        0x76 => vec![],
+        0x70..=0x77 | 0x36 => vec![(hl, Access::Write)],
+        0x46 | 0x4E | 0x56 | 0x5E | 0x66 | 0x6E | 0x7E => vec![(hl, Access::Read)],
+        0x86 | 0x8E | 0x96 | 0x9E | 0xA6 | 0xAE | 0xB6 | 0xBE => vec![(hl, Access::Read)],
+        0x34 | 0x35 => vec![(hl, Access::ReadWrite)],
+        0x0A => vec![(cpu.cmb_be(cpu.regs.b, cpu.regs.c), Access::Read)],
+        0x1A => vec![(cpu.cmb_be(cpu.regs.d, cpu.regs.e), Access::Read)],
+        0x02 => vec![(cpu.cmb_be(cpu.regs.b, cpu.regs.c), Access::Write)],
+        0x12 => vec![(cpu.cmb_be(cpu.regs.d, cpu.regs.e), Access::Write)],
+        0x3A => vec![(imm, Access::Read)],
+        0x32 => vec![(imm, Access::Write)],
+        0x2A => pair(imm, Access::Read),
+        0x22 => pair(imm, Access::Write),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 | 0xCD | 0xDD | 0xED | 0xFD => push(),
+        0xC1 | 0xD1 | 0xE1 | 0xF1 | 0xC9 | 0xD9 => pop(),
+        0xE3 => pair(sp, Access::ReadWrite),
+        _ if op & 0xC7 == 0xC7 => push(),
+        _ if op & 0xC7 == 0xC4 && condition(cpu, op) => push(),
+        _ if op & 0xC7 == 0xC0 && condition(cpu, op) => pop(),
+        _ => vec![],
+    }
+}
+
+// The port the next instruction uses, if it's an IN or OUT
+
+pub fn port_access(cpu: &Cpu) -> Option<(u8, Access)> {
+    let pc = cpu.regs.pc;
+    let port = cpu.memory[pc.wrapping_add(1) as usize];
+    match cpu.memory[pc as usize] {
+        0xDB => Some((port, Access::Read)),
+        0xD3 => Some((port, Access::Write)),
+        _ => None,
+    }
+}
+
+// Condition encoded in bits 3-5 of a conditional jump, call or return
+
+pub fn condition(cpu: &Cpu, op: u8) -> bool {
+    let f = &cpu.flags;
+    match (op >> 3) & 7 {
+        0 => !f.zero,
+        1 => f.zero,
+        2 => !f.carry,
+        3 => f.carry,
+        4 => !f.parity,
+        5 => f.parity,
+        6 => !f.sign,
+        _ => f.sign,
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    // Which watchpoint, if any, the next instruction will trigger
+
+    pub fn check_watches(&self, cpu: &Cpu) -> Option<Stop> {
+        for (addr, access) in memory_accesses(cpu) {
+            let hit = self
+                .watchpoints
+                .iter()
+                .any(|w| (w.start..=w.end).contains(&addr) && w.access.covers(access));
+            if hit {
+                return Some(Stop::Watch { addr, access });
+            }
+        }
+
+        if let Some((port, access)) = port_access(cpu) {
+            if self.port_watches.iter().any(|w| w.port == port && w.access.covers(access)) {
+                return Some(Stop::Port { port, access });
+            }
+        }
+
+        None
+    }
+
+    // Single steps, watchpoints fire after the instruction that hit them
+
+    pub fn step<T: Debuggable>(&self, target: &mut T) -> Stop {
+        let watch = self.check_watches(target.cpu());
+        match target.step() {
+            Ok(()) => watch.unwrap_or(Stop::Step),
+            Err(e) => Stop::Fault(e),
+        }
+    }
+
+    // Runs until a breakpoint, watchpoint, HLT or until limit instructions
+    // have run. A breakpoint on the current pc doesn't stop it straight away
+
+    pub fn run<T: Debuggable>(&self, target: &mut T, limit: u64) -> Stop {
+        for n in 0..limit {
+            let cpu = target.cpu();
+            if n > 0 && self.breakpoints.contains(&cpu.regs.pc) {
+                return Stop::Breakpoint(cpu.regs.pc);
+            }
+            if cpu.hlted && !cpu.int_enabled {
+                return Stop::Halted;
+            }
+
+            if let stop @ (Stop::Watch { .. } | Stop::Port { .. } | Stop::Fault(_)) = self.step(target) {
+                return stop;
+            }
+        }
+
+        Stop::Limit
+    }
+
+    // Steps over CALLs and RSTs by running until they return
+
+    pub fn next<T: Debuggable>(&self, target: &mut T, limit: u64) -> Stop {
+        let cpu = target.cpu();
+        let op = cpu.memory[cpu.regs.pc as usize];
+        let is_call = matches!(op, 0xCD | 0xDD | 0xED | 0xFD) || op & 0xC7 == 0xC7 || (op & 0xC7 == 0xC4 && condition(cpu, op));
+
+        if !is_call {
+            return self.step(target);
+        }
+
+        let ret = cpu.regs.pc.wrapping_add(instruction_len(op));
+        let sp = cpu.regs.sp;
+
+        if let stop @ (Stop::Watch { .. } | Stop::Port { .. } | Stop::Fault(_)) = self.step(target) {
+            return stop;
+        }
+
+        for _ in 0..limit {
+            let cpu = target.cpu();
+            if cpu.regs.pc == ret && cpu.regs.sp == sp {
+                return Stop::Step;
+            }
+            if self.breakpoints.contains(&cpu.regs.pc) {
+                return Stop::Breakpoint(cpu.regs.pc);
+            }
+            if cpu.hlted && !cpu.int_enabled {
+                return Stop::Halted;
+            }
+            if let stop @ (Stop::Watch { .. } | Stop::Port { .. } | Stop::Fault(_)) = self.step(target) {
+                return stop;
+            }
+        }
+
+        Stop::Limit
+    }
+}
+
+#[cfg(test)]
+mod debugger_test {
+    use super::*;
+
+    fn cpu(prog: &[u8]) -> Cpu {
+        let mut cpu = Cpu::init(0, prog);
+        cpu.regs.sp = 0x2400;
+        cpu
+    }
+
+    #[test]
+    fn breakpoint_stops_run() {
+        // NOP NOP NOP HLT
+        let mut cpu = cpu(&[0x00, 0x00, 0x00, 0x76]);
+        let mut dbg = Debugger::new();
+        dbg.breakpoints.push(0x0002);
+
+        assert_eq!(dbg.run(&mut cpu, 100), Stop::Breakpoint(0x0002));
+        assert_eq!(dbg.run(&mut cpu, 100), Stop::Halted);
+    }
+
+    #[test]
+    fn write_watch_range() {
+        // LXI H, 0x2005; MOV A, M; MVI M, 1; HLT
+        let mut cpu = cpu(&[0x21, 0x05, 0x20, 0x7E, 0x36, 0x01, 0x76]);
+        let mut dbg = Debugger::new();
+        dbg.watchpoints.push(Watchpoint { start: 0x2000, end: 0x20FF, access: Access::Write });
+
+        assert_eq!(dbg.run(&mut cpu, 100), Stop::Watch { addr: 0x2005, access: Access::Write });
+        assert_eq!(cpu.regs.pc, 0x0006);
+        assert_eq!(cpu.memory[0x2005], 1);
+    }
+
+    #[test]
+    fn stack_and_port_accesses() {
+        // CALL 0x0010, at 0x0010: OUT 3; RET
+        let mut prog = [0u8; 0x14];
+        prog[..3].copy_from_slice(&[0xCD, 0x10, 0x00]);
+        prog[0x10..0x13].copy_from_slice(&[0xD3, 0x03, 0xC9]);
+        let mut cpu = cpu(&prog);
+
+        assert_eq!(memory_accesses(&cpu), [(0x23FE, Access::Write), (0x23FF, Access::Write)]);
+
+        let mut dbg = Debugger::new();
+        dbg.port_watches.push(PortWatch { port: 3, access: Access::Write });
+        assert_eq!(dbg.next(&mut cpu, 100), Stop::Port { port: 3, access: Access::Write });
+
+        assert_eq!(memory_accesses(&cpu), [(0x23FE, Access::Read), (0x23FF, Access::Read)]);
+    }
+
+    #[test]
+    fn next_steps_over_call() {
+        // CALL 0x0010; HLT, at 0x0010: NOP; RET
+        let mut prog = [0u8; 0x12];
+        prog[..4].copy_from_slice(&[0xCD, 0x10, 0x00, 0x76]);
+        prog[0x10..0x12].copy_from_slice(&[0x00, 0xC9]);
+        let mut cpu = cpu(&prog);
+
+        assert_eq!(Debugger::new().next(&mut cpu, 100), Stop::Step);
+        assert_eq!(cpu.regs.pc, 0x0003);
+    }
+
+    #[test]
+    fn faults_stop_the_run() {
+        // NOP; IN 9
+        let mut cpu = cpu(&[0x00, 0xDB, 0x09]);
+        let stop = Debugger::new().run(&mut cpu, 100);
+        assert_eq!(stop, Stop::Fault(EmuError::UnmappedPort { port: 9, write: false }));
+        assert_eq!(cpu.regs.pc, 0x0001);
+    }
+}
@@ -0,0 +1,337 @@
+// Command line front end for the debugger
+//
+// Numbers are hex, with or without a 0x prefix. An empty line repeats the
+// last command.
+
+use super::{Access, Debuggable, Debugger, PortWatch, Stop, Watchpoint};
+use crate::cpu::disasm::disassemble;
+use std::io::{self, BufRead, Write};
+
+// How many instructions continue runs before handing control back
+const RUN_LIMIT: u64 = 50_000_000;
+
+const HELP: &str = "\
+s, step [n]              step n instructions
+n, next                  step over calls
+c, continue              run until a breakpoint or watchpoint
+b, break <addr>          break when pc reaches addr
+d, delete [addr]         remove a breakpoint, or all of them
+w, watch <a>[-<b>] [r|w|rw]  watch memory, writes by default
+pw, pwatch <port> [in|out|both]  watch an IO port
+uw, unwatch              remove all watchpoints
+i, info                  list breakpoints and watchpoints
+r, regs                  show registers
+set <reg> <value>        set a register, pair (BC, DE, HL, SP, PC) or flag (S, Z, P, CY, AC, IE)
+x <addr> [len]           hexdump memory
+l, dis [addr] [count]    disassemble, around pc by default
+q, quit                  leave the debugger";
+
+fn parse_num(text: &str) -> Result<u16, String> {
+    let digits = text
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad number {}", text))
+}
+
+fn parse_access(text: Option<&str>, default: Access) -> Result<Access, String> {
+    match text.map(|t| t.to_lowercase()).as_deref() {
+        None => Ok(default),
+        Some("r" | "in" | "read") => Ok(Access::Read),
+        Some("w" | "out" | "write") => Ok(Access::Write),
+        Some("rw" | "both") => Ok(Access::ReadWrite),
+        Some(other) => Err(format!("bad access {}", other)),
+    }
+}
+
+fn describe(stop: Stop) -> String {
+    match stop {
+        Stop::Step => String::new(),
+        Stop::Breakpoint(addr) => format!("breakpoint at {:04X}\n", addr),
+        Stop::Watch { addr, access } => format!("watchpoint, {:?} of {:04X}\n", access, addr),
+        Stop::Port { port, access } => format!("port watch, {:?} on port {:02X}\n", access, port),
+        Stop::Halted => "cpu halted with interrupts off\n".to_string(),
+        Stop::Limit => format!("stopped after {} instructions\n", RUN_LIMIT),
+        Stop::Fault(e) => format!("{}\n", e),
+    }
+}
+
+fn hexdump(memory: &[u8], start: u16, len: u16) -> String {
+    let mut out = String::new();
+    let mut addr = start as usize;
+    let end = (start as usize + len as usize).min(memory.len());
+
+    while addr < end {
+        let row = &memory[addr..(addr + 16).min(end)];
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = row
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        out.push_str(&format!("{:04X}  {:<48} {}\n", addr, hex.join(" "), ascii));
+        addr += 16;
+    }
+
+    out
+}
+
+// Finds a start address a few bytes before pc that decodes cleanly into pc.
+// An instruction that runs past 0xFFFF doesn't
+
+fn start_before(memory: &[u8], pc: u16, back: u16) -> u16 {
+    for offset in (1..=back).rev() {
+        let mut addr = Some(pc.saturating_sub(offset));
+        while let Some(a) = addr.filter(|&a| a < pc) {
+            addr = a.checked_add(disassemble(memory, a).1);
+        }
+        if addr == Some(pc) {
+            return pc.saturating_sub(offset);
+        }
+    }
+    pc
+}
+
+fn listing(memory: &[u8], start: u16, count: usize, pc: u16) -> String {
+    let mut out = String::new();
+    let mut addr = start;
+
+    for _ in 0..count {
+        let (text, len) = disassemble(memory, addr);
+        let bytes: Vec<String> = (0..len)
+            .map(|n| format!("{:02X}", memory.get(addr.wrapping_add(n) as usize).copied().unwrap_or(0)))
+            .collect();
+        let marker = if addr == pc { ">" } else { " " };
+        out.push_str(&format!("{} {:04X}  {:<9} {}\n", marker, addr, bytes.join(" "), text));
+        addr = addr.wrapping_add(len);
+    }
+
+    out
+}
+
+pub struct Repl {
+    pub debugger: Debugger,
+    last: String,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            debugger: Debugger::new(),
+            last: String::new(),
+        }
+    }
+
+    // Reads commands until quit or the end of input
+
+    pub fn run<T: Debuggable, R: BufRead, W: Write>(&mut self, target: &mut T, input: R, out: &mut W) -> io::Result<()> {
+        write!(out, "{}", listing(&target.cpu().memory, target.cpu().regs.pc, 1, target.cpu().regs.pc))?;
+        write!(out, "(dbg) ")?;
+        out.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            let line = if line.trim().is_empty() { self.last.clone() } else { line };
+
+            match self.command(target, &line) {
+                Ok(Some(text)) => write!(out, "{}", text)?,
+                Ok(None) => return Ok(()),
+                Err(msg) => writeln!(out, "error: {}", msg)?,
+            }
+
+            self.last = line;
+            write!(out, "(dbg) ")?;
+            out.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // Runs one command and returns what to print, None means quit
+
+    pub fn command<T: Debuggable>(&mut self, target: &mut T, line: &str) -> Result<Option<String>, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let arg = |n: usize| words.get(n).copied();
+        let num = |n: usize| arg(n).map(parse_num).transpose();
+        let dbg = &mut self.debugger;
+
+        let text = match arg(0).unwrap_or("") {
+            "" => String::new(),
+            "q" | "quit" => return Ok(None),
+            "h" | "help" => format!("{}\n", HELP),
+
+            "s" | "step" => {
+                let mut stop = Stop::Step;
+                for _ in 0..num(1)?.unwrap_or(1) {
+                    stop = dbg.step(target);
+                    if stop != Stop::Step {
+                        break;
+                    }
+                }
+                self.stopped(target, stop)
+            }
+            "n" | "next" => {
+                let stop = dbg.next(target, RUN_LIMIT);
+                self.stopped(target, stop)
+            }
+            "c" | "continue" => {
+                let stop = dbg.run(target, RUN_LIMIT);
+                self.stopped(target, stop)
+            }
+
+            "b" | "break" => {
+                let addr = num(1)?.ok_or("break needs an address")?;
+                if !dbg.breakpoints.contains(&addr) {
+                    dbg.breakpoints.push(addr);
+                }
+                format!("breakpoint at {:04X}\n", addr)
+            }
+            "d" | "delete" => {
+                match num(1)? {
+                    Some(addr) => dbg.breakpoints.retain(|&b| b != addr),
+                    None => dbg.breakpoints.clear(),
+                }
+                String::new()
+            }
+
+            "w" | "watch" => {
+                let range = arg(1).ok_or("watch needs an address")?;
+                let (start, end) = match range.split_once('-') {
+                    Some((a, b)) => (parse_num(a)?, parse_num(b)?),
+                    None => (parse_num(range)?, parse_num(range)?),
+                };
+                if end < start {
+                    return Err("range ends before it starts".to_string());
+                }
+                let access = parse_access(arg(2), Access::Write)?;
+                dbg.watchpoints.push(Watchpoint { start, end, access });
+                format!("watching {:04X}-{:04X} for {:?}\n", start, end, access)
+            }
+            "pw" | "pwatch" => {
+                let port = num(1)?.ok_or("pwatch needs a port")?;
+                if port > 0xFF {
+                    return Err("ports are 00-FF".to_string());
+                }
+                let access = parse_access(arg(2), Access::ReadWrite)?;
+                dbg.port_watches.push(PortWatch { port: port as u8, access });
+                format!("watching port {:02X} for {:?}\n", port, access)
+            }
+            "uw" | "unwatch" => {
+                dbg.watchpoints.clear();
+                dbg.port_watches.clear();
+                String::new()
+            }
+            "i" | "info" => {
+                let mut out = String::new();
+                for b in &dbg.breakpoints {
+                    out.push_str(&format!("break {:04X}\n", b));
+                }
+                for w in &dbg.watchpoints {
+                    out.push_str(&format!("watch {:04X}-{:04X} {:?}\n", w.start, w.end, w.access));
+                }
+                for w in &dbg.port_watches {
+                    out.push_str(&format!("port {:02X} {:?}\n", w.port, w.access));
+                }
+                out
+            }
+
+            "r" | "regs" => format!("{}\n", target.cpu().get_regs()),
+            "set" => {
+                let name = arg(1).ok_or("set needs a register")?;
+                let value = num(2)?.ok_or("set needs a value")?;
+                target.cpu_mut().set_reg(name, value)?;
+                format!("{}\n", target.cpu().get_regs())
+            }
+
+            "x" => {
+                let addr = num(1)?.ok_or("x needs an address")?;
+                let len = num(2)?.unwrap_or(0x40);
+                hexdump(&target.cpu().memory, addr, len)
+            }
+            "l" | "dis" => {
+                let cpu = target.cpu();
+                let pc = cpu.regs.pc;
+                let start = match num(1)? {
+                    Some(addr) => addr,
+                    None => start_before(&cpu.memory, pc, 9),
+                };
+                let count = num(2)?.unwrap_or(10) as usize;
+                listing(&cpu.memory, start, count, pc)
+            }
+
+            other => return Err(format!("unknown command {}, try help", other)),
+        };
+
+        Ok(Some(text))
+    }
+
+    fn stopped<T: Debuggable>(&self, target: &T, stop: Stop) -> String {
+        let cpu = target.cpu();
+        describe(stop) + &listing(&cpu.memory, cpu.regs.pc, 1, cpu.regs.pc)
+    }
+}
+
+#[cfg(test)]
+mod repl_test {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    fn session(prog: &[u8], commands: &str) -> (Cpu, String) {
+        let mut cpu = Cpu::init(0, prog);
+        cpu.regs.sp = 0x2400;
+        let mut out = Vec::new();
+        Repl::new().run(&mut cpu, commands.as_bytes(), &mut out).unwrap();
+        (cpu, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn step_break_and_continue() {
+        // MVI A, 5; NOP; NOP; HLT
+        let (cpu, out) = session(&[0x3E, 0x05, 0x00, 0x00, 0x76], "b 3\ns\nc\nr\nq\n");
+        assert!(out.contains("> 0002  00        NOP"), "{}", out);
+        assert!(out.contains("breakpoint at 0003"), "{}", out);
+        assert!(out.contains("A=05"), "{}", out);
+        assert_eq!(cpu.regs.pc, 0x0003);
+    }
+
+    #[test]
+    fn edit_registers_and_memory_view() {
+        let (cpu, out) = session(&[0x00; 4], "set hl 2400\nset cy 1\nx 0 4\n");
+        assert_eq!(cpu.cmb_be(cpu.regs.h, cpu.regs.l), 0x2400);
+        assert!(cpu.flags.carry);
+        assert!(out.contains("0000  00 00 00 00"), "{}", out);
+    }
+
+    #[test]
+    fn disassembles_at_top_of_memory() {
+        // JMP at 0xFFFE runs past the end, so nothing before 0xFFFF decodes
+        // into it
+        let mut cpu = Cpu::init(0, &[]);
+        cpu.memory[0xFFFE] = 0xC3;
+        assert_eq!(start_before(&cpu.memory, 0xFFFF, 9), 0xFFFF);
+
+        cpu.memory[0xFFFE] = 0x00;
+        cpu.regs.pc = 0xFFFF;
+        let mut out = Vec::new();
+        Repl::new().run(&mut cpu, "disasm\nq\n".as_bytes(), &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("> FFFF"));
+    }
+
+    #[test]
+    fn empty_line_repeats() {
+        let (cpu, _) = session(&[0x00; 8], "s\n\n\n");
+        assert_eq!(cpu.regs.pc, 3);
+    }
+
+    #[test]
+    fn bad_commands_report_errors() {
+        let (_, out) = session(&[0x00], "frobnicate\nb zz\n");
+        assert!(out.contains("unknown command frobnicate"));
+        assert!(out.contains("bad number zz"));
+    }
+}
@@ -0,0 +1,75 @@
+// Errors the emulator reports instead of panicking
+//
+// None of them leave the machine in a broken state, a host can show the
+// error and carry on stepping, reset or load another program.
+
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmuError {
+    // The cpu fetched an opcode it doesn't implement, pc is left on it
+    IllegalOpcode { opcode: u8, addr: u16 },
+
+    // An undocumented opcode, only reported in strict mode
+    UndocumentedOpcode { opcode: u8, addr: u16 },
+
+    // IN or OUT on a port the machine has nothing wired to
+    UnmappedPort { port: u8, write: bool },
+
+    // A program that doesn't fit in memory at the address it was loaded to
+    LoadOutOfRange { addr: usize, len: usize },
+
+    RomMissing { path: PathBuf, reason: String },
+
+    // A ROM file that isn't the size the machine expects
+    RomMismatch { path: PathBuf, expected: usize, found: usize },
+
+    // A push that would write into ROM
+    StackOverflow { sp: u16, pc: u16 },
+
+    // An environment step with an action index past the end of its action set
+    BadAction { action: usize, actions: usize },
+
+    // An environment reset where the game wasn't being played this many
+    // frames after start was pressed, usually there's no ROM loaded
+    GameNotStarted { frames: u64 },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::IllegalOpcode { opcode, addr } => {
+                write!(f, "illegal opcode 0x{:02X} at 0x{:04X}", opcode, addr)
+            }
+            EmuError::UndocumentedOpcode { opcode, addr } => {
+                write!(f, "undocumented opcode 0x{:02X} at 0x{:04X}", opcode, addr)
+            }
+            EmuError::UnmappedPort { port, write } => {
+                let dir = if *write { "write to" } else { "read from" };
+                write!(f, "{} unmapped port 0x{:02X}", dir, port)
+            }
+            EmuError::LoadOutOfRange { addr, len } => {
+                write!(f, "{} bytes at 0x{:04X} don't fit in memory", len, addr)
+            }
+            EmuError::RomMissing { path, reason } => {
+                write!(f, "can't read ROM {}: {}", path.display(), reason)
+            }
+            EmuError::RomMismatch { path, expected, found } => {
+                write!(f, "ROM {} is {} bytes, expected {}", path.display(), found, expected)
+            }
+            EmuError::StackOverflow { sp, pc } => {
+                write!(f, "stack overflowed into ROM, SP=0x{:04X} at PC=0x{:04X}", sp, pc)
+            }
+            EmuError::BadAction { action, actions } => {
+                write!(f, "action {} out of range, there are {}", action, actions)
+            }
+            EmuError::GameNotStarted { frames } => {
+                write!(f, "game not started {} frames after pressing start", frames)
+            }
+        }
+    }
+}
+
+impl Error for EmuError {}
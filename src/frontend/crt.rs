@@ -0,0 +1,187 @@
+// CRT look for the decoded frame, done on the cpu
+//
+// Phosphor decay works on native size frames and has to see every one of
+// them. Glow, scanlines and the shadow mask go on afterwards, usually after
+// scaling so each monitor line is a few output pixels wide. The monitor is
+// mounted on its side, so its scanlines run down the picture.
+
+// Each effect's strength, 0 is off and 100 the strongest
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CrtSettings {
+    // How much of the last frame is still glowing in this one
+    pub persistence: u8,
+    // How much the gap between monitor lines is darkened
+    pub scanlines: u8,
+    // How much light bleeds into the pixels around
+    pub glow: u8,
+    // How much of the other two colours each phosphor dot holds back
+    pub mask: u8,
+}
+
+impl CrtSettings {
+    pub fn is_off(&self) -> bool {
+        *self == CrtSettings::default()
+    }
+
+    // Sets an effect by its config name
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let effect = match name {
+            "persistence" => &mut self.persistence,
+            "scanlines" => &mut self.scanlines,
+            "glow" => &mut self.glow,
+            "mask" => &mut self.mask,
+            _ => return Err(format!("unknown crt effect {}", name)),
+        };
+        *effect = match value.parse() {
+            Ok(n) if n <= 100 => n,
+            _ => return Err(format!("{} must be 0 to 100, not {}", name, value)),
+        };
+        Ok(())
+    }
+}
+
+// Scales a channel by percent
+
+fn fade(value: u8, percent: u8) -> u8 {
+    (value as u32 * percent as u32 / 100) as u8
+}
+
+pub struct Crt {
+    pub settings: CrtSettings,
+    // What's left glowing of the frames so far
+    afterglow: Vec<u8>,
+}
+
+impl Crt {
+    pub fn new(settings: CrtSettings) -> Self {
+        Crt {
+            settings,
+            afterglow: Vec::new(),
+        }
+    }
+
+    // Lights up rgb with what's left of the earlier frames, call it with
+    // every frame at native size
+
+    pub fn decay(&mut self, rgb: &mut [u8]) {
+        if self.afterglow.len() != rgb.len() {
+            self.afterglow = vec![0; rgb.len()];
+        }
+
+        let persistence = self.settings.persistence;
+        for (pixel, glow) in rgb.iter_mut().zip(self.afterglow.iter_mut()) {
+            *pixel = (*pixel).max(fade(*glow, persistence));
+            *glow = *pixel;
+        }
+    }
+
+    // Glow, scanlines and shadow mask on a frame of width by height that has
+    // been scaled up by factor
+
+    pub fn finish(&self, rgb: &mut [u8], width: usize, height: usize, factor: usize) {
+        let factor = factor.max(1);
+        if self.settings.glow > 0 {
+            glow(rgb, width, height, factor, self.settings.glow);
+        }
+
+        let lines = 100 - self.settings.scanlines;
+        let mask = 100 - self.settings.mask;
+        for (n, pixel) in rgb.chunks_mut(3).take(width * height).enumerate() {
+            let (x, y) = (n % width, n / width);
+            // The last column of each monitor line, every other one at 1:1
+            let gap = if factor == 1 { x % 2 == 1 } else { x % factor == factor - 1 };
+            for (channel, value) in pixel.iter_mut().enumerate() {
+                if gap {
+                    *value = fade(*value, lines);
+                }
+                // Red, green and blue dots in turn down the picture
+                if channel != y % 3 {
+                    *value = fade(*value, mask);
+                }
+            }
+        }
+    }
+}
+
+// Adds a box blur of rgb, factor pixels each way, back onto it
+
+fn glow(rgb: &mut [u8], width: usize, height: usize, radius: usize, strength: u8) {
+    let blur = |input: &[u32], along: usize, across: usize, at: &dyn Fn(usize, usize) -> usize| {
+        let mut out = vec![0; input.len()];
+        for line in 0..across {
+            for i in 0..along {
+                let (from, to) = (i.saturating_sub(radius), (i + radius).min(along - 1));
+                for channel in 0..3 {
+                    let sum: u32 = (from..=to).map(|j| input[at(j, line) * 3 + channel]).sum();
+                    out[at(i, line) * 3 + channel] = sum / (2 * radius as u32 + 1);
+                }
+            }
+        }
+        out
+    };
+
+    let input: Vec<u32> = rgb.iter().map(|&v| v as u32).collect();
+    let rows = blur(&input, width, height, &|x, y| y * width + x);
+    let blurred = blur(&rows, height, width, &|y, x| y * width + x);
+
+    for (value, glow) in rgb.iter_mut().zip(blurred) {
+        *value = value.saturating_add(fade(glow.min(0xFF) as u8, strength));
+    }
+}
+
+#[cfg(test)]
+mod crt_test {
+    use super::*;
+
+    #[test]
+    fn phosphor_decay() {
+        let mut crt = Crt::new(CrtSettings { persistence: 50, ..Default::default() });
+        let mut lit = [200, 100, 0];
+        crt.decay(&mut lit);
+        assert_eq!(lit, [200, 100, 0]);
+
+        let mut dark = [0, 150, 0];
+        crt.decay(&mut dark);
+        assert_eq!(dark, [100, 150, 0]);
+        let mut dark = [0; 3];
+        crt.decay(&mut dark);
+        assert_eq!(dark, [50, 75, 0]);
+    }
+
+    #[test]
+    fn scanlines_and_mask() {
+        let settings = CrtSettings { scanlines: 50, mask: 100, ..Default::default() };
+        let mut rgb = vec![200; 4 * 3 * 3];
+        Crt::new(settings).finish(&mut rgb, 4, 3, 2);
+        let at = |x: usize, y: usize| &rgb[(y * 4 + x) * 3..(y * 4 + x) * 3 + 3];
+
+        assert_eq!(at(0, 0), [200, 0, 0]);
+        assert_eq!(at(1, 0), [100, 0, 0]);
+        assert_eq!(at(2, 1), [0, 200, 0]);
+        assert_eq!(at(3, 2), [0, 0, 100]);
+        assert!(CrtSettings::default().is_off());
+    }
+
+    #[test]
+    fn glow_spreads() {
+        let mut rgb = vec![0; 5 * 5 * 3];
+        let centre = (2 * 5 + 2) * 3;
+        rgb[centre..centre + 3].copy_from_slice(&[0xFF; 3]);
+        Crt::new(CrtSettings { glow: 100, ..Default::default() }).finish(&mut rgb, 5, 5, 1);
+
+        assert_eq!(rgb[centre], 0xFF);
+        assert_eq!(&rgb[centre + 3..centre + 6], [28; 3]);
+        assert_eq!(&rgb[..3], [0; 3]);
+    }
+
+    #[test]
+    fn settings() {
+        let mut settings = CrtSettings::default();
+        settings.set("glow", "30").unwrap();
+        assert_eq!(settings.glow, 30);
+        assert_eq!(settings.set("glow", "101"), Err("glow must be 0 to 100, not 101".to_string()));
+        assert_eq!(settings.set("bloom", "1"), Err("unknown crt effect bloom".to_string()));
+    }
+}
@@ -0,0 +1,231 @@
+// Backends the command line front end can pick from
+//
+// Everything here is plain std, video goes to the terminal or nowhere and
+// audio is a terminal bell or a log of the sounds the game triggers.
+
+pub mod crt;
+pub mod png;
+pub mod scale;
+pub mod terminal;
+
+use crate::platform::io::{Button, InvadersInput};
+use crate::platform::sound::Sound;
+use crate::platform::video::{Frame, Overlay};
+use std::io;
+
+pub trait Video {
+    fn present(&mut self, frame: &Frame, overlay: Overlay) -> io::Result<()>;
+}
+
+pub trait Audio {
+    fn play(&mut self, sounds: &[Sound]);
+}
+
+// Raw key presses since the last poll
+pub trait Keyboard {
+    fn poll(&mut self) -> io::Result<Vec<u8>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoBackend {
+    Terminal,
+    None,
+}
+
+impl VideoBackend {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "terminal" => Ok(VideoBackend::Terminal),
+            "none" => Ok(VideoBackend::None),
+            _ => Err(format!("unknown video backend {}, expected terminal or none", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioBackend {
+    None,
+    Bell,
+    Log,
+}
+
+impl AudioBackend {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "none" => Ok(AudioBackend::None),
+            "bell" => Ok(AudioBackend::Bell),
+            "log" => Ok(AudioBackend::Log),
+            _ => Err(format!("unknown audio backend {}, expected none, bell or log", name)),
+        }
+    }
+
+    // Volume is 0 to 100, neither backend has levels so 0 just mutes
+
+    pub fn open(self, volume: u8) -> Box<dyn Audio> {
+        match self {
+            _ if volume == 0 => Box::new(NullAudio),
+            AudioBackend::None => Box::new(NullAudio),
+            AudioBackend::Bell => Box::new(BellAudio),
+            AudioBackend::Log => Box::new(LogAudio),
+        }
+    }
+}
+
+pub struct NullVideo;
+
+impl Video for NullVideo {
+    fn present(&mut self, _frame: &Frame, _overlay: Overlay) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// No keys ever arrive either, for runs driven by a movie or nothing at all
+
+impl Keyboard for NullVideo {
+    fn poll(&mut self) -> io::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+pub struct NullAudio;
+
+impl Audio for NullAudio {
+    fn play(&mut self, _sounds: &[Sound]) {}
+}
+
+// Rings the terminal bell for the effects worth noticing
+
+pub struct BellAudio;
+
+impl Audio for BellAudio {
+    fn play(&mut self, sounds: &[Sound]) {
+        if sounds.iter().any(|s| matches!(s, Sound::PlayerDie | Sound::ExtraLife | Sound::UfoHit)) {
+            eprint!("\x07");
+        }
+    }
+}
+
+pub struct LogAudio;
+
+impl Audio for LogAudio {
+    fn play(&mut self, sounds: &[Sound]) {
+        for sound in sounds {
+            eprintln!("sound: {}", sound.name());
+        }
+    }
+}
+
+// Key names as written in the config, a single character or one of these
+
+pub fn parse_key(name: &str) -> Option<u8> {
+    match name {
+        "space" => Some(b' '),
+        "enter" => Some(b'\n'),
+        "tab" => Some(b'\t'),
+        "esc" => Some(0x1B),
+        _ if name.len() == 1 && name.is_ascii() => Some(name.as_bytes()[0]),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    bindings: Vec<(u8, Button)>,
+    pub quit: u8,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap {
+            bindings: vec![
+                (b'c', Button::Coin),
+                (b'1', Button::P1Start),
+                (b'2', Button::P2Start),
+                (b' ', Button::P1Fire),
+                (b'a', Button::P1Left),
+                (b'd', Button::P1Right),
+                (b'i', Button::P2Fire),
+                (b'j', Button::P2Left),
+                (b'l', Button::P2Right),
+                (b't', Button::Tilt),
+            ],
+            quit: b'q',
+        }
+    }
+}
+
+impl KeyMap {
+    // Binds key to button, replacing whatever key the button had
+    pub fn bind(&mut self, key: u8, button: Button) {
+        self.bindings.retain(|&(k, b)| b != button && k != key);
+        self.bindings.push((key, button));
+    }
+
+    pub fn button(&self, key: u8) -> Option<Button> {
+        self.bindings.iter().find(|&&(k, _)| k == key).map(|&(_, b)| b)
+    }
+}
+
+// Terminals only report key presses, and repeats while a key is held, so a
+// button counts as held for a few frames after its last press
+
+pub const HOLD_FRAMES: u32 = 6;
+
+#[derive(Default)]
+pub struct HeldKeys {
+    held: [u32; Button::ALL.len()],
+}
+
+impl HeldKeys {
+    // Ages the held buttons by a frame and applies new presses, returns
+    // the input for this frame and whether quit was pressed
+    pub fn update(&mut self, keys: &[u8], map: &KeyMap) -> (InvadersInput, bool) {
+        self.held.iter_mut().for_each(|h| *h = h.saturating_sub(1));
+
+        let mut quit = false;
+        for &key in keys {
+            if key == map.quit {
+                quit = true;
+            }
+            if let Some(button) = map.button(key) {
+                let n = Button::ALL.iter().position(|&b| b == button).unwrap_or(0);
+                self.held[n] = HOLD_FRAMES;
+            }
+        }
+
+        let mut input = InvadersInput::default();
+        for (n, &button) in Button::ALL.iter().enumerate() {
+            input.set(button, self.held[n] > 0);
+        }
+        (input, quit)
+    }
+}
+
+#[cfg(test)]
+mod frontend_test {
+    use super::*;
+
+    #[test]
+    fn keys_hold_for_a_few_frames() {
+        let map = KeyMap::default();
+        let mut keys = HeldKeys::default();
+
+        let (input, quit) = keys.update(b"c ", &map);
+        assert!(input.coin && input.p1_fire && !quit);
+
+        for _ in 1..HOLD_FRAMES {
+            assert!(keys.update(b"", &map).0.coin);
+        }
+        assert_eq!(keys.update(b"q", &map), (InvadersInput::default(), true));
+    }
+
+    #[test]
+    fn rebinding() {
+        let mut map = KeyMap::default();
+        map.bind(b'x', Button::P1Fire);
+        assert_eq!(map.button(b'x'), Some(Button::P1Fire));
+        assert_eq!(map.button(b' '), None);
+        assert_eq!(parse_key("space"), Some(b' '));
+        assert_eq!(parse_key("nope"), None);
+    }
+}
@@ -0,0 +1,287 @@
+// Image scaling for screenshots and other output larger than 1:1
+//
+// Besides nearest neighbour there are the pixel art filters, which round
+// off diagonals by looking at each pixel's neighbours. They work on RGB
+// bytes row by row, like everything else that handles decoded frames.
+
+type Pixel = [u8; 3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Scale2x,
+    Scale3x,
+    // Eric's Pixel Expansion, the formulation Scale2x came from. It gives
+    // the same picture
+    Epx,
+    // Blends along edges it finds by weighing the colour differences around
+    // each corner, like Hyllian's first xBR
+    Xbr,
+}
+
+impl Filter {
+    pub fn parse(name: &str) -> Result<Filter, String> {
+        match name {
+            "nearest" => Ok(Filter::Nearest),
+            "scale2x" => Ok(Filter::Scale2x),
+            "scale3x" => Ok(Filter::Scale3x),
+            "epx" => Ok(Filter::Epx),
+            "xbr" => Ok(Filter::Xbr),
+            _ => Err(format!("unknown filter {}, expected nearest, scale2x, scale3x, epx or xbr", name)),
+        }
+    }
+
+    // Scales by factor, running the filter as many times as its own factor
+    // goes into it and making up the rest with nearest neighbour
+
+    pub fn scale(self, rgb: &[u8], width: usize, height: usize, factor: usize) -> Vec<u8> {
+        let step = match self {
+            Filter::Nearest => return nearest(rgb, width, height, factor),
+            Filter::Scale3x => 3,
+            _ => 2,
+        };
+
+        let (mut out, mut width, mut height, mut factor) = (rgb.to_vec(), width, height, factor.max(1));
+        while factor % step == 0 {
+            let image = Image { rgb: &out, width, height };
+            out = match self {
+                Filter::Scale3x => image.scale3x(),
+                Filter::Epx => image.epx(),
+                Filter::Xbr => image.xbr(),
+                _ => image.scale2x(),
+            };
+            width *= step;
+            height *= step;
+            factor /= step;
+        }
+        nearest(&out, width, height, factor)
+    }
+}
+
+// Repeats every pixel factor times in both directions
+
+pub fn nearest(rgb: &[u8], width: usize, height: usize, factor: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rgb.len() * factor * factor);
+    for row in rgb.chunks(width * 3).take(height) {
+        let mut scaled = Vec::with_capacity(row.len() * factor);
+        for pixel in row.chunks(3) {
+            for _ in 0..factor {
+                scaled.extend_from_slice(pixel);
+            }
+        }
+        for _ in 0..factor {
+            out.extend_from_slice(&scaled);
+        }
+    }
+    out
+}
+
+struct Image<'a> {
+    rgb: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl Image<'_> {
+    // The pixel dx, dy away from x, y, the edge ones repeat past the border
+
+    fn at(&self, x: usize, y: usize, dx: isize, dy: isize) -> Pixel {
+        let x = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+        let y = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+        let i = (y * self.width + x) * 3;
+        [self.rgb[i], self.rgb[i + 1], self.rgb[i + 2]]
+    }
+
+    // Runs block over every pixel, it returns the factor by factor pixels
+    // that replace it row by row
+
+    fn map<const N: usize>(&self, factor: usize, block: impl Fn(usize, usize) -> [Pixel; N]) -> Vec<u8> {
+        let out_width = self.width * factor;
+        let mut out = vec![0; self.rgb.len() * factor * factor];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for (n, pixel) in block(x, y).iter().enumerate() {
+                    let (ox, oy) = (x * factor + n % factor, y * factor + n / factor);
+                    let i = (oy * out_width + ox) * 3;
+                    out[i..i + 3].copy_from_slice(pixel);
+                }
+            }
+        }
+        out
+    }
+
+    // With B above E, D left of it, F right and H below
+
+    fn scale2x(&self) -> Vec<u8> {
+        self.map(2, |x, y| {
+            let at = |dx, dy| self.at(x, y, dx, dy);
+            let (b, d, e, f, h) = (at(0, -1), at(-1, 0), at(0, 0), at(1, 0), at(0, 1));
+            if b == h || d == f {
+                return [e; 4];
+            }
+            [
+                if d == b { d } else { e },
+                if b == f { f } else { e },
+                if d == h { d } else { e },
+                if h == f { f } else { e },
+            ]
+        })
+    }
+
+    fn epx(&self) -> Vec<u8> {
+        self.map(2, |x, y| {
+            let at = |dx, dy| self.at(x, y, dx, dy);
+            let (a, c, p, b, d) = (at(0, -1), at(-1, 0), at(0, 0), at(1, 0), at(0, 1));
+            let same = [a == b, a == c, a == d, b == c, b == d, c == d].iter().filter(|&&s| s).count();
+            if same >= 3 {
+                return [p; 4];
+            }
+            [
+                if c == a { a } else { p },
+                if a == b { b } else { p },
+                if d == c { c } else { p },
+                if b == d { d } else { p },
+            ]
+        })
+    }
+
+    // A to I are the 3x3 block around E, row by row
+
+    fn scale3x(&self) -> Vec<u8> {
+        self.map(3, |x, y| {
+            let at = |dx, dy| self.at(x, y, dx, dy);
+            let (a, b, c) = (at(-1, -1), at(0, -1), at(1, -1));
+            let (d, e, f) = (at(-1, 0), at(0, 0), at(1, 0));
+            let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+            if b == h || d == f {
+                return [e; 9];
+            }
+
+            let pick = |cond: bool, p: Pixel| if cond { p } else { e };
+            [
+                pick(d == b, d),
+                pick((d == b && e != c) || (b == f && e != a), b),
+                pick(b == f, f),
+                pick((d == b && e != g) || (d == h && e != a), d),
+                e,
+                pick((b == f && e != i) || (h == f && e != c), f),
+                pick(d == h, d),
+                pick((d == h && e != i) || (h == f && e != g), h),
+                pick(h == f, f),
+            ]
+        })
+    }
+
+    fn xbr(&self) -> Vec<u8> {
+        self.map(2, |x, y| {
+            // Each corner is the bottom right one with the neighbourhood
+            // mirrored onto it
+            let corner = |sx: isize, sy: isize| {
+                let at = |dx: isize, dy: isize| self.at(x, y, dx * sx, dy * sy);
+                let (e, b, c, d, f) = (at(0, 0), at(0, -1), at(1, -1), at(-1, 0), at(1, 0));
+                let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+                let (f4, i4, h5, i5) = (at(2, 0), at(2, 1), at(0, 2), at(1, 2));
+
+                // Differences across the H-F diagonal against along it
+                let across = distance(e, c) + distance(e, g) + distance(i, f4) + distance(i, h5) + 4 * distance(h, f);
+                let along = distance(h, d) + distance(h, i5) + distance(f, i4) + distance(f, b) + 4 * distance(e, i);
+                if across < along {
+                    let edge = if distance(e, f) <= distance(e, h) { f } else { h };
+                    blend(e, edge)
+                } else {
+                    e
+                }
+            };
+            [corner(-1, -1), corner(1, -1), corner(-1, 1), corner(1, 1)]
+        })
+    }
+}
+
+// How different two colours look, weighted towards brightness
+
+fn distance(a: Pixel, b: Pixel) -> u32 {
+    let [r, g, b] = [0, 1, 2].map(|n| a[n] as i32 - b[n] as i32);
+    let y = (299 * r + 587 * g + 114 * b) / 1000;
+    let u = (-169 * r - 331 * g + 500 * b) / 1000;
+    let v = (500 * r - 419 * g - 81 * b) / 1000;
+    (48 * y.unsigned_abs() + 7 * u.unsigned_abs() + 6 * v.unsigned_abs()) / 8
+}
+
+fn blend(a: Pixel, b: Pixel) -> Pixel {
+    [0, 1, 2].map(|n| ((a[n] as u16 + b[n] as u16) / 2) as u8)
+}
+
+#[cfg(test)]
+mod scale_test {
+    use super::*;
+
+    #[test]
+    fn nearest_doubles() {
+        let rgb = [1, 1, 1, 2, 2, 2];
+        let out = nearest(&rgb, 2, 1, 2);
+        assert_eq!(out, [1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2]);
+    }
+
+    const W: Pixel = [0xFF; 3];
+    const K: Pixel = [0x00; 3];
+
+    fn image(pixels: &[Pixel]) -> Vec<u8> {
+        pixels.concat()
+    }
+
+    fn pixel(rgb: &[u8], width: usize, x: usize, y: usize) -> Pixel {
+        let i = (y * width + x) * 3;
+        [rgb[i], rgb[i + 1], rgb[i + 2]]
+    }
+
+    // A diagonal step, white above and left of the diagonal
+    fn step() -> Vec<u8> {
+        image(&[W, W, K, W, K, K, K, K, K])
+    }
+
+    #[test]
+    fn scale2x_rounds_diagonals() {
+        let out = Filter::Scale2x.scale(&step(), 3, 3, 2);
+        // The middle pixel's top left corner takes the white around it
+        assert_eq!(pixel(&out, 6, 2, 2), W);
+        assert_eq!(pixel(&out, 6, 3, 3), K);
+        assert_eq!(pixel(&out, 6, 3, 2), K);
+
+        // Flat areas and factors it can't reach fall back to nearest
+        let flat = image(&[W; 4]);
+        assert_eq!(Filter::Scale2x.scale(&flat, 2, 2, 2), nearest(&flat, 2, 2, 2));
+        assert_eq!(Filter::Scale2x.scale(&step(), 3, 3, 3), nearest(&step(), 3, 3, 3));
+        assert_eq!(Filter::Scale2x.scale(&step(), 3, 3, 4).len(), 3 * 3 * 16 * 3);
+    }
+
+    #[test]
+    fn epx_matches_scale2x() {
+        // Above, left and right of the middle are white, it's left alone
+        let t = image(&[K, W, K, W, K, W, K, K, K]);
+        assert_eq!(pixel(&Filter::Epx.scale(&t, 3, 3, 2), 6, 2, 2), K);
+        assert_eq!(Filter::Epx.scale(&t, 3, 3, 2), Filter::Scale2x.scale(&t, 3, 3, 2));
+
+        let corner = image(&[K, W, K, W, K, K, K, K, K]);
+        assert_eq!(pixel(&Filter::Epx.scale(&corner, 3, 3, 2), 6, 2, 2), W);
+        assert_eq!(Filter::Epx.scale(&step(), 3, 3, 2), Filter::Scale2x.scale(&step(), 3, 3, 2));
+    }
+
+    #[test]
+    fn scale3x_rounds_diagonals() {
+        let out = Filter::Scale3x.scale(&step(), 3, 3, 3);
+        assert_eq!(pixel(&out, 9, 3, 3), W);
+        assert_eq!(pixel(&out, 9, 4, 3), K);
+        assert_eq!(pixel(&out, 9, 4, 4), K);
+        assert_eq!(out.len(), 9 * 9 * 3);
+    }
+
+    #[test]
+    fn xbr_blends_edges() {
+        let out = Filter::Xbr.scale(&step(), 3, 3, 2);
+        assert_eq!(pixel(&out, 6, 2, 2), [0x7F; 3]);
+        assert_eq!(pixel(&out, 6, 3, 3), K);
+        assert_eq!(pixel(&out, 6, 0, 0), W);
+        assert_eq!(Filter::parse("xbr"), Ok(Filter::Xbr));
+        assert!(Filter::parse("hq2x").is_err());
+    }
+}
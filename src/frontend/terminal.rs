@@ -0,0 +1,193 @@
+// Terminal backend, draws the screen with braille characters and reads keys
+//
+// Each character cell holds 2x4 pixels so the whole screen fits in 112x64
+// cells. The terminal is put into non canonical mode with stty, there's no
+// other way to read single keys without a dependency.
+
+use super::crt::{Crt, CrtSettings};
+use super::scale::Filter;
+use super::{Keyboard, Video};
+use crate::platform::video::{Frame, Overlay, Rgb, HEIGHT, WIDTH};
+use std::fs::File;
+use std::io::{self, Read, Stdout, Write};
+use std::process::Command;
+
+// Braille dot for the pixel at dx, dy within a cell
+const DOTS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let out = Command::new("stty").args(args).stdin(File::open("/dev/tty")?).output()?;
+    if !out.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+// The whole frame as lines of braille, coloured through the overlay or the
+// colour PROMs
+
+pub fn braille(frame: &Frame, overlay: Overlay) -> String {
+    let mut out = String::new();
+    let mut color: Option<Rgb> = None;
+
+    for cy in 0..HEIGHT / 4 {
+        for cx in 0..WIDTH / 2 {
+            let mut dots = 0;
+            for (dx, column) in DOTS.iter().enumerate() {
+                for (dy, dot) in column.iter().enumerate() {
+                    if frame.pixel(cx * 2 + dx, cy * 4 + dy) {
+                        dots |= dot;
+                    }
+                }
+            }
+
+            let cell = frame.color(cx * 2, cy * 4, overlay);
+            if dots != 0 && color != Some(cell) {
+                out.push_str(&format!("\x1b[38;2;{};{};{}m", cell[0], cell[1], cell[2]));
+                color = Some(cell);
+            }
+            out.push(char::from_u32(0x2800 + dots as u32).unwrap_or(' '));
+        }
+        out.push_str("\r\n");
+    }
+
+    out.push_str("\x1b[0m");
+    out
+}
+
+// The same from a width by height RGB picture, a dot is lit if any of its
+// channels is bright enough and a cell takes the brightest colour in it
+
+pub fn braille_rgb(rgb: &[u8], width: usize, height: usize) -> String {
+    let mut out = String::new();
+    let mut color: Option<Rgb> = None;
+
+    for cy in 0..height / 4 {
+        for cx in 0..width / 2 {
+            let mut dots = 0;
+            let mut cell = [0; 3];
+            for (dx, column) in DOTS.iter().enumerate() {
+                for (dy, dot) in column.iter().enumerate() {
+                    let at = ((cy * 4 + dy) * width + cx * 2 + dx) * 3;
+                    let pixel = &rgb[at..at + 3];
+                    if pixel.iter().any(|&c| c >= 0x40) {
+                        dots |= dot;
+                        cell.iter_mut().zip(pixel).for_each(|(c, &p)| *c = (*c).max(p));
+                    }
+                }
+            }
+
+            if dots != 0 && color != Some(cell) {
+                out.push_str(&format!("\x1b[38;2;{};{};{}m", cell[0], cell[1], cell[2]));
+                color = Some(cell);
+            }
+            out.push(char::from_u32(0x2800 + dots as u32).unwrap_or(' '));
+        }
+        out.push_str("\r\n");
+    }
+
+    out.push_str("\x1b[0m");
+    out
+}
+
+pub struct Terminal {
+    out: Stdout,
+    saved: String,
+    crt: Crt,
+    filter: Filter,
+    factor: usize,
+}
+
+impl Terminal {
+    pub fn open() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "min", "0", "time", "0"])?;
+
+        let mut out = io::stdout();
+        write!(out, "\x1b[?25l\x1b[2J")?;
+        out.flush()?;
+        Ok(Terminal {
+            out,
+            saved,
+            crt: Crt::new(CrtSettings::default()),
+            filter: Filter::Nearest,
+            factor: 1,
+        })
+    }
+
+    // Persistence and glow leave trails, scanlines and the mask mostly dim
+    // dots out unless the screen is scaled up
+
+    pub fn set_crt(&mut self, settings: CrtSettings) {
+        self.crt = Crt::new(settings);
+    }
+
+    // Draws the screen factor times bigger, which needs a terminal that big
+
+    pub fn set_scale(&mut self, filter: Filter, factor: usize) {
+        self.filter = filter;
+        self.factor = factor.max(1);
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+        let _ = write!(self.out, "\x1b[0m\x1b[?25h\r\n");
+        let _ = self.out.flush();
+    }
+}
+
+impl Video for Terminal {
+    fn present(&mut self, frame: &Frame, overlay: Overlay) -> io::Result<()> {
+        let text = if self.crt.settings.is_off() && self.factor == 1 {
+            braille(frame, overlay)
+        } else {
+            let mut rgb = frame.to_rgb(overlay);
+            self.crt.decay(&mut rgb);
+            let (factor, width, height) = (self.factor, WIDTH * self.factor, HEIGHT * self.factor);
+            let mut rgb = self.filter.scale(&rgb, WIDTH, HEIGHT, factor);
+            self.crt.finish(&mut rgb, width, height, factor);
+            braille_rgb(&rgb, width, height)
+        };
+        write!(self.out, "\x1b[H{}", text)?;
+        self.out.flush()
+    }
+}
+
+impl Keyboard for Terminal {
+    fn poll(&mut self) -> io::Result<Vec<u8>> {
+        let mut buf = [0; 64];
+        let n = io::stdin().read(&mut buf)?;
+        Ok(buf[..n].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod terminal_test {
+    use super::*;
+    use crate::platform::video::{VRAM_END, VRAM_START};
+
+    #[test]
+    fn braille_cells() {
+        let mut vram = vec![0; VRAM_END - VRAM_START];
+        // Top left pixel of the monitor is the last bit of the first row
+        vram[31] = 0x80;
+        let text = braille(&Frame::from_vram(&vram), Overlay::None);
+        let lines: Vec<&str> = text.split("\r\n").collect();
+
+        assert_eq!(lines.len(), HEIGHT / 4 + 1);
+        assert!(lines[0].starts_with("\x1b[38;2;255;255;255m\u{2801}\u{2800}"), "{:?}", lines[0]);
+        assert_eq!(lines[1].chars().count(), WIDTH / 2);
+    }
+
+    #[test]
+    fn braille_from_rgb() {
+        let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+        rgb[..3].copy_from_slice(&[0x80, 0x20, 0x00]);
+        rgb[3..6].copy_from_slice(&[0x00, 0x10, 0x00]);
+        let text = braille_rgb(&rgb, WIDTH, HEIGHT);
+
+        assert!(text.starts_with("\x1b[38;2;128;32;0m\u{2801}\u{2800}"), "{:?}", &text[..40]);
+    }
+}
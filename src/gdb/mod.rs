@@ -0,0 +1,510 @@
+// GDB remote serial protocol stub
+//
+// Serves one debugger connection over TCP. Breakpoints and watchpoints go
+// through the same Debugger the REPL uses. Registers are sent in the order
+// of TARGET_XML: A F B C D E H L as bytes then SP and PC as little endian words.
+
+use crate::cpu::Flags;
+use crate::debugger::{Access, Debuggable, Debugger, Stop, Watchpoint};
+use crate::EmuError;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+pub const DEFAULT_PORT: u16 = 1234;
+
+// Instructions run between checks for a ^C from the client
+const CHUNK: u64 = 10_000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>i8080</architecture>
+  <feature name="org.i8080.core">
+    <reg name="a" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="f" bitsize="8" regnum="1" type="uint8"/>
+    <reg name="b" bitsize="8" regnum="2" type="uint8"/>
+    <reg name="c" bitsize="8" regnum="3" type="uint8"/>
+    <reg name="d" bitsize="8" regnum="4" type="uint8"/>
+    <reg name="e" bitsize="8" regnum="5" type="uint8"/>
+    <reg name="h" bitsize="8" regnum="6" type="uint8"/>
+    <reg name="l" bitsize="8" regnum="7" type="uint8"/>
+    <reg name="sp" bitsize="16" regnum="8" type="data_ptr"/>
+    <reg name="pc" bitsize="16" regnum="9" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// Waits for a client on the listener and serves it until it detaches
+
+pub fn serve<T: Debuggable>(target: &mut T, listener: &TcpListener) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    Session::new(stream).run(target)
+}
+
+struct Session {
+    stream: TcpStream,
+    pending: VecDeque<u8>,
+    debugger: Debugger,
+    no_ack: bool,
+    last_reply: String,
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b))
+}
+
+fn hex_byte(text: &str) -> Option<u8> {
+    u8::from_str_radix(text.get(..2)?, 16).ok()
+}
+
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| hex_byte(&text[i..])).collect()
+}
+
+fn parse_addr_len(text: &str) -> Option<(u16, u16)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((u16::from_str_radix(addr, 16).ok()?, u16::from_str_radix(len, 16).ok()?))
+}
+
+// Registers in the order of the target description, with the flags in the
+// PSW layout
+
+fn registers<T: Debuggable>(target: &T) -> Vec<u8> {
+    let r = target.cpu().regs;
+    vec![
+        r.a,
+        target.cpu().flags.to_psw(),
+        r.b,
+        r.c,
+        r.d,
+        r.e,
+        r.h,
+        r.l,
+        (r.sp & 0xff) as u8,
+        (r.sp >> 8) as u8,
+        (r.pc & 0xff) as u8,
+        (r.pc >> 8) as u8,
+    ]
+}
+
+// Byte offset and size of register n in the g packet
+
+fn register_slot(n: usize) -> Option<(usize, usize)> {
+    match n {
+        0..=7 => Some((n, 1)),
+        8 => Some((8, 2)),
+        9 => Some((10, 2)),
+        _ => None,
+    }
+}
+
+fn set_registers<T: Debuggable>(target: &mut T, bytes: &[u8]) {
+    target.cpu_mut().flags = Flags::from_psw(bytes[1]);
+    let r = &mut target.cpu_mut().regs;
+    r.a = bytes[0];
+    r.b = bytes[2];
+    r.c = bytes[3];
+    r.d = bytes[4];
+    r.e = bytes[5];
+    r.h = bytes[6];
+    r.l = bytes[7];
+    r.sp = bytes[8] as u16 | (bytes[9] as u16) << 8;
+    r.pc = bytes[10] as u16 | (bytes[11] as u16) << 8;
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Session {
+    fn new(stream: TcpStream) -> Self {
+        Session {
+            stream,
+            pending: VecDeque::new(),
+            debugger: Debugger::new(),
+            no_ack: false,
+            last_reply: String::new(),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(b) = self.pending.pop_front() {
+            return Ok(Some(b));
+        }
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // Checks for a ^C without blocking, anything else read is kept for later
+
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0; 64];
+        let result = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(n) => {
+                let hit = buf[..n].contains(&0x03);
+                self.pending.extend(buf[..n].iter().filter(|&&b| b != 0x03));
+                Ok(hit)
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.last_reply = data.to_string();
+        write!(self.stream, "${}#{:02x}", data, checksum(data))?;
+        self.stream.flush()
+    }
+
+    fn run<T: Debuggable>(&mut self, target: &mut T) -> io::Result<()> {
+        loop {
+            let byte = match self.read_byte()? {
+                Some(b) => b,
+                None => return Ok(()),
+            };
+
+            match byte {
+                b'$' => {}
+                b'-' => {
+                    let last = self.last_reply.clone();
+                    self.send(&last)?;
+                    continue;
+                }
+                0x03 => {
+                    self.send("S02")?;
+                    continue;
+                }
+                _ => continue,
+            }
+
+            let mut packet = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(b) => packet.push(b),
+                    None => return Ok(()),
+                }
+            }
+            let sum = [self.read_byte()?.unwrap_or(0), self.read_byte()?.unwrap_or(0)];
+            let packet = String::from_utf8_lossy(&packet).to_string();
+
+            if !self.no_ack {
+                let ok = std::str::from_utf8(&sum).ok().and_then(hex_byte) == Some(checksum(&packet));
+                self.stream.write_all(if ok { b"+" } else { b"-" })?;
+                if !ok {
+                    continue;
+                }
+            }
+
+            match self.handle(target, &packet)? {
+                Some(reply) => self.send(&reply)?,
+                None => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Answers one packet, None ends the session
+
+    fn handle<T: Debuggable>(&mut self, target: &mut T, packet: &str) -> io::Result<Option<String>> {
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match cmd {
+            "?" => "S05".to_string(),
+            "g" => to_hex(&registers(target)),
+            "G" => match hex_bytes(args) {
+                Some(bytes) if bytes.len() >= 12 => {
+                    set_registers(target, &bytes);
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "p" => match usize::from_str_radix(args, 16).ok().and_then(register_slot) {
+                Some((offset, size)) => to_hex(&registers(target)[offset..offset + size]),
+                None => "E01".to_string(),
+            },
+            "P" => self.write_register(target, args),
+            "m" => self.read_memory(target, args),
+            "M" => self.write_memory(target, args),
+            "s" => {
+                self.jump_to(target, args);
+                let stop = self.debugger.step(target);
+                stop_reply(stop)
+            }
+            "c" => {
+                self.jump_to(target, args);
+                self.cont(target)?
+            }
+            "Z" | "z" => self.breakpoint(cmd == "Z", args),
+            "H" => "OK".to_string(),
+            "k" | "D" => return Ok(None),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+
+        Ok(Some(reply))
+    }
+
+    fn jump_to<T: Debuggable>(&self, target: &mut T, args: &str) {
+        if let Ok(addr) = u16::from_str_radix(args, 16) {
+            target.cpu_mut().regs.pc = addr;
+        }
+    }
+
+    fn cont<T: Debuggable>(&mut self, target: &mut T) -> io::Result<String> {
+        // The first chunk may start on a breakpoint, later ones must not skip one
+        let mut stop = self.debugger.run(target, CHUNK);
+        while stop == Stop::Limit {
+            if self.interrupted()? {
+                return Ok("S02".to_string());
+            }
+            let pc = target.cpu().regs.pc;
+            if self.debugger.breakpoints.contains(&pc) {
+                return Ok(stop_reply(Stop::Breakpoint(pc)));
+            }
+            stop = self.debugger.run(target, CHUNK);
+        }
+        Ok(stop_reply(stop))
+    }
+
+    fn write_register<T: Debuggable>(&self, target: &mut T, args: &str) -> String {
+        let parsed = args.split_once('=').and_then(|(n, v)| {
+            let slot = register_slot(usize::from_str_radix(n, 16).ok()?)?;
+            Some((slot, hex_bytes(v)?))
+        });
+
+        match parsed {
+            Some(((offset, size), value)) if value.len() == size => {
+                let mut regs = registers(target);
+                regs[offset..offset + size].copy_from_slice(&value);
+                set_registers(target, &regs);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_memory<T: Debuggable>(&self, target: &T, args: &str) -> String {
+        let memory = &target.cpu().memory;
+        match parse_addr_len(args) {
+            Some((addr, len)) if addr as usize + len as usize <= memory.len() => {
+                to_hex(&memory[addr as usize..addr as usize + len as usize])
+            }
+            _ => "E14".to_string(),
+        }
+    }
+
+    fn write_memory<T: Debuggable>(&self, target: &mut T, args: &str) -> String {
+        let parsed = args
+            .split_once(':')
+            .and_then(|(range, data)| Some((parse_addr_len(range)?, hex_bytes(data)?)));
+        let memory = &mut target.cpu_mut().memory;
+
+        match parsed {
+            Some(((addr, len), data)) if data.len() == len as usize && addr as usize + data.len() <= memory.len() => {
+                memory[addr as usize..addr as usize + data.len()].copy_from_slice(&data);
+                "OK".to_string()
+            }
+            _ => "E14".to_string(),
+        }
+    }
+
+    // Z0 breakpoints, Z2/Z3/Z4 write, read and access watchpoints
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        let kind = parts.next();
+        let addr = parts.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+        let len = parts.next().and_then(|l| u16::from_str_radix(l, 16).ok()).unwrap_or(1);
+
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return "E01".to_string(),
+        };
+
+        let access = match kind {
+            Some("0") | Some("1") => {
+                self.debugger.breakpoints.retain(|&b| b != addr);
+                if insert {
+                    self.debugger.breakpoints.push(addr);
+                }
+                return "OK".to_string();
+            }
+            Some("2") => Access::Write,
+            Some("3") => Access::Read,
+            Some("4") => Access::ReadWrite,
+            _ => return String::new(),
+        };
+
+        let watch = Watchpoint {
+            start: addr,
+            end: addr.saturating_add(len.max(1) - 1),
+            access,
+        };
+        self.debugger.watchpoints.retain(|&w| w != watch);
+        if insert {
+            self.debugger.watchpoints.push(watch);
+        }
+        "OK".to_string()
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            return "OK".to_string();
+        }
+        if packet == "qAttached" {
+            return "1".to_string();
+        }
+        if packet == "qC" {
+            return "QC1".to_string();
+        }
+        if packet == "qfThreadInfo" {
+            return "m1".to_string();
+        }
+        if packet == "qsThreadInfo" {
+            return "l".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_addr_len(range) {
+                Some((offset, len)) => {
+                    let start = (offset as usize).min(TARGET_XML.len());
+                    let end = (start + len as usize).min(TARGET_XML.len());
+                    let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                    format!("{}{}", marker, &TARGET_XML[start..end])
+                }
+                None => "E01".to_string(),
+            };
+        }
+        String::new()
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Watch { addr, access } => {
+            let kind = match access {
+                Access::Write => "watch",
+                Access::Read => "rwatch",
+                Access::ReadWrite => "awatch",
+            };
+            format!("T05{}:{:04x};", kind, addr)
+        }
+        // SIGILL for a bad opcode, SIGSEGV for anything else the machine rejects
+        Stop::Fault(EmuError::IllegalOpcode { .. } | EmuError::UndocumentedOpcode { .. }) => "S04".to_string(),
+        Stop::Fault(_) => "S0b".to_string(),
+        _ => "S05".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod gdb_test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+
+    // Plays the client side, sends packets and reads back the replies
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            write!(self.writer, "${}#{:02x}", data, checksum(data)).unwrap();
+            let mut ack = [0];
+            self.reader.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+
+            let mut reply = Vec::new();
+            self.reader.read_until(b'$', &mut reply).unwrap();
+            reply.clear();
+            self.reader.read_until(b'#', &mut reply).unwrap();
+            let mut sum = [0; 2];
+            self.reader.read_exact(&mut sum).unwrap();
+
+            reply.pop();
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    fn session(prog: &'static [u8], script: impl FnOnce(&mut Client) + Send + 'static) -> Cpu {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut client = Client {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+            };
+            script(&mut client);
+            assert_eq!(client.request("D"), "OK");
+        });
+
+        let mut cpu = Cpu::init(0, prog);
+        cpu.regs.sp = 0x2400;
+        serve(&mut cpu, &listener).unwrap();
+        client.join().unwrap();
+        cpu
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let cpu = session(&[0x3E, 0x42, 0x76], |c| {
+            assert_eq!(c.request("s"), "S05");
+            assert_eq!(c.request("g"), "420200000000000000240200");
+            assert_eq!(c.request("m0,3"), "3e4276");
+            assert_eq!(c.request("M2000,2:beef"), "OK");
+            assert_eq!(c.request("P9=0001"), "OK");
+            assert_eq!(c.request("p9"), "0001");
+        });
+        assert_eq!(cpu.regs.a, 0x42);
+        assert_eq!(cpu.regs.pc, 0x0100);
+        assert_eq!(cpu.memory[0x2000..0x2002], [0xBE, 0xEF]);
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        // NOP; LXI H, 0x2010; MVI M, 7; NOP; HLT
+        let cpu = session(&[0x00, 0x21, 0x10, 0x20, 0x36, 0x07, 0x00, 0x76], |c| {
+            assert_eq!(c.request("Z0,4,1"), "OK");
+            assert_eq!(c.request("c"), "S05");
+            assert_eq!(c.request("p9"), "0400");
+            assert_eq!(c.request("z0,4,1"), "OK");
+            assert_eq!(c.request("Z2,2010,1"), "OK");
+            assert_eq!(c.request("c0"), "T05watch:2010;");
+            assert_eq!(c.request("p9"), "0600");
+        });
+        assert_eq!(cpu.memory[0x2010], 7);
+    }
+
+    #[test]
+    fn target_description() {
+        session(&[0x76], |c| {
+            assert!(c.request("qSupported:xmlRegisters=i386").contains("qXfer:features:read+"));
+            let xml = c.request("qXfer:features:read:target.xml:0,fff");
+            assert!(xml.starts_with("l<?xml"));
+            assert!(xml.contains(r#"<reg name="pc" bitsize="16""#));
+        });
+    }
+}
@@ -0,0 +1,17 @@
+// Intel 8080 emulator and Space Invaders cabinet
+//
+// The binary in main.rs is a thin front end over this crate, everything it
+// does is available to other crates and to the integration tests.
+
+pub mod config;
+pub mod cpu;
+pub mod debugger;
+mod error;
+pub mod frontend;
+pub mod gdb;
+pub mod platform;
+
+pub use error::EmuError;
+
+#[cfg(test)]
+mod asm;
@@ -1,11 +1,251 @@
-mod platform;
+mod cli;
+
+use cli::{Args, Command};
+use space_invaders::config::{Config, Settings};
+use space_invaders::cpu::disasm::disassemble;
+use space_invaders::debugger::repl::Repl;
+use space_invaders::frontend::crt::Crt;
+use space_invaders::frontend::terminal::Terminal;
+use space_invaders::frontend::{png, HeldKeys, Keyboard, NullVideo, Video, VideoBackend};
+use space_invaders::gdb;
+use space_invaders::platform::movie::Movie;
+use space_invaders::platform::video::{HEIGHT, WIDTH};
+use space_invaders::platform::InvadersCabinet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::net::TcpListener;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
-// mod cpu;
-// mod flags;
-mod cpu;
 fn main() {
-    let mut invaders = platform::InvadersCabinet::init(None);
-    loop {
-        invaders.emulate_cycle();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args = match cli::parse(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(&args) {
+        // Piping a listing into head isn't an error
+        if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe) {
+            return;
+        }
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    match args.command {
+        Command::Help => println!("{}", cli::USAGE),
+        Command::Run => match args.video {
+            VideoBackend::Terminal => {
+                let settings = settings(args)?;
+                let mut terminal = Terminal::open()?;
+                terminal.set_crt(settings.terminal_crt);
+                terminal.set_scale(settings.filter, settings.terminal_scale as usize);
+                play(args, &settings, &mut terminal)?
+            }
+            VideoBackend::None => play(args, &settings(args)?, &mut NullVideo)?,
+        },
+        Command::Headless => play(args, &settings(args)?, &mut NullVideo)?,
+        Command::Cpm => cpm(args)?,
+        Command::Disasm => disasm(args)?,
+        Command::Debug => debug(args, &settings(args)?)?,
+        Command::Trace => trace(args, &settings(args)?)?,
+    }
+    Ok(())
+}
+
+// Config file settings for the ROM being run, with the command line on top
+
+fn settings(args: &Args) -> Result<Settings, Box<dyn Error>> {
+    let config = Config::load(args.config.as_deref()).map_err(|e| match &args.config {
+        Some(path) => format!("can't read config {}: {}", path.display(), e),
+        None => format!("bad config file: {}", e),
+    })?;
+    let rom = match &args.path {
+        Some(path) => path.to_string_lossy().to_string(),
+        None => config.rom()?,
+    };
+
+    let mut settings = config.settings(&rom)?;
+    if let Some(preset) = args.preset() {
+        config.preset(preset, &mut settings)?;
+    }
+    args.apply(&mut settings)?;
+    Ok(settings)
+}
+
+fn cabinet(settings: &Settings) -> Result<InvadersCabinet, Box<dyn Error>> {
+    let mut cabinet = InvadersCabinet::init_machine(settings.machine, Some(settings.rom.clone()))?;
+    cabinet.set_dips(&settings.dips);
+    cabinet.cpu_mut().strict = settings.strict;
+    cabinet.watchdog = settings.watchdog;
+    cabinet.latch_scanlines(settings.beam);
+    Ok(cabinet)
+}
+
+// Runs the game a frame at a time, with input from the keyboard or a movie
+
+fn play<T: Video + Keyboard>(args: &Args, settings: &Settings, screen: &mut T) -> Result<(), Box<dyn Error>> {
+    let mut cabinet = cabinet(settings)?;
+    let mut audio = args.audio.open(settings.volume);
+    let playback = args.playback.as_deref().map(Movie::load).transpose()?;
+    let mut recording = Movie::default();
+
+    // Phosphor decay has to see every frame, the rest only goes on the
+    // screenshot
+    let mut crt = Crt::new(settings.screenshot_crt);
+    let decay = args.screenshot.is_some() && crt.settings.persistence > 0;
+    let mut decayed = None;
+
+    let mut keys = HeldKeys::default();
+    let throttle = args.command == Command::Run;
+    let frame_time = Duration::from_secs_f64(1.0 / 60.0 / args.speed);
+    let mut next_frame = Instant::now();
+
+    while args.frames.is_none_or(|n| cabinet.frames < n) {
+        let (held, quit) = keys.update(&screen.poll()?, &settings.keys);
+        if quit {
+            break;
+        }
+        let input = match &playback {
+            Some(movie) => movie.input(cabinet.frames),
+            None => held,
+        };
+        recording.frames.push(input);
+        cabinet.set_input(&input);
+
+        let before = cabinet.sound_latches();
+        cabinet.run_frame()?;
+        audio.play(&cabinet.sounds(before));
+        for reset in cabinet.take_watchdog_resets() {
+            eprintln!("watchdog: {}", reset);
+        }
+        screen.present(&cabinet.frame(), settings.overlay)?;
+        if decay {
+            let mut rgb = cabinet.frame().to_rgb(settings.overlay);
+            crt.decay(&mut rgb);
+            decayed = Some(rgb);
+        }
+
+        if throttle {
+            next_frame += frame_time;
+            match next_frame.checked_duration_since(Instant::now()) {
+                Some(wait) => thread::sleep(wait),
+                None => next_frame = Instant::now(),
+            }
+        }
+    }
+
+    if let Some(path) = &args.record {
+        recording.save(path)?;
+    }
+    if let Some(path) = &args.screenshot {
+        let factor = settings.scale as usize;
+        let rgb = decayed.unwrap_or_else(|| cabinet.frame().to_rgb(settings.overlay));
+        let mut rgb = settings.filter.scale(&rgb, WIDTH, HEIGHT, factor);
+        crt.finish(&mut rgb, WIDTH * factor, HEIGHT * factor, factor);
+        fs::write(path, png::encode_rgb(WIDTH * factor, HEIGHT * factor, &rgb))?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "cpm")]
+fn cpm(args: &Args) -> Result<(), Box<dyn Error>> {
+    use space_invaders::cpu::Cpu;
+
+    let path = args.path.as_deref().ok_or("cpm needs a program")?;
+    let mut cpu = Cpu::init_cpm(&fs::read(path)?);
+    cpu.variant = args.cpu;
+
+    let mut settings = Settings::default();
+    args.apply(&mut settings)?;
+    cpu.strict = settings.strict;
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        cpu.cpm.dir = dir.to_path_buf();
+    }
+
+    while !cpu.hlted {
+        cpu.step()?;
+    }
+    println!();
+    Ok(())
+}
+
+#[cfg(not(feature = "cpm"))]
+fn cpm(_args: &Args) -> Result<(), Box<dyn Error>> {
+    Err("built without the cpm feature".into())
+}
+
+fn disasm(args: &Args) -> Result<(), Box<dyn Error>> {
+    let path = args.path.as_deref().ok_or("disasm needs a file")?;
+    let bytes = fs::read(path)?;
+    let end = args.org as usize + bytes.len();
+    if end > 0x10000 {
+        return Err("program runs past the end of memory".into());
+    }
+
+    let mut memory = vec![0; end];
+    memory[args.org as usize..].copy_from_slice(&bytes);
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut addr = args.org as usize;
+    while addr < end {
+        let (text, len) = disassemble(&memory, addr as u16);
+        let raw: Vec<String> = memory[addr..(addr + len as usize).min(end)]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        writeln!(out, "{:04X}  {:<9} {}", addr, raw.join(" "), text)?;
+        addr += len as usize;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn debug(args: &Args, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    let mut cabinet = cabinet(settings)?;
+
+    match args.gdb {
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            println!("waiting for gdb on 127.0.0.1:{}", port);
+            gdb::serve(&mut cabinet, &listener)?;
+        }
+        None => Repl::new().run(&mut cabinet, io::stdin().lock(), &mut io::stdout())?,
+    }
+    Ok(())
+}
+
+// Prints each instruction before it runs along with the registers
+
+fn trace(args: &Args, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    let mut cabinet = cabinet(settings)?;
+    let frames = args.frames.unwrap_or(1);
+    let mut count = 0;
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    while cabinet.frames < frames && args.limit.is_none_or(|n| count < n) {
+        let cpu = cabinet.cpu();
+        let r = cpu.regs;
+        if !cpu.hlted {
+            let (text, _) = disassemble(&cpu.memory, r.pc);
+            writeln!(
+                out,
+                "{:04X}  {:<16} A={:02X} BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X} SP={:04X}",
+                r.pc, text, r.a, r.b, r.c, r.d, r.e, r.h, r.l, r.sp
+            )?;
+            count += 1;
+        }
+        cabinet.step()?;
     }
-}
\ No newline at end of file
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,192 @@
+// Runs many headless cabinets at once, for training agents and regression
+// sweeps
+//
+// The cabinets are split evenly over a fixed number of scoped threads, each
+// gets its input from its own stream on every frame.
+
+use super::movie::Movie;
+use super::{Frame, InvadersCabinet, InvadersInput};
+use crate::EmuError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Where a cabinet's input comes from, asked once before each frame
+
+pub trait InputStream: Send {
+    fn input(&mut self, frame: u64, cabinet: &InvadersCabinet) -> InvadersInput;
+}
+
+impl InputStream for Movie {
+    fn input(&mut self, frame: u64, _: &InvadersCabinet) -> InvadersInput {
+        Movie::input(self, frame)
+    }
+}
+
+impl<F: FnMut(u64, &InvadersCabinet) -> InvadersInput + Send> InputStream for F {
+    fn input(&mut self, frame: u64, cabinet: &InvadersCabinet) -> InvadersInput {
+        self(frame, cabinet)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    // Frames and instructions run by the last call to Batch::run
+    pub frames: u64,
+    pub instructions: usize,
+    pub elapsed: Duration,
+}
+
+// A cabinet's screen after a run and what it took to get there
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub frame: Frame,
+    pub stats: Stats,
+}
+
+struct Instance {
+    cabinet: InvadersCabinet,
+    input: Box<dyn InputStream>,
+}
+
+pub struct Batch {
+    instances: Vec<Instance>,
+    threads: usize,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Batch::new(threads)
+    }
+}
+
+impl Batch {
+    pub fn new(threads: usize) -> Batch {
+        Batch {
+            instances: Vec::new(),
+            threads: threads.max(1),
+        }
+    }
+
+    pub fn push(&mut self, cabinet: InvadersCabinet, input: impl InputStream + 'static) {
+        self.instances.push(Instance { cabinet, input: Box::new(input) });
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn cabinet(&self, n: usize) -> &InvadersCabinet {
+        &self.instances[n].cabinet
+    }
+
+    pub fn cabinet_mut(&mut self, n: usize) -> &mut InvadersCabinet {
+        &mut self.instances[n].cabinet
+    }
+
+    // Runs every cabinet for frames more frames, results are in the order
+    // the cabinets were pushed. A cabinet that fails stops there, the others
+    // carry on
+
+    pub fn run(&mut self, frames: u64) -> Vec<Result<Outcome, EmuError>> {
+        let chunk = self.instances.len().div_ceil(self.threads).max(1);
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = self
+                .instances
+                .chunks_mut(chunk)
+                .map(|chunk| scope.spawn(move || chunk.iter_mut().map(|i| i.run(frames)).collect::<Vec<_>>()))
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("batch worker panicked"))
+                .collect()
+        })
+    }
+}
+
+impl Instance {
+    fn run(&mut self, frames: u64) -> Result<Outcome, EmuError> {
+        let start = Instant::now();
+        let instructions = self.cabinet.cpu().instructions;
+        let first = self.cabinet.frames;
+
+        while self.cabinet.frames - first < frames {
+            let input = self.input.input(self.cabinet.frames, &self.cabinet);
+            self.cabinet.set_input(&input);
+            self.cabinet.run_frame()?;
+        }
+
+        Ok(Outcome {
+            frame: self.cabinet.frame(),
+            stats: Stats {
+                frames,
+                instructions: self.cabinet.cpu().instructions - instructions,
+                elapsed: start.elapsed(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod batch_test {
+    use super::*;
+
+    // IN 1; STA 0x2400; EI in a loop, both interrupt handlers are EI; RET
+    fn cabinet() -> InvadersCabinet {
+        let mut cabinet = InvadersCabinet::init(None).unwrap();
+        let memory = &mut cabinet.cpu_mut().memory;
+        memory[..3].copy_from_slice(&[0xC3, 0x20, 0x00]);
+        memory[0x08..0x0A].copy_from_slice(&[0xFB, 0xC9]);
+        memory[0x10..0x12].copy_from_slice(&[0xFB, 0xC9]);
+        memory[0x20..0x29].copy_from_slice(&[0xDB, 0x01, 0x32, 0x00, 0x24, 0xFB, 0xC3, 0x20, 0x00]);
+        cabinet.cpu_mut().regs.sp = 0x2400;
+        cabinet
+    }
+
+    #[test]
+    fn runs_each_with_its_own_input() {
+        let mut batch = Batch::new(3);
+        for n in 0..7 {
+            let movie = Movie { frames: vec![InvadersInput::from_bits(1 << (n % 3)); 4] };
+            batch.push(cabinet(), movie);
+        }
+        batch.push(cabinet(), |_: u64, _: &InvadersCabinet| InvadersInput { p1_fire: true, ..Default::default() });
+
+        let results = batch.run(2);
+        assert_eq!(results.len(), 8);
+        for (n, result) in results.iter().enumerate() {
+            let outcome = result.as_ref().unwrap();
+            assert_eq!(outcome.stats.frames, 2);
+            assert!(outcome.stats.instructions > 0);
+            assert_eq!(batch.cabinet(n).frames, 2);
+            let port = if n == 7 { 0x18 } else { [0x09, 0x0C, 0x0A][n % 3] };
+            assert_eq!(outcome.frame.vram[0], port, "cabinet {}", n);
+        }
+
+        // Port 1 reads 0x08 once the movies run out
+        assert_eq!(batch.run(3)[0].as_ref().unwrap().frame.vram[0], 0x08);
+        assert_eq!(batch.cabinet(0).frames, 5);
+    }
+
+    #[test]
+    fn failures_stay_with_their_cabinet() {
+        let mut batch = Batch::new(2);
+        let mut bad = cabinet();
+        // RIM, undocumented on the 8080 so strict mode stops on it
+        bad.cpu_mut().memory[0x20] = 0x20;
+        bad.cpu_mut().strict = true;
+        batch.push(cabinet(), Movie::default());
+        batch.push(bad, Movie::default());
+
+        let results = batch.run(1);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+}
@@ -0,0 +1,293 @@
+// Gym style environment for training agents on the game without a window
+//
+// reset puts a coin in and starts a one player game, step holds an action
+// for a few frames and reports the score gained, done is set once the game
+// is no longer being played.
+
+use super::state::{GameMode, InvadersState};
+use super::video::{Frame, HEIGHT, WIDTH};
+use super::{Button, Dips, InvadersCabinet, InvadersInput};
+use crate::EmuError;
+
+// Frames for the game to clear RAM and reach the attract screen
+const BOOT_FRAMES: u64 = 60;
+// Frames each button of the coin and start sequence is held and released
+const PRESS_FRAMES: u64 = 8;
+// Upper bound on frames between pressing start and the game running
+const START_FRAMES: u64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionSet {
+    // Nothing, fire, left, right, left and fire, right and fire for player one
+    Minimal,
+    // Nothing or any single cabinet button
+    Full,
+}
+
+impl ActionSet {
+    pub fn actions(self) -> Vec<InvadersInput> {
+        let press = |buttons: &[Button]| {
+            let mut input = InvadersInput::default();
+            buttons.iter().for_each(|&b| input.set(b, true));
+            input
+        };
+
+        match self {
+            ActionSet::Minimal => [
+                &[][..],
+                &[Button::P1Fire],
+                &[Button::P1Left],
+                &[Button::P1Right],
+                &[Button::P1Left, Button::P1Fire],
+                &[Button::P1Right, Button::P1Fire],
+            ]
+            .iter()
+            .map(|buttons| press(buttons))
+            .collect(),
+            ActionSet::Full => std::iter::once(InvadersInput::default())
+                .chain(Button::ALL.iter().map(|&b| press(&[b])))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvConfig {
+    pub actions: ActionSet,
+    // Frames each action is held for, at least 1
+    pub frame_skip: u32,
+    // Observations are shrunk by this factor in both directions, 1 to 8
+    pub downsample: usize,
+    // Most idle frames the seed can add before the coin goes in
+    pub noop_max: u32,
+    pub dips: Dips,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        EnvConfig {
+            actions: ActionSet::Minimal,
+            frame_skip: 4,
+            downsample: 2,
+            noop_max: 30,
+            dips: Dips::default(),
+        }
+    }
+}
+
+pub struct InvadersEnv {
+    config: EnvConfig,
+    actions: Vec<InvadersInput>,
+    cabinet: InvadersCabinet,
+    // Score at the last step, rewards are the difference
+    score: u32,
+}
+
+// xorshift64, enough to spread out the start times
+
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// The score is four BCD digits and rolls over from 9999 to 0, so points
+// gained are counted modulo 10000
+
+fn score_gained(before: u32, after: u32) -> u32 {
+    (after + 10000 - before % 10000) % 10000
+}
+
+// Averages each factor by factor block of the screen into one gray byte, row
+// by row from the top
+
+pub fn downsample(frame: &Frame, factor: usize) -> Vec<u8> {
+    let factor = factor.clamp(1, 8);
+    let (width, height) = (WIDTH / factor, HEIGHT / factor);
+    let mut out = Vec::with_capacity(width * height);
+
+    for by in 0..height {
+        for bx in 0..width {
+            let mut lit = 0;
+            for y in by * factor..(by + 1) * factor {
+                for x in bx * factor..(bx + 1) * factor {
+                    lit += frame.pixel(x, y) as usize;
+                }
+            }
+            out.push((lit * 0xFF / (factor * factor)) as u8);
+        }
+    }
+    out
+}
+
+impl InvadersEnv {
+    // rom is loaded once, as by InvadersCabinet::init
+
+    pub fn init(rom: Option<String>, config: EnvConfig) -> Result<Self, EmuError> {
+        Ok(InvadersEnv {
+            cabinet: InvadersCabinet::init(rom)?,
+            actions: config.actions.actions(),
+            config,
+            score: 0,
+        })
+    }
+
+    pub fn actions(&self) -> &[InvadersInput] {
+        &self.actions
+    }
+
+    // Width and height of an observation
+
+    pub fn observation_shape(&self) -> (usize, usize) {
+        let factor = self.config.downsample.clamp(1, 8);
+        (WIDTH / factor, HEIGHT / factor)
+    }
+
+    pub fn observe(&self) -> Vec<u8> {
+        downsample(&self.cabinet.frame(), self.config.downsample)
+    }
+
+    pub fn cabinet(&self) -> &InvadersCabinet {
+        &self.cabinet
+    }
+
+    fn hold(&mut self, input: InvadersInput, frames: u64) -> Result<(), EmuError> {
+        self.cabinet.set_input(&input);
+        for _ in 0..frames {
+            self.cabinet.run_frame()?;
+        }
+        Ok(())
+    }
+
+    // Powers the cabinet back on and starts a game. The game itself is
+    // deterministic, the seed only picks how long it idles before the coin.
+    // If the game isn't being played START_FRAMES after start is pressed
+    // that's an error, the cabinet is left where it got to
+
+    pub fn reset(&mut self, seed: u64) -> Result<Vec<u8>, EmuError> {
+        self.cabinet.reset()?;
+        self.cabinet.set_dips(&self.config.dips);
+
+        // xorshift gets stuck on 0
+        let mut random = seed | 1;
+        let idle = next_random(&mut random) % (self.config.noop_max as u64 + 1);
+
+        let none = InvadersInput::default();
+        self.hold(none, BOOT_FRAMES + idle)?;
+        self.hold(InvadersInput { coin: true, ..none }, PRESS_FRAMES)?;
+        self.hold(none, PRESS_FRAMES)?;
+        self.hold(InvadersInput { p1_start: true, ..none }, PRESS_FRAMES)?;
+        self.cabinet.set_input(&none);
+
+        let mut frames = 0;
+        while self.cabinet.state().mode != GameMode::Playing {
+            if frames == START_FRAMES {
+                return Err(EmuError::GameNotStarted { frames });
+            }
+            self.cabinet.run_frame()?;
+            frames += 1;
+        }
+
+        self.score = self.cabinet.state().score();
+        Ok(self.observe())
+    }
+
+    // Holds actions()[action] for frame_skip frames, the reward is the
+    // score gained meanwhile. An action past the end of the set is an error
+
+    pub fn step(&mut self, action: usize) -> Result<(Vec<u8>, f32, bool, InvadersState), EmuError> {
+        let actions = self.actions.len();
+        let input = *self.actions.get(action).ok_or(EmuError::BadAction { action, actions })?;
+        self.hold(input, self.config.frame_skip.max(1) as u64)?;
+
+        let state = self.cabinet.state();
+        let reward = score_gained(self.score, state.score()) as f32;
+        self.score = state.score();
+        let done = state.mode != GameMode::Playing;
+        Ok((self.observe(), reward, done, state))
+    }
+}
+
+#[cfg(test)]
+mod env_test {
+    use super::*;
+    use crate::platform::video::{VRAM_END, VRAM_START};
+
+    #[test]
+    fn action_sets() {
+        let minimal = ActionSet::Minimal.actions();
+        assert_eq!(minimal.len(), 6);
+        assert_eq!(minimal[0], InvadersInput::default());
+        assert_eq!(minimal[5], InvadersInput { p1_right: true, p1_fire: true, ..Default::default() });
+
+        let full = ActionSet::Full.actions();
+        assert_eq!(full.len(), Button::ALL.len() + 1);
+        assert!(full[1..].iter().all(|input| input.bits().count_ones() == 1));
+    }
+
+    #[test]
+    fn downsamples_frames() {
+        let mut vram = vec![0; VRAM_END - VRAM_START];
+        // Bottom left 8 pixels of the first two columns
+        vram[0] = 0xFF;
+        vram[HEIGHT / 8] = 0xFF;
+        let frame = Frame::from_vram(&vram);
+
+        assert_eq!(downsample(&frame, 1), frame.to_gray());
+        let small = downsample(&frame, 4);
+        assert_eq!(small.len(), (WIDTH / 4) * (HEIGHT / 4));
+        let bottom_left = (HEIGHT / 4 - 1) * (WIDTH / 4);
+        assert_eq!(small[bottom_left], 0x7F);
+        assert_eq!(small[bottom_left - WIDTH / 4], 0x7F);
+        assert_eq!(small.iter().filter(|&&p| p != 0).count(), 2);
+    }
+
+    #[test]
+    fn score_rolls_over() {
+        assert_eq!(score_gained(0, 30), 30);
+        assert_eq!(score_gained(120, 120), 0);
+        assert_eq!(score_gained(9990, 20), 30);
+        assert_eq!(score_gained(9999, 0), 1);
+    }
+
+    #[test]
+    fn reset_and_step_without_rom() {
+        let config = EnvConfig { noop_max: 0, frame_skip: 2, ..Default::default() };
+        let mut env = InvadersEnv::init(None, config).unwrap();
+        let (width, height) = env.observation_shape();
+        // Nothing runs the game, so it never leaves attract mode
+        assert_eq!(env.reset(7).unwrap_err(), EmuError::GameNotStarted { frames: START_FRAMES });
+        assert_eq!(env.observe().len(), width * height);
+
+        let frames = env.cabinet().frames;
+        let (observation, reward, done, state) = env.step(1).unwrap();
+        assert_eq!(observation.len(), width * height);
+        assert_eq!(env.cabinet().frames, frames + 2);
+        assert_eq!((reward, done, state.mode), (0.0, true, GameMode::Attract));
+
+        let frames = env.cabinet().frames;
+        assert_eq!(env.step(6).unwrap_err(), EmuError::BadAction { action: 6, actions: 6 });
+        assert_eq!(env.cabinet().frames, frames);
+    }
+
+    #[test]
+    #[ignore = "needs the invaders ROM in roms/"]
+    fn plays_with_rom() {
+        let roms = concat!(env!("CARGO_MANIFEST_DIR"), "/roms").to_string();
+        let mut env = InvadersEnv::init(Some(roms), EnvConfig::default()).unwrap();
+        env.reset(1).unwrap();
+        assert_eq!(env.cabinet().state().mode, GameMode::Playing);
+
+        // Firing from the start position hits the invaders straight above
+        let mut score = 0.0;
+        for _ in 0..1000 {
+            let (_, reward, done, _) = env.step(1).unwrap();
+            score += reward;
+            if score > 0.0 || done {
+                break;
+            }
+        }
+        assert!(score > 0.0, "no points after firing for 4000 frames");
+    }
+}
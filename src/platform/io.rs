@@ -1,59 +1,411 @@
-#[derive(Default)]
+use super::machine::{MachineDescription, INVADERS};
+use crate::EmuError;
+
+// Cabinet controls, each field is true while held down
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InvadersInput {
+    pub coin: bool,
+    pub p1_start: bool,
+    pub p2_start: bool,
+    pub p1_fire: bool,
+    pub p1_left: bool,
+    pub p1_right: bool,
+    pub p2_fire: bool,
+    pub p2_left: bool,
+    pub p2_right: bool,
+    pub tilt: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Coin,
+    P1Start,
+    P2Start,
+    P1Fire,
+    P1Left,
+    P1Right,
+    P2Fire,
+    P2Left,
+    P2Right,
+    Tilt,
+}
+
+impl Button {
+    pub const ALL: [Button; 10] = [
+        Button::Coin,
+        Button::P1Start,
+        Button::P2Start,
+        Button::P1Fire,
+        Button::P1Left,
+        Button::P1Right,
+        Button::P2Fire,
+        Button::P2Left,
+        Button::P2Right,
+        Button::Tilt,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::Coin => "coin",
+            Button::P1Start => "p1_start",
+            Button::P2Start => "p2_start",
+            Button::P1Fire => "p1_fire",
+            Button::P1Left => "p1_left",
+            Button::P1Right => "p1_right",
+            Button::P2Fire => "p2_fire",
+            Button::P2Left => "p2_left",
+            Button::P2Right => "p2_right",
+            Button::Tilt => "tilt",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Button> {
+        Button::ALL.iter().copied().find(|b| b.name() == name)
+    }
+}
+
+impl InvadersInput {
+    fn field(&mut self, button: Button) -> &mut bool {
+        match button {
+            Button::Coin => &mut self.coin,
+            Button::P1Start => &mut self.p1_start,
+            Button::P2Start => &mut self.p2_start,
+            Button::P1Fire => &mut self.p1_fire,
+            Button::P1Left => &mut self.p1_left,
+            Button::P1Right => &mut self.p1_right,
+            Button::P2Fire => &mut self.p2_fire,
+            Button::P2Left => &mut self.p2_left,
+            Button::P2Right => &mut self.p2_right,
+            Button::Tilt => &mut self.tilt,
+        }
+    }
+
+    pub fn set(&mut self, button: Button, held: bool) {
+        *self.field(button) = held;
+    }
+
+    pub fn held(&self, button: Button) -> bool {
+        let mut input = *self;
+        *input.field(button)
+    }
+
+    // Packs the buttons into bits in the order of Button::ALL, used by movies
+
+    pub fn bits(&self) -> u16 {
+        Button::ALL
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (n, &b)| bits | (self.held(b) as u16) << n)
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        let mut input = InvadersInput::default();
+        for (n, &b) in Button::ALL.iter().enumerate() {
+            input.set(b, bits & (1 << n) != 0);
+        }
+        input
+    }
+}
+
+// Operator settings on the DIP switch bank, read through port 2
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dips {
+    // 3 to 6
+    pub ships: u8,
+    // Score for the bonus ship, 1000 or 1500
+    pub bonus_life: u16,
+    // Show the coin info on the attract screen
+    pub coin_info: bool,
+    // Part II and Deluxe, lets the operator enter the name shown with the
+    // high score
+    pub name_preset: bool,
+    // Cocktail table rather than upright, the screen turns round for
+    // player two
+    pub cocktail: bool,
+}
+
+impl Default for Dips {
+    fn default() -> Self {
+        Dips {
+            ships: 3,
+            bonus_life: 1500,
+            coin_info: true,
+            name_preset: false,
+            cocktail: false,
+        }
+    }
+}
+
+impl Dips {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(3..=6).contains(&self.ships) {
+            return Err(format!("ships must be 3 to 6, not {}", self.ships));
+        }
+        if self.bonus_life != 1000 && self.bonus_life != 1500 {
+            return Err(format!("bonus life must be 1000 or 1500, not {}", self.bonus_life));
+        }
+        Ok(())
+    }
+}
+
+// The ports wired up as the machine description says
+
+#[derive(Clone)]
 pub struct IO {
+    machine: &'static MachineDescription,
+
     shift_offset: u8,
     shift_reg: u16,
 
-    // read_ports: [u8; 3],
-    read_1: u8,
+    // Buttons and DIP switches as they read on each port
+    inputs: [u8; 256],
+    dips: [u8; 256],
+
+    // Sound latches, written by the game on the machine's two sound ports
+    pub sound_1: u8,
+    pub sound_2: u8,
+
+    // Flip bit as last written, it only turns the screen on cocktail cabinets
+    flip: bool,
+    cocktail: bool,
+    // Colour map select as last written, on boards with colour PROMs
+    color_map: bool,
+
+    // Set by a write to the watchdog port since the cabinet last looked
+    kicked: bool,
+}
+
+impl Default for IO {
+    fn default() -> Self {
+        IO::new(&INVADERS)
+    }
 }
 
 impl IO {
-    pub fn cpu_write(&mut self, port: u8, value: u8) {
-        match port {
-            2 => self.shift_offset = value & 0x7,
-            4 => self.shift_reg = (self.shift_reg >> 8) | (value as u16) << 8,
-            6 => {},
-            _ => unimplemented!(),
+    pub fn new(machine: &'static MachineDescription) -> Self {
+        IO {
+            machine,
+            shift_offset: 0,
+            shift_reg: 0,
+            inputs: [0; 256],
+            dips: [0; 256],
+            sound_1: 0,
+            sound_2: 0,
+            flip: false,
+            cocktail: false,
+            color_map: false,
+            kicked: false,
         }
     }
 
-    pub fn cpu_read(&self, port: u8) -> u8 {
+    pub fn machine(&self) -> &'static MachineDescription {
+        self.machine
+    }
+
+    pub fn cpu_write(&mut self, port: u8, value: u8) -> Result<(), EmuError> {
+        let m = self.machine;
+        // The flip bit shares its port with a sound latch on most boards
+        if let Some((_, bit)) = m.flip.filter(|&(p, _)| p == port) {
+            self.flip = value & (1 << bit) != 0;
+        }
+        if let Some((_, bit)) = m.color_map.filter(|&(p, _)| p == port) {
+            self.color_map = value & (1 << bit) != 0;
+        }
+
         match port {
-            1 => self.read_1,
-            _ => todo!(),
+            _ if port == m.shift.amount => self.shift_offset = value & 0x7,
+            _ if port == m.shift.data => self.shift_reg = (self.shift_reg >> 8) | (value as u16) << 8,
+            _ if port == m.sound_ports[0] => self.sound_1 = value,
+            _ if port == m.sound_ports[1] => self.sound_2 = value,
+            _ if Some(port) == m.watchdog => self.kicked = true,
+            _ if m.flip.is_some_and(|(p, _)| p == port) => {},
+            _ if m.color_map.is_some_and(|(p, _)| p == port) => {},
+            _ => return Err(EmuError::UnmappedPort { port, write: true }),
         }
+        Ok(())
     }
 
-    pub fn set_port_1(&mut self, bit: u8) {
-        self.read_1 |= 1 << bit;
+    pub fn cpu_read(&self, port: u8) -> Result<u8, EmuError> {
+        // Result of the shift register
+        if port == self.machine.shift.result {
+            return Ok(((self.shift_reg << self.shift_offset) >> 8) as u8);
+        }
+
+        match self.machine.ports.iter().find(|&&(p, _)| p == port) {
+            Some(&(_, high)) => Ok(high | self.inputs[port as usize] | self.dips[port as usize]),
+            None => Err(EmuError::UnmappedPort { port, write: false }),
+        }
     }
 
     pub fn insert_coin(&mut self) {
-        self.set_port_1(0);
+        self.set_button(Button::Coin, true);
+    }
+
+    fn set_button(&mut self, button: Button, held: bool) {
+        for input in self.machine.inputs.iter().filter(|i| i.button == button) {
+            let port = &mut self.inputs[input.port as usize];
+            *port = (*port & !(1 << input.bit)) | (held as u8) << input.bit;
+        }
+    }
+
+    pub fn set_dips(&mut self, dips: &Dips) {
+        let layout = &self.machine.dips;
+        self.dips[layout.port as usize] = layout.bits(dips);
+        self.cocktail = dips.cocktail;
+    }
+
+    // Whether the watchdog was kicked since the last call
+
+    pub fn take_kick(&mut self) -> bool {
+        std::mem::take(&mut self.kicked)
+    }
+
+    // Whether the screen is turned round for player two
+
+    pub fn flipped(&self) -> bool {
+        self.flip && self.cocktail
+    }
+
+    // Which of the colour PROMs the board is drawing from, 0 or 1
+
+    pub fn color_map(&self) -> usize {
+        self.color_map as usize
+    }
+
+    // Latches the controls onto their ports, the DIP switches are kept
+    // apart so they're left alone
+
+    pub fn set_input(&mut self, input: &InvadersInput) {
+        for button in Button::ALL {
+            self.set_button(button, input.held(button));
+        }
+    }
+
+    // Clears everything the game writes, as the board's reset line does. The
+    // controls and DIP switches aren't the game's so they stay as they are
+
+    pub fn reset(&mut self) {
+        *self = IO { inputs: self.inputs, dips: self.dips, cocktail: self.cocktail, ..IO::new(self.machine) };
     }
 }
 
 #[cfg(test)]
 mod io_test {
     use super::*;
+    use crate::platform::machine::INVADPT2;
 
     #[test]
     fn cpu_write_port_4() {
         let mut io = IO::default();
-        io.cpu_write(4, 0xAB);
+        io.cpu_write(4, 0xAB).unwrap();
         assert_eq!(io.shift_reg, 0xAB00);
 
-        io.cpu_write(4, 0xCD);
+        io.cpu_write(4, 0xCD).unwrap();
         assert_eq!(io.shift_reg, 0xCDAB);
     }
 
+    #[test]
+    fn cpu_read_shift_result() {
+        let mut io = IO::default();
+        io.cpu_write(4, 0xFF).unwrap();
+        io.cpu_write(4, 0x0F).unwrap();
+        io.cpu_write(2, 4).unwrap();
+
+        assert_eq!(io.cpu_read(3).unwrap(), 0xFF);
+
+        io.cpu_write(2, 0).unwrap();
+        assert_eq!(io.cpu_read(3).unwrap(), 0x0F);
+    }
+
     #[test]
     fn cpu_read_port_1() {
         let mut io = IO::default();
         io.insert_coin();
 
-        assert_eq!(io.read_1, 0b000001);
+        assert_eq!(io.inputs[1], 0b000001);
+    }
+
+    #[test]
+    fn set_input_ports() {
+        let mut io = IO::default();
+        io.dips[2] = 0x83;
+        io.set_input(&InvadersInput { p1_start: true, p1_fire: true, p2_left: true, ..Default::default() });
+
+        assert_eq!(io.cpu_read(1).unwrap(), 0x1C);
+        assert_eq!(io.cpu_read(2).unwrap(), 0xA3);
+
+        io.set_input(&InvadersInput::default());
+        assert_eq!(io.cpu_read(1).unwrap(), 0x08);
+        assert_eq!(io.cpu_read(2).unwrap(), 0x83);
+    }
+
+    #[test]
+    fn unmapped_ports() {
+        let mut io = IO::default();
+        assert_eq!(io.cpu_read(7), Err(EmuError::UnmappedPort { port: 7, write: false }));
+        assert_eq!(io.cpu_write(0, 1), Err(EmuError::UnmappedPort { port: 0, write: true }));
+    }
+
+    #[test]
+    fn cocktail_flip() {
+        let mut io = IO::default();
+        io.cpu_write(5, 0x21).unwrap();
+        assert_eq!(io.sound_2, 0x21);
+        assert!(!io.flipped());
+
+        io.set_dips(&Dips { cocktail: true, ..Default::default() });
+        assert!(io.flipped());
+        io.cpu_write(5, 0x01).unwrap();
+        assert!(!io.flipped());
+    }
+
+    #[test]
+    fn color_map_select() {
+        // Part II switches colour map with the flip bit, cocktail or not
+        let mut io = IO::new(&INVADPT2);
+        io.cpu_write(5, 0x20).unwrap();
+        assert_eq!((io.color_map(), io.flipped()), (1, false));
+        io.cpu_write(5, 0x00).unwrap();
+        assert_eq!(io.color_map(), 0);
+
+        let mut io = IO::default();
+        io.cpu_write(5, 0x20).unwrap();
+        assert_eq!(io.color_map(), 0);
+    }
+
+    #[test]
+    fn reset_keeps_dips_and_input() {
+        let mut io = IO::new(&INVADPT2);
+        io.set_dips(&Dips { ships: 4, cocktail: true, ..Default::default() });
+        io.set_input(&InvadersInput { p1_fire: true, ..Default::default() });
+        let (port_1, port_2) = (io.cpu_read(1).unwrap(), io.cpu_read(2).unwrap());
+        io.cpu_write(2, 3).unwrap();
+        io.cpu_write(4, 0xFF).unwrap();
+        io.cpu_write(3, 0x12).unwrap();
+        io.cpu_write(5, 0x21).unwrap();
+        io.cpu_write(6, 0).unwrap();
 
-        
+        io.reset();
+        assert_eq!((io.sound_1, io.sound_2), (0, 0));
+        assert_eq!(io.cpu_read(3).unwrap(), 0);
+        assert_eq!((io.flipped(), io.color_map(), io.take_kick()), (false, 0, false));
+        assert_eq!((io.cpu_read(1).unwrap(), io.cpu_read(2).unwrap()), (port_1, port_2));
+        io.cpu_write(5, 0x20).unwrap();
+        assert!(io.flipped());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn dips_and_input_bits() {
+        let mut io = IO::default();
+        io.set_dips(&Dips { ships: 5, bonus_life: 1000, coin_info: false, ..Default::default() });
+        io.set_input(&InvadersInput { tilt: true, ..Default::default() });
+        assert_eq!(io.cpu_read(2).unwrap(), 0x8E);
+
+        let input = InvadersInput { coin: true, p2_right: true, ..Default::default() };
+        assert_eq!(input.bits(), 0x101);
+        assert_eq!(InvadersInput::from_bits(0x101), input);
+        assert_eq!(Button::from_name("p2_right"), Some(Button::P2Right));
+    }
+}
@@ -0,0 +1,277 @@
+// The Midway/Taito 8080 boards, described as data
+//
+// Every game on the board shares the cpu, the bitmap and the shift register
+// but has its own ROM layout, input and DIP switch bits and sound ports.
+// InvadersCabinet::init_machine runs any of them.
+
+use super::io::{Button, Dips};
+use super::sound::{Sound, PORT_3, PORT_5};
+use super::video::Overlay;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RomRegion {
+    pub file: &'static str,
+    pub addr: u16,
+    pub size: usize,
+}
+
+// A button's bit on an input port, set while it's held
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InputBit {
+    pub button: Button,
+    pub port: u8,
+    pub bit: u8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DipLayout {
+    pub port: u8,
+    // ships - min_ships goes in ship_bits, from its lowest set bit up
+    pub ship_bits: u8,
+    pub min_ships: u8,
+    // Set for the bonus ship at 1000 points rather than 1500
+    pub bonus_1000: Option<u8>,
+    // Set to hide the coin info
+    pub coin_info_off: Option<u8>,
+    // Set to let the operator enter the high score name
+    pub name_preset: Option<u8>,
+}
+
+impl DipLayout {
+    pub fn bits(&self, dips: &Dips) -> u8 {
+        let shift = self.ship_bits.trailing_zeros();
+        let max = self.min_ships + (self.ship_bits >> shift);
+        let ships = (dips.ships.clamp(self.min_ships, max) - self.min_ships) << shift;
+        let bit = |bit: Option<u8>, on: bool| bit.map_or(0, |n| (on as u8) << n);
+
+        ships
+            | bit(self.bonus_1000, dips.bonus_life == 1000)
+            | bit(self.coin_info_off, !dips.coin_info)
+            | bit(self.name_preset, dips.name_preset)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ShiftPorts {
+    // Written with the shift amount and the next byte, read for the result
+    pub amount: u8,
+    pub data: u8,
+    pub result: u8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MachineDescription {
+    // Used for --machine and in the config
+    pub name: &'static str,
+    pub title: &'static str,
+    // A single file image holds the regions one after another
+    pub roms: &'static [RomRegion],
+    // The input ports the game reads, with the bits that are tied high
+    pub ports: &'static [(u8, u8)],
+    pub inputs: &'static [InputBit],
+    pub dips: DipLayout,
+    pub shift: ShiftPorts,
+    // Ports of the two sound latches and the effect each bit starts
+    pub sound_ports: [u8; 2],
+    pub sounds: [&'static [Sound]; 2],
+    // Writes here kick the watchdog
+    pub watchdog: Option<u8>,
+    // Port and bit written to turn the screen round for player two, only
+    // wired up on cocktail cabinets
+    pub flip: Option<(u8, u8)>,
+    pub overlay: Overlay,
+    // Colour PROMs on colour boards, a colour map each. They come after the
+    // ROMs in a single file image and addr is the offset into the maps
+    pub proms: &'static [RomRegion],
+    // Port and bit that switch to the second colour map
+    pub color_map: Option<(u8, u8)>,
+}
+
+impl MachineDescription {
+    // Size of a single file image
+
+    pub fn rom_size(&self) -> usize {
+        self.roms.iter().chain(self.proms).map(|r| r.size).sum()
+    }
+}
+
+// Coin and starts on port 1, player one there too and player two on port 2
+// with the tilt switch
+const UPRIGHT_INPUTS: [InputBit; 10] = [
+    InputBit { button: Button::Coin, port: 1, bit: 0 },
+    InputBit { button: Button::P2Start, port: 1, bit: 1 },
+    InputBit { button: Button::P1Start, port: 1, bit: 2 },
+    InputBit { button: Button::P1Fire, port: 1, bit: 4 },
+    InputBit { button: Button::P1Left, port: 1, bit: 5 },
+    InputBit { button: Button::P1Right, port: 1, bit: 6 },
+    InputBit { button: Button::Tilt, port: 2, bit: 2 },
+    InputBit { button: Button::P2Fire, port: 2, bit: 4 },
+    InputBit { button: Button::P2Left, port: 2, bit: 5 },
+    InputBit { button: Button::P2Right, port: 2, bit: 6 },
+];
+
+// Bits 0-1 ships, bit 3 bonus at 1000, bit 7 coin info off
+const UPRIGHT_DIPS: DipLayout = DipLayout {
+    port: 2,
+    ship_bits: 0x03,
+    min_ships: 3,
+    bonus_1000: Some(3),
+    coin_info_off: Some(7),
+    name_preset: None,
+};
+
+const PART_II_DIPS: DipLayout = DipLayout { bonus_1000: None, name_preset: Some(3), ..UPRIGHT_DIPS };
+
+pub static INVADERS: MachineDescription = MachineDescription {
+    name: "invaders",
+    title: "Space Invaders",
+    roms: &[
+        RomRegion { file: "invaders.h", addr: 0x0000, size: 0x800 },
+        RomRegion { file: "invaders.g", addr: 0x0800, size: 0x800 },
+        RomRegion { file: "invaders.f", addr: 0x1000, size: 0x800 },
+        RomRegion { file: "invaders.e", addr: 0x1800, size: 0x800 },
+    ],
+    // Bits 1-3 of port 0 and bit 3 of port 1 are tied high
+    ports: &[(0, 0x0E), (1, 0x08), (2, 0x00)],
+    inputs: &UPRIGHT_INPUTS,
+    dips: UPRIGHT_DIPS,
+    shift: ShiftPorts { amount: 2, data: 4, result: 3 },
+    sound_ports: [3, 5],
+    sounds: [&PORT_3, &PORT_5],
+    watchdog: Some(6),
+    flip: Some((5, 5)),
+    overlay: Overlay::Classic,
+    proms: &[],
+    color_map: None,
+};
+
+// Same board and controls, with 4K more ROM above the RAM. Its sound board
+// isn't emulated
+pub static LRESCUE: MachineDescription = MachineDescription {
+    name: "lrescue",
+    title: "Lunar Rescue",
+    roms: &[
+        RomRegion { file: "lrescue.1", addr: 0x0000, size: 0x800 },
+        RomRegion { file: "lrescue.2", addr: 0x0800, size: 0x800 },
+        RomRegion { file: "lrescue.3", addr: 0x1000, size: 0x800 },
+        RomRegion { file: "lrescue.4", addr: 0x1800, size: 0x800 },
+        RomRegion { file: "lrescue.5", addr: 0x4000, size: 0x800 },
+        RomRegion { file: "lrescue.6", addr: 0x4800, size: 0x800 },
+    ],
+    ports: &[(0, 0x0E), (1, 0x08), (2, 0x00)],
+    inputs: &UPRIGHT_INPUTS,
+    dips: UPRIGHT_DIPS,
+    shift: ShiftPorts { amount: 2, data: 4, result: 3 },
+    sound_ports: [3, 5],
+    sounds: [&[], &[]],
+    watchdog: Some(6),
+    flip: Some((5, 5)),
+    overlay: Overlay::None,
+    proms: &[],
+    color_map: None,
+};
+
+// Taito's sequel, it adds colour PROMs and a fifth ROM above the RAM. The
+// bonus ship switch is gone and its bit enters the high score name. The
+// flip bit also picks the colour map, player two gets the second one
+pub static INVADPT2: MachineDescription = MachineDescription {
+    name: "invadpt2",
+    title: "Space Invaders Part II",
+    roms: &[
+        RomRegion { file: "pv01", addr: 0x0000, size: 0x800 },
+        RomRegion { file: "pv02", addr: 0x0800, size: 0x800 },
+        RomRegion { file: "pv03", addr: 0x1000, size: 0x800 },
+        RomRegion { file: "pv04", addr: 0x1800, size: 0x800 },
+        RomRegion { file: "pv05", addr: 0x4000, size: 0x800 },
+    ],
+    ports: &[(0, 0x0E), (1, 0x08), (2, 0x00)],
+    inputs: &UPRIGHT_INPUTS,
+    dips: PART_II_DIPS,
+    shift: ShiftPorts { amount: 2, data: 4, result: 3 },
+    sound_ports: [3, 5],
+    sounds: [&PORT_3, &PORT_5],
+    watchdog: Some(6),
+    flip: Some((5, 5)),
+    overlay: Overlay::None,
+    proms: &[
+        RomRegion { file: "pv06.1", addr: 0x000, size: 0x400 },
+        RomRegion { file: "pv07.2", addr: 0x400, size: 0x400 },
+    ],
+    color_map: Some((5, 5)),
+};
+
+// Midway's release of Part II, black and white under the usual overlay
+pub static INVADDLX: MachineDescription = MachineDescription {
+    name: "invaddlx",
+    title: "Space Invaders Deluxe",
+    roms: &[
+        RomRegion { file: "invdelux.h", addr: 0x0000, size: 0x800 },
+        RomRegion { file: "invdelux.g", addr: 0x0800, size: 0x800 },
+        RomRegion { file: "invdelux.f", addr: 0x1000, size: 0x800 },
+        RomRegion { file: "invdelux.e", addr: 0x1800, size: 0x800 },
+        RomRegion { file: "invdelux.d", addr: 0x4000, size: 0x800 },
+    ],
+    ports: &[(0, 0x0E), (1, 0x08), (2, 0x00)],
+    inputs: &UPRIGHT_INPUTS,
+    dips: PART_II_DIPS,
+    shift: ShiftPorts { amount: 2, data: 4, result: 3 },
+    sound_ports: [3, 5],
+    sounds: [&PORT_3, &PORT_5],
+    watchdog: Some(6),
+    flip: Some((5, 5)),
+    overlay: Overlay::Classic,
+    proms: &[],
+    color_map: None,
+};
+
+pub static MACHINES: [&MachineDescription; 4] = [&INVADERS, &INVADPT2, &INVADDLX, &LRESCUE];
+
+pub fn find(name: &str) -> Result<&'static MachineDescription, String> {
+    MACHINES.iter().copied().find(|m| m.name == name).ok_or_else(|| {
+        let names: Vec<&str> = MACHINES.iter().map(|m| m.name).collect();
+        let (last, rest) = names.split_last().unwrap_or((&"", &[]));
+        format!("unknown machine {}, expected {} or {}", name, rest.join(", "), last)
+    })
+}
+
+#[cfg(test)]
+mod machine_test {
+    use super::*;
+
+    #[test]
+    fn descriptions() {
+        assert_eq!(find("invaders"), Ok(&INVADERS));
+        assert_eq!(find("pong"), Err("unknown machine pong, expected invaders, invadpt2, invaddlx or lrescue".to_string()));
+        assert_eq!(INVADERS.rom_size(), 0x2000);
+        assert_eq!(INVADPT2.rom_size(), 0x3000);
+        assert_eq!(LRESCUE.rom_size(), 0x3000);
+
+        for machine in MACHINES {
+            // Every button is wired to a port the game reads
+            for button in Button::ALL {
+                let input = machine.inputs.iter().find(|i| i.button == button).unwrap();
+                assert!(machine.ports.iter().any(|&(port, _)| port == input.port), "{} {:?}", machine.name, button);
+            }
+        }
+    }
+
+    #[test]
+    fn dip_bits() {
+        let dips = &INVADERS.dips;
+        assert_eq!(dips.bits(&Dips::default()), 0x00);
+        assert_eq!(dips.bits(&Dips { ships: 6, bonus_life: 1000, coin_info: false, name_preset: true, ..Default::default() }), 0x8B);
+        assert_eq!(INVADPT2.dips.bits(&Dips { name_preset: true, ..Default::default() }), 0x08);
+
+        let shifted = DipLayout {
+            port: 0,
+            ship_bits: 0x30,
+            min_ships: 2,
+            bonus_1000: None,
+            coin_info_off: None,
+            name_preset: None,
+        };
+        assert_eq!(shifted.bits(&Dips { ships: 4, ..Default::default() }), 0x20);
+        assert_eq!(shifted.bits(&Dips { ships: 9, ..Default::default() }), 0x30);
+    }
+}
@@ -1,49 +1,340 @@
 use crate::cpu::Cpu;
+use crate::EmuError;
+pub mod batch;
+pub mod env;
 pub mod io;
-use std::env;
-use std::fs::File;
-use std::io::Read;
+pub mod machine;
+pub mod movie;
+pub mod sound;
+pub mod state;
+pub mod video;
+
+pub use batch::Batch;
+pub use env::InvadersEnv;
+pub use io::{Button, Dips, InvadersInput};
+use io::IO;
+pub use machine::MachineDescription;
+use machine::INVADERS;
+use sound::Sound;
+pub use state::InvadersState;
+use state::{RAM_END, RAM_START};
+pub use video::Frame;
+use video::{Beam, COLOR_MAP_SIZE, VRAM_END, VRAM_START};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+// The 8080 runs at 2MHz and the screen refreshes at 60Hz
+pub const CYCLES_PER_FRAME: u32 = 2_000_000 / 60;
+
+// Every game on the board has 8K of ROM at the bottom of memory, some add
+// more above the RAM
+pub const ROM_SIZE: usize = 0x2000;
+
+// The watchdog counts vblanks and resets the cpu on the 255th since the game
+// last wrote to its port
+pub const WATCHDOG_FRAMES: u32 = 255;
+
+// A reset by the watchdog, the game was stuck somewhere around pc
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogReset {
+    pub frame: u64,
+    pub pc: u16,
+}
+
+impl fmt::Display for WatchdogReset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "reset at frame {}, pc {:04X}", self.frame, self.pc)
+    }
+}
+
+// The board the Midway/Taito 8080 games ran on, which game it is comes from
+// its MachineDescription. Cabinets are Send, and cloning one copies its RAM
+// and cpu state but shares the ROM image
+
+#[derive(Clone)]
 pub struct InvadersCabinet {
     cpu: Cpu,
+
+    // The ROM as loaded, reset copies it back into memory
+    rom: Arc<[u8]>,
+
+    // Cycles into the current frame
+    frame_cycles: u32,
+
+    pub frames: u64,
+
+    // Lets the watchdog reset the cpu, on machines that have one
+    pub watchdog: bool,
+    // Frames since the watchdog was last kicked
+    watchdog_frames: u32,
+    watchdog_resets: Vec<WatchdogReset>,
+
+    // Lines latched as the beam draws them, when that's turned on
+    beam: Option<Beam>,
+}
+
+// A cpu fresh from power on with the ROM image spread over the machine's
+// regions
+
+fn power_on(machine: &'static MachineDescription, rom: &[u8]) -> Result<Cpu, EmuError> {
+    // Initialize the cpu and configure the actual arcade cabinet, such as setting up IO and interupts
+    let mut intel_8080 = Cpu::init(0x0, &[]);
+    intel_8080.rom = machine.roms.iter().map(|r| r.addr as usize..r.addr as usize + r.size).collect();
+    intel_8080.io = IO::new(machine);
+
+    let mut rest = rom;
+    for region in machine.roms {
+        let (chunk, tail) = rest.split_at(region.size.min(rest.len()));
+        intel_8080.load_into_memory(chunk, region.addr as usize)?;
+        rest = tail;
+    }
+    Ok(intel_8080)
 }
 
 impl InvadersCabinet {
 
-    // Pass None to InvadersCabinet::init to power on without loading a program
+    // Pass None to InvadersCabinet::init to power on without loading a program.
+    // The path is either a directory holding the four ROM chips or a single
+    // file with the whole ROM
+
+    pub fn init(path: Option<String>) -> Result<Self, EmuError> {
+        InvadersCabinet::init_machine(&INVADERS, path)
+    }
 
-    pub fn init(path: Option<String>) -> Self {
-        // Initialize the cpu and configure the actual arcade cabinet, such as setting up IO and interupts
-        let mut buf: Vec<u8> = Vec::new();
+    // The same for any game on the board, a directory holds the files named
+    // in the machine's ROM regions and then its colour PROMs
 
-        match path {
-            Some(file_name) => {
-                let args: Vec<String> = env::args().collect();
-                let mut file = File::open(file_name).expect("File failed to read!");
-                
-                file.read_to_end(&mut buf).unwrap();
+    pub fn init_machine(machine: &'static MachineDescription, path: Option<String>) -> Result<Self, EmuError> {
+        let mut rom = Vec::new();
+
+        if let Some(path) = path {
+            let path = Path::new(&path);
+            if path.is_dir() {
+                for region in machine.roms.iter().chain(machine.proms) {
+                    rom.extend(read_rom(&path.join(region.file), region.size, true)?);
+                }
+            } else {
+                rom = read_rom(path, machine.rom_size(), false)?;
             }
-            None => {},
         }
 
-        let intel_8080 = Cpu::init(0x0, &buf);
+        InvadersCabinet::with_rom(machine, rom.into())
+    }
+
+    // Powers on with a ROM image that's already loaded
+
+    pub fn with_rom(machine: &'static MachineDescription, rom: Arc<[u8]>) -> Result<Self, EmuError> {
+        Ok(InvadersCabinet {
+            cpu: power_on(machine, &rom)?,
+            rom,
+            frame_cycles: 0,
+            frames: 0,
+            watchdog: true,
+            watchdog_frames: 0,
+            watchdog_resets: Vec::new(),
+            beam: None,
+        })
+    }
+
+    pub fn machine(&self) -> &'static MachineDescription {
+        self.cpu.io.machine()
+    }
+
+    pub fn rom(&self) -> &Arc<[u8]> {
+        &self.rom
+    }
 
-        
-        
-        InvadersCabinet {
-            cpu: intel_8080,
-            
+    // Back to power on with the same ROM, the DIP switches and input are
+    // cleared too but strict mode and the watchdog setting are kept
+
+    pub fn reset(&mut self) -> Result<(), EmuError> {
+        let strict = self.cpu.strict;
+        self.cpu = power_on(self.machine(), &self.rom)?;
+        self.cpu.strict = strict;
+        self.frame_cycles = 0;
+        self.frames = 0;
+        self.watchdog_frames = 0;
+        self.watchdog_resets.clear();
+        if self.beam.is_some() {
+            self.latch_scanlines(true);
         }
+        Ok(())
+    }
+
+    // With it on, frame returns each line as it was when the beam drew it
+    // instead of VRAM as it is now. It costs a check per instruction
+
+    pub fn latch_scanlines(&mut self, on: bool) {
+        self.beam = on.then(|| Beam::new(screen(&self.cpu, &self.rom).1.is_some()));
+    }
+
+    // Resets the watchdog has made since power on or the last take
+
+    pub fn watchdog_resets(&self) -> &[WatchdogReset] {
+        &self.watchdog_resets
+    }
+
+    // Hands over the resets so far and empties the log, a host that runs for
+    // a long time takes them each frame so they don't pile up
+
+    pub fn take_watchdog_resets(&mut self) -> Vec<WatchdogReset> {
+        std::mem::take(&mut self.watchdog_resets)
+    }
+
+    // Called at each vblank, resets the cpu and the latches on the IO ports
+    // once the game has gone too long without kicking the watchdog
+
+    fn tick_watchdog(&mut self) {
+        if self.cpu.io.take_kick() || !self.watchdog || self.machine().watchdog.is_none() {
+            self.watchdog_frames = 0;
+            return;
+        }
+
+        self.watchdog_frames += 1;
+        if self.watchdog_frames >= WATCHDOG_FRAMES {
+            self.watchdog_resets.push(WatchdogReset { frame: self.frames, pc: self.cpu.regs.pc });
+            self.watchdog_frames = 0;
+            self.cpu.reset();
+            self.cpu.io.reset();
+        }
+    }
+
+    // Sound effects started since the latches read before
+
+    pub fn sounds(&self, before: (u8, u8)) -> Vec<Sound> {
+        sound::triggered(&self.machine().sounds, before, self.sound_latches())
+    }
+
+    pub fn emulate_cycle(&mut self) -> Result<(), EmuError> {
+        self.cpu.cycle_d()
+    }
+
+    // Runs one instruction and raises the video interrupts, RST 1 when the
+    // beam reaches the middle of the screen and RST 2 at vblank, where the
+    // watchdog counts too
+
+    pub fn step(&mut self) -> Result<u32, EmuError> {
+        let cycles = self.cpu.step()?;
+        let half = CYCLES_PER_FRAME / 2;
+
+        let before = self.frame_cycles;
+        self.frame_cycles += cycles;
+
+        if let Some(beam) = &mut self.beam {
+            let (vram, color) = screen(&self.cpu, &self.rom);
+            beam.advance(vram, color, self.frame_cycles);
+        }
+
+        if before < half && self.frame_cycles >= half {
+            self.cpu.interrupt(1)?;
+        }
+
+        if self.frame_cycles >= CYCLES_PER_FRAME {
+            self.frame_cycles -= CYCLES_PER_FRAME;
+            if let Some(beam) = &mut self.beam {
+                beam.vblank();
+            }
+            self.frames += 1;
+            self.tick_watchdog();
+            self.cpu.interrupt(2)?;
+        }
+
+        Ok(cycles)
+    }
+
+    // Runs until the next vblank
+
+    pub fn run_frame(&mut self) -> Result<(), EmuError> {
+        let frame = self.frames;
+        while self.frames == frame {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_input(&mut self, input: &InvadersInput) {
+        self.cpu.io.set_input(input);
+    }
+
+    pub fn set_dips(&mut self, dips: &Dips) {
+        self.cpu.io.set_dips(dips);
+    }
+
+    // Current (port 3, port 5) sound latches
+
+    pub fn sound_latches(&self) -> (u8, u8) {
+        (self.cpu.io.sound_1, self.cpu.io.sound_2)
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
     }
 
-    pub fn emulate_cycle(&mut self) {
-        self.cpu.cycle_d();
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
     }
 
-    // Fetches the framebuffer
+    // Fetches the framebuffer, with the colour map if the board has colour
+    // PROMs and turned round if a cocktail cabinet is flipped for player two
+
+    pub fn frame(&self) -> Frame {
+        let mut frame = match &self.beam {
+            Some(beam) => beam.frame().clone(),
+            None => match screen(&self.cpu, &self.rom) {
+                (vram, Some(color)) => Frame::with_color(vram, color),
+                (vram, None) => Frame::from_vram(vram),
+            },
+        };
+        frame.flipped = self.cpu.io.flipped();
+        frame
+    }
 
+    // The raw VRAM bytes as returned before the crate split, one short of
+    // the end like it always was. Kept for existing callers
+
+    #[deprecated(note = "use frame")]
     pub fn get_frame(&self) -> Vec<u8> {
-        self.cpu.memory[0x2400..0x3fff].to_vec()
+        self.cpu.memory[VRAM_START..VRAM_END - 1].to_vec()
     }
+
+    // Decodes the game's variables from work RAM
+
+    pub fn state(&self) -> InvadersState {
+        InvadersState::from_ram(&self.cpu.memory[RAM_START..RAM_END])
+    }
+}
+
+// VRAM and the colour map the board is drawing it with, if the ROM image
+// came with colour PROMs
+
+fn screen<'a>(cpu: &'a Cpu, rom: &'a [u8]) -> (&'a [u8], Option<&'a [u8]>) {
+    let vram = &cpu.memory[VRAM_START..VRAM_END];
+    let cpu_rom: usize = cpu.io.machine().roms.iter().map(|r| r.size).sum();
+    let start = cpu_rom + cpu.io.color_map() * COLOR_MAP_SIZE;
+    (vram, rom.get(start..start + COLOR_MAP_SIZE))
+}
+
+// Reads a ROM image, exact says whether it has to be exactly size bytes or
+// just no bigger
+
+fn read_rom(path: &Path, size: usize, exact: bool) -> Result<Vec<u8>, EmuError> {
+    let rom = fs::read(path).map_err(|e| EmuError::RomMissing {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })?;
+
+    if rom.is_empty() || rom.len() > size || (exact && rom.len() != size) {
+        return Err(EmuError::RomMismatch {
+            path: path.to_path_buf(),
+            expected: size,
+            found: rom.len(),
+        });
+    }
+
+    Ok(rom)
 }
 
 #[cfg(test)]
@@ -52,10 +343,188 @@ mod test {
 
     #[test]
     fn framebuffer_test() {
-        let mut invaders = InvadersCabinet::init(None);
+        let mut invaders = InvadersCabinet::init(None).unwrap();
         let new_vram = [69; (0x3fff - 0x2400)];
         // invaders.cpu.memory[2400..0x3fff].copy_from_slice(&new_vram);
         invaders.cpu.memory[0x2400..0x3fff].copy_from_slice(&new_vram);
         assert_eq!(invaders.cpu.memory[0x2400..0x3fff], new_vram);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn video_interrupts() {
+        // EI, then spin in a loop, RST 1 and RST 2 land on their handlers
+        let prog = [0xFB, 0xC3, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x76, 0, 0, 0, 0, 0, 0, 0, 0x76];
+        let mut invaders = InvadersCabinet::init(None).unwrap();
+        invaders.cpu.memory[..prog.len()].copy_from_slice(&prog);
+        invaders.cpu.regs.sp = 0x2400;
+
+        while !invaders.cpu.hlted {
+            invaders.step().unwrap();
+        }
+        assert_eq!(invaders.cpu.regs.pc, 0x09);
+        assert_eq!(invaders.frames, 0);
+
+        invaders.cpu.int_enabled = true;
+        while invaders.frames == 0 {
+            invaders.step().unwrap();
+        }
+        assert_eq!(invaders.cpu.regs.pc, 0x10);
+    }
+
+    #[test]
+    fn rom_errors() {
+        let dir = std::env::temp_dir().join(format!("invaders_roms_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("invaders.h"), [0u8; 0x800]).unwrap();
+        fs::write(dir.join("invaders.g"), [0u8; 0x7FF]).unwrap();
+
+        let missing = InvadersCabinet::init(Some(dir.join("nope").to_string_lossy().to_string()));
+        assert!(matches!(missing, Err(EmuError::RomMissing { .. })));
+
+        let short = InvadersCabinet::init(Some(dir.to_string_lossy().to_string()));
+        assert!(matches!(short, Err(EmuError::RomMismatch { expected: 0x800, found: 0x7FF, .. })));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn clones_share_the_rom() {
+        fn send<T: Send>(_: &T) {}
+
+        let mut invaders = InvadersCabinet::with_rom(&INVADERS, Arc::from(&[0x3C, 0x76][..])).unwrap();
+        invaders.step().unwrap();
+        let mut copy = invaders.clone();
+        send(&copy);
+        assert!(Arc::ptr_eq(invaders.rom(), copy.rom()));
+
+        copy.cpu.memory[0x2000] = 1;
+        copy.step().unwrap();
+        assert_eq!((copy.cpu.regs.a, copy.cpu.hlted), (1, true));
+        assert_eq!(invaders.cpu.memory[0x2000], 0);
+
+        copy.reset().unwrap();
+        assert_eq!((copy.cpu.regs.pc, copy.cpu.memory[0x2000], copy.cpu.memory[0]), (0, 0, 0x3C));
+    }
+
+    #[test]
+    fn rom_regions() {
+        let image: Vec<u8> = (0..machine::LRESCUE.rom_size()).map(|n| (n / 0x800) as u8 + 1).collect();
+        let lrescue = InvadersCabinet::with_rom(&machine::LRESCUE, image.into()).unwrap();
+        let memory = &lrescue.cpu.memory;
+        assert_eq!((memory[0x0000], memory[0x1FFF], memory[0x2000]), (1, 4, 0));
+        assert_eq!((memory[0x4000], memory[0x4FFF], memory[0x5000]), (5, 6, 0));
+        assert_eq!(lrescue.machine().name, "lrescue");
+    }
+
+    #[test]
+    fn color_proms() {
+        // The first PROM is red but for green at the bottom left, the second
+        // is blue. MVI A, 0xFF; STA 0x2400; MVI A, 0x20; OUT 5; spin
+        let prog = [0x3E, 0xFF, 0x32, 0x00, 0x24, 0x3E, 0x20, 0xD3, 0x05, 0xC3, 0x09, 0x00];
+        let mut image = vec![0; machine::INVADPT2.rom_size()];
+        image[..prog.len()].copy_from_slice(&prog);
+        let proms = 0x2800;
+        image[proms..proms + COLOR_MAP_SIZE].fill(0x01);
+        image[proms + 0x80] = 0x04;
+        image[proms + COLOR_MAP_SIZE..].fill(0x02);
+        let mut cabinet = InvadersCabinet::with_rom(&machine::INVADPT2, image.into()).unwrap();
+        cabinet.cpu.memory[VRAM_START + 8 * 32] = 0xFF;
+
+        cabinet.step().unwrap();
+        cabinet.step().unwrap();
+        let frame = cabinet.frame();
+        assert!(frame.pixel(0, video::HEIGHT - 1));
+        assert_eq!(frame.color(0, video::HEIGHT - 1, video::Overlay::None), [0x00, 0xFF, 0x00]);
+        assert_eq!(frame.color(8, video::HEIGHT - 1, video::Overlay::None), [0xFF, 0x00, 0x00]);
+
+        cabinet.step().unwrap();
+        cabinet.step().unwrap();
+        assert_eq!(cabinet.frame().color(0, video::HEIGHT - 1, video::Overlay::None), [0x00, 0x00, 0xFF]);
+
+        cabinet.latch_scanlines(true);
+        cabinet.run_frame().unwrap();
+        assert_eq!(cabinet.frame().color(8, video::HEIGHT - 1, video::Overlay::None), [0x00, 0x00, 0xFF]);
+
+        // Without the PROMs the picture is black and white
+        let plain = InvadersCabinet::with_rom(&machine::INVADPT2, vec![0; 0x2800].into()).unwrap();
+        assert_eq!(plain.frame().color, None);
+    }
+
+    #[test]
+    fn watchdog() {
+        // OUT 6, then spin without kicking it again
+        let prog = [0xD3, 0x06, 0xC3, 0x02, 0x00];
+        let mut invaders = InvadersCabinet::init(None).unwrap();
+        invaders.cpu.memory[..prog.len()].copy_from_slice(&prog);
+        invaders.cpu.io.sound_1 = 0x02;
+        invaders.set_input(&InvadersInput { coin: true, ..Default::default() });
+
+        // The kick lands in the first frame
+        for _ in 0..=WATCHDOG_FRAMES {
+            invaders.run_frame().unwrap();
+        }
+        assert_eq!(invaders.watchdog_resets(), [WatchdogReset { frame: 256, pc: 0x02 }]);
+        assert_eq!(invaders.watchdog_resets()[0].to_string(), "reset at frame 256, pc 0002");
+        assert_eq!(invaders.sound_latches(), (0, 0));
+        assert_eq!(invaders.cpu.io.cpu_read(1).unwrap() & 1, 1);
+
+        // The kick after the reset starts the count again
+        for _ in 0..WATCHDOG_FRAMES - 1 {
+            invaders.run_frame().unwrap();
+        }
+        assert_eq!(invaders.take_watchdog_resets().len(), 1);
+        assert!(invaders.watchdog_resets().is_empty());
+
+        invaders.reset().unwrap();
+        invaders.cpu.memory[..prog.len()].copy_from_slice(&prog);
+        invaders.watchdog = false;
+        for _ in 0..WATCHDOG_FRAMES + 1 {
+            invaders.run_frame().unwrap();
+        }
+        assert!(invaders.watchdog_resets().is_empty());
+    }
+
+    #[test]
+    fn latched_scanlines() {
+        let mut invaders = InvadersCabinet::init(None).unwrap();
+        invaders.latch_scanlines(true);
+
+        // The first line changes once the beam is past it, so it only shows
+        // in the next frame
+        while invaders.frame_cycles < video::line_cycle(1) {
+            invaders.step().unwrap();
+        }
+        invaders.cpu.memory[VRAM_START] = 0x01;
+        invaders.run_frame().unwrap();
+        assert!(!invaders.frame().pixel(0, video::HEIGHT - 1));
+
+        invaders.run_frame().unwrap();
+        assert!(invaders.frame().pixel(0, video::HEIGHT - 1));
+
+        invaders.cpu.memory[VRAM_START] = 0x00;
+        invaders.latch_scanlines(false);
+        assert!(!invaders.frame().pixel(0, video::HEIGHT - 1));
+    }
+
+    #[test]
+    fn stack_into_rom() {
+        // LXI SP, 0x2001; PUSH B
+        let mut invaders = InvadersCabinet::init(None).unwrap();
+        invaders.cpu.memory[..4].copy_from_slice(&[0x31, 0x01, 0x20, 0xC5]);
+
+        invaders.step().unwrap();
+        assert_eq!(invaders.step(), Err(EmuError::StackOverflow { sp: 0x1FFF, pc: 0x0003 }));
+
+        // Lunar Rescue has ROM at 0x4000 too, the RAM between is fine
+        let mut lrescue = InvadersCabinet::init_machine(&machine::LRESCUE, None).unwrap();
+        lrescue.cpu.memory[..4].copy_from_slice(&[0x31, 0x01, 0x40, 0xC5]);
+        lrescue.step().unwrap();
+        assert_eq!(lrescue.step(), Err(EmuError::StackOverflow { sp: 0x3FFF, pc: 0x0003 }));
+
+        let mut lrescue = InvadersCabinet::init_machine(&machine::LRESCUE, None).unwrap();
+        lrescue.cpu.memory[..4].copy_from_slice(&[0x31, 0x00, 0x40, 0xC5]);
+        lrescue.step().unwrap();
+        lrescue.step().unwrap();
+        assert_eq!(lrescue.cpu.regs.sp, 0x3FFE);
+    }
+}
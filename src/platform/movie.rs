@@ -0,0 +1,81 @@
+// Input movies, the buttons held on every frame so a run can be replayed
+//
+// The file is text, a header line then one line per frame with the buttons
+// as hex bits in the order of Button::ALL.
+
+use super::io::InvadersInput;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const HEADER: &str = "space-invaders movie 1";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub frames: Vec<InvadersInput>,
+}
+
+impl Movie {
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some(HEADER) {
+            return Err("not a movie file".to_string());
+        }
+
+        let frames = lines
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(n, line)| {
+                u16::from_str_radix(line.trim(), 16)
+                    .map(InvadersInput::from_bits)
+                    .map_err(|_| format!("bad frame on line {}", n + 2))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Movie { frames })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Movie> {
+        let text = fs::read_to_string(path)?;
+        Movie::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", HEADER);
+        for input in &self.frames {
+            out.push_str(&format!("{:03x}\n", input.bits()));
+        }
+        out
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    // Input for a frame, nothing held once the movie runs out
+
+    pub fn input(&self, frame: u64) -> InvadersInput {
+        self.frames.get(frame as usize).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod movie_test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let movie = Movie {
+            frames: vec![
+                InvadersInput::default(),
+                InvadersInput { coin: true, ..Default::default() },
+                InvadersInput { p1_fire: true, p1_left: true, ..Default::default() },
+            ],
+        };
+        let text = movie.to_text();
+        assert_eq!(text, "space-invaders movie 1\n000\n001\n018\n");
+        assert_eq!(Movie::parse(&text), Ok(movie.clone()));
+        assert_eq!(movie.input(9), InvadersInput::default());
+        assert!(Movie::parse("000\n").is_err());
+    }
+}
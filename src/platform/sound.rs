@@ -0,0 +1,61 @@
+// Sound effects, triggered by the game setting bits on its sound ports
+//
+// The cabinet plays samples from discrete circuits, so an effect starts on
+// the rising edge of its bit. The UFO drone plays for as long as its bit is set.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sound {
+    Ufo,
+    Shot,
+    PlayerDie,
+    InvaderDie,
+    ExtraLife,
+    // The four notes of the marching fleet, 0 to 3
+    Fleet(u8),
+    UfoHit,
+}
+
+impl Sound {
+    pub fn name(self) -> String {
+        match self {
+            Sound::Ufo => "ufo".to_string(),
+            Sound::Shot => "shot".to_string(),
+            Sound::PlayerDie => "player die".to_string(),
+            Sound::InvaderDie => "invader die".to_string(),
+            Sound::ExtraLife => "extra life".to_string(),
+            Sound::Fleet(n) => format!("fleet {}", n + 1),
+            Sound::UfoHit => "ufo hit".to_string(),
+        }
+    }
+}
+
+pub const PORT_3: [Sound; 5] = [Sound::Ufo, Sound::Shot, Sound::PlayerDie, Sound::InvaderDie, Sound::ExtraLife];
+pub const PORT_5: [Sound; 5] = [Sound::Fleet(0), Sound::Fleet(1), Sound::Fleet(2), Sound::Fleet(3), Sound::UfoHit];
+
+// Sounds started between two readings of the two sound latches, table is
+// the machine's sounds for each latch bit
+
+pub fn triggered(table: &[&[Sound]; 2], before: (u8, u8), now: (u8, u8)) -> Vec<Sound> {
+    let mut sounds = Vec::new();
+    for (before, now, port) in [(before.0, now.0, table[0]), (before.1, now.1, table[1])] {
+        for (n, &sound) in port.iter().enumerate() {
+            if now & !before & (1 << n) != 0 {
+                sounds.push(sound);
+            }
+        }
+    }
+    sounds
+}
+
+#[cfg(test)]
+mod sound_test {
+    use super::*;
+
+    #[test]
+    fn rising_edges_only() {
+        let table = [&PORT_3[..], &PORT_5[..]];
+        assert_eq!(triggered(&table, (0x01, 0), (0x03, 0x10)), [Sound::Shot, Sound::UfoHit]);
+        assert_eq!(triggered(&table, (0x03, 0x10), (0x03, 0x10)), []);
+        assert_eq!(triggered(&table, (0, 0x01), (0, 0x02)), [Sound::Fleet(1)]);
+    }
+}
@@ -0,0 +1,187 @@
+// The game's variables in work RAM, decoded
+//
+// Addresses follow the commented disassembly of the ROM. Positions are in
+// the game's own coordinates, x across the monitor from the left and y up
+// from the bottom, both in pixels.
+
+pub const RAM_START: usize = 0x2000;
+pub const RAM_END: usize = 0x2400;
+
+// Each player's rack, shields and ship count live in their own page
+const PLAYER_1: usize = 0x2100;
+const PLAYER_2: usize = 0x2200;
+const SHIPS: usize = 0xFF;
+
+pub const ALIENS: usize = 55;
+pub const ALIENS_PER_ROW: usize = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    // Splash screens and the demo
+    Attract,
+    Playing,
+    // The current player's last ship is gone and GAME OVER is up
+    GameOver,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Shot {
+    pub active: bool,
+    // Hit something and showing its explosion
+    pub exploding: bool,
+    pub x: u8,
+    pub y: u8,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Ufo {
+    pub active: bool,
+    pub hit: bool,
+    pub x: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvadersState {
+    pub mode: GameMode,
+    // 1 or 2
+    pub player: u8,
+    pub scores: [u32; 2],
+    pub high_score: u32,
+    pub ships: [u8; 2],
+    pub credits: u8,
+    // Bit n set while alien n of the current player's rack is alive, counting
+    // along the bottom row from the left then up
+    pub aliens: u64,
+    // Bottom left alien of the rack
+    pub fleet_x: u8,
+    pub fleet_y: u8,
+    pub ufo: Ufo,
+    pub player_x: u8,
+    pub player_shot: Shot,
+    // The rolling, plunger and squiggly shots
+    pub alien_shots: [Shot; 3],
+}
+
+fn bcd(byte: u8) -> u32 {
+    (byte >> 4) as u32 * 10 + (byte & 0x0F) as u32
+}
+
+impl InvadersState {
+    // ram is the work RAM from RAM_START to RAM_END
+
+    pub fn from_ram(ram: &[u8]) -> InvadersState {
+        let at = |addr: usize| ram[addr - RAM_START];
+        let score = |addr: usize| bcd(at(addr + 1)) * 100 + bcd(at(addr));
+
+        let player = if at(0x2067) == 0x22 { 2 } else { 1 };
+        let rack = if player == 2 { PLAYER_2 } else { PLAYER_1 };
+        let aliens = (0..ALIENS).fold(0, |bits, n| bits | ((at(rack + n) != 0) as u64) << n);
+        let ships = [at(PLAYER_1 + SHIPS), at(PLAYER_2 + SHIPS)];
+
+        // Alien shots keep bit 7 for in flight and bit 0 for blowing up
+        let alien_shot = |obj: usize| Shot {
+            active: at(obj + 5) & 0x80 != 0,
+            exploding: at(obj + 5) & 0x01 != 0,
+            x: at(obj + 0x0E),
+            y: at(obj + 0x0D),
+        };
+
+        // The player's shot counts up, 1 and 2 are flying and 3 to 5 are
+        // explosions
+        let player_shot = at(0x2025);
+
+        // The UFO is drawn at a VRAM address, 32 bytes to a column
+        let ufo = (at(0x2087) as usize | (at(0x2088) as usize) << 8).saturating_sub(RAM_END) / 32;
+
+        let mode = match at(0x20EF) {
+            0 => GameMode::Attract,
+            _ if ships[player as usize - 1] == 0 => GameMode::GameOver,
+            _ => GameMode::Playing,
+        };
+
+        InvadersState {
+            mode,
+            player,
+            scores: [score(0x20F8), score(0x20FC)],
+            high_score: score(0x20F4),
+            ships,
+            credits: bcd(at(0x20EB)) as u8,
+            aliens,
+            fleet_x: at(0x200A),
+            fleet_y: at(0x2009),
+            ufo: Ufo {
+                active: at(0x2084) != 0,
+                hit: at(0x2085) != 0,
+                x: ufo as u8,
+            },
+            player_x: at(0x201B),
+            player_shot: Shot {
+                active: (1..=2).contains(&player_shot),
+                exploding: (3..=5).contains(&player_shot),
+                x: at(0x202A),
+                y: at(0x2029),
+            },
+            alien_shots: [alien_shot(0x2030), alien_shot(0x2040), alien_shot(0x2050)],
+        }
+    }
+
+    pub fn alive(&self) -> u32 {
+        self.aliens.count_ones()
+    }
+
+    // Row 0 is the bottom row, column 0 the left
+
+    pub fn alien(&self, row: usize, column: usize) -> bool {
+        self.aliens & 1 << (row * ALIENS_PER_ROW + column) != 0
+    }
+
+    pub fn score(&self) -> u32 {
+        self.scores[self.player as usize - 1]
+    }
+}
+
+#[cfg(test)]
+mod state_test {
+    use super::*;
+
+    fn ram(values: &[(usize, u8)]) -> Vec<u8> {
+        let mut ram = vec![0; RAM_END - RAM_START];
+        for &(addr, val) in values {
+            ram[addr - RAM_START] = val;
+        }
+        ram
+    }
+
+    #[test]
+    fn decodes_ram() {
+        let mut ram = ram(&[
+            (0x20EF, 1), (0x2067, 0x22), (0x20F8, 0x50), (0x20F9, 0x01), (0x20FC, 0x90), (0x20FD, 0x12),
+            (0x20F4, 0x30), (0x20F5, 0x45), (0x20EB, 0x12), (0x21FF, 0), (0x22FF, 2), (0x2200, 1), (0x2236, 1),
+            (0x2100, 1), (0x200A, 0x18), (0x2009, 0x78), (0x2084, 1), (0x2087, 0x50), (0x2088, 0x29),
+            (0x201B, 0x40), (0x2025, 2), (0x2029, 0x30), (0x202A, 0x48), (0x2045, 0x81), (0x204D, 0x60),
+            (0x204E, 0x20),
+        ]);
+
+        let state = InvadersState::from_ram(&ram);
+        assert_eq!(state.mode, GameMode::Playing);
+        assert_eq!(state.player, 2);
+        assert_eq!(state.scores, [150, 1290]);
+        assert_eq!(state.score(), 1290);
+        assert_eq!(state.high_score, 4530);
+        assert_eq!(state.ships, [0, 2]);
+        assert_eq!(state.credits, 12);
+        assert_eq!(state.alive(), 2);
+        assert!(state.alien(0, 0) && state.alien(4, 10) && !state.alien(0, 1));
+        assert_eq!((state.fleet_x, state.fleet_y), (0x18, 0x78));
+        assert_eq!(state.ufo, Ufo { active: true, hit: false, x: 42 });
+        assert_eq!(state.player_x, 0x40);
+        assert_eq!(state.player_shot, Shot { active: true, exploding: false, x: 0x48, y: 0x30 });
+        assert_eq!(state.alien_shots[0], Shot::default());
+        assert_eq!(state.alien_shots[1], Shot { active: true, exploding: true, x: 0x20, y: 0x60 });
+
+        ram[0x22FF - RAM_START] = 0;
+        assert_eq!(InvadersState::from_ram(&ram).mode, GameMode::GameOver);
+        ram[0x20EF - RAM_START] = 0;
+        assert_eq!(InvadersState::from_ram(&ram).mode, GameMode::Attract);
+    }
+}
@@ -0,0 +1,300 @@
+// Video RAM as it appears on the monitor
+//
+// The screen is 256x224 in memory, one bit per pixel with the lowest bit
+// leftmost, but the monitor is mounted rotated 90 degrees anticlockwise so
+// the player sees a 224 wide by 256 tall picture.
+
+use super::state::RAM_START;
+use super::CYCLES_PER_FRAME;
+use std::ops::Range;
+
+pub const VRAM_START: usize = 0x2400;
+pub const VRAM_END: usize = 0x4000;
+
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 256;
+
+// The beam draws a 32 byte row of VRAM per line, 224 lines and then 38 of
+// blanking. Frames start at vblank like the cabinet's do
+pub const LINES: u32 = 262;
+const BLANK_LINES: u32 = LINES - WIDTH as u32;
+const LINE_BYTES: usize = HEIGHT / 8;
+
+pub type Rgb = [u8; 3];
+
+pub const WHITE: Rgb = [0xFF, 0xFF, 0xFF];
+pub const BLACK: Rgb = [0x00, 0x00, 0x00];
+pub const RED: Rgb = [0xFF, 0x30, 0x30];
+pub const GREEN: Rgb = [0x30, 0xFF, 0x30];
+
+// The cabinet is black and white, colour came from strips of cellophane
+// stuck on the monitor
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlay {
+    None,
+    // Red band over the UFO, green over the shields and the player
+    Classic,
+}
+
+impl Overlay {
+    pub fn parse(name: &str) -> Result<Overlay, String> {
+        match name {
+            "none" => Ok(Overlay::None),
+            "classic" => Ok(Overlay::Classic),
+            _ => Err(format!("unknown overlay {}, expected none or classic", name)),
+        }
+    }
+
+    // Colour a lit pixel at x, y shows through the overlay
+
+    pub fn color(self, x: usize, y: usize) -> Rgb {
+        match self {
+            Overlay::None => WHITE,
+            Overlay::Classic => match y {
+                32..=63 => RED,
+                184..=239 => GREEN,
+                240..=255 if (16..=133).contains(&x) => GREEN,
+                _ => WHITE,
+            },
+        }
+    }
+}
+
+// Colour boards look the colour up in a PROM instead, one entry for each 8
+// pixel strip of every 8 lines. The board counts lines from the start of RAM
+// so VRAM starts 4 rows into the map
+pub const COLOR_MAP_SIZE: usize = 0x400;
+
+// The colour map entries for a line of VRAM
+
+fn color_row(line: usize) -> Range<usize> {
+    let start = (line + (VRAM_START - RAM_START) / LINE_BYTES) / 8 * LINE_BYTES;
+    start..start + LINE_BYTES
+}
+
+// An entry has 3 bits of colour, red, blue and green from bit 0 up
+
+pub fn palette(color: u8) -> Rgb {
+    let on = |bit: u8| if color & (1 << bit) != 0 { 0xFF } else { 0x00 };
+    [on(0), on(2), on(1)]
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub vram: Vec<u8>,
+    // The colour map in use, on boards with colour PROMs
+    pub color: Option<Vec<u8>>,
+    // Turned round for player two on a cocktail cabinet
+    pub flipped: bool,
+}
+
+impl Frame {
+    pub fn from_vram(vram: &[u8]) -> Self {
+        Frame {
+            vram: vram.to_vec(),
+            color: None,
+            flipped: false,
+        }
+    }
+
+    pub fn with_color(vram: &[u8], map: &[u8]) -> Self {
+        Frame {
+            vram: vram.to_vec(),
+            color: Some(map.to_vec()),
+            flipped: false,
+        }
+    }
+
+    // Byte offset into VRAM and bit of the pixel at x, y on the monitor
+
+    fn locate(&self, x: usize, y: usize) -> (usize, usize) {
+        let (x, y) = if self.flipped { (WIDTH - 1 - x, HEIGHT - 1 - y) } else { (x, y) };
+        let column = HEIGHT - 1 - y;
+        (x * HEIGHT / 8 + column / 8, column % 8)
+    }
+
+    // Colour a lit pixel at x, y shows, the overlay only counts on black and
+    // white boards. It's stuck to the glass so it doesn't turn with the screen
+
+    pub fn color(&self, x: usize, y: usize, overlay: Overlay) -> Rgb {
+        match &self.color {
+            Some(map) => {
+                let byte = self.locate(x, y).0;
+                palette(map[color_row(byte / LINE_BYTES).start + byte % LINE_BYTES])
+            }
+            None => overlay.color(x, y),
+        }
+    }
+
+    // Whether the pixel at x, y on the rotated screen is lit, 0, 0 is top left
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let (byte, bit) = self.locate(x, y);
+        self.vram[byte] & (1 << bit) != 0
+    }
+
+    // Three bytes per pixel, row by row from the top
+
+    pub fn to_rgb(&self, overlay: Overlay) -> Vec<u8> {
+        let mut out = Vec::with_capacity(WIDTH * HEIGHT * 3);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let color = if self.pixel(x, y) { self.color(x, y, overlay) } else { BLACK };
+                out.extend_from_slice(&color);
+            }
+        }
+        out
+    }
+
+    // One byte per pixel, 0 or 0xFF, row by row from the top
+
+    pub fn to_gray(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(WIDTH * HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                out.push(if self.pixel(x, y) { 0xFF } else { 0x00 });
+            }
+        }
+        out
+    }
+}
+
+// Cycles into the frame at which the beam starts drawing line
+
+pub fn line_cycle(line: usize) -> u32 {
+    (BLANK_LINES + line as u32) * CYCLES_PER_FRAME / LINES
+}
+
+// Copies VRAM a line at a time as the beam passes it, so the frame is what
+// the monitor showed rather than VRAM at the moment it's read. The game
+// races the beam and redraws each half of the screen while the other is
+// being drawn
+
+#[derive(Clone)]
+pub struct Beam {
+    frame: Frame,
+    // Next line the beam draws
+    line: usize,
+}
+
+impl Beam {
+    pub fn new(color: bool) -> Beam {
+        let blank = vec![0; VRAM_END - VRAM_START];
+        Beam {
+            frame: if color { Frame::with_color(&blank, &[0; COLOR_MAP_SIZE]) } else { Frame::from_vram(&blank) },
+            line: 0,
+        }
+    }
+
+    // Latches every line the beam has reached by cycles into the frame
+    // from vram, with its colours from the colour map in use
+
+    pub fn advance(&mut self, vram: &[u8], color: Option<&[u8]>, cycles: u32) {
+        while self.line < WIDTH && cycles >= line_cycle(self.line) {
+            let row = self.line * LINE_BYTES..(self.line + 1) * LINE_BYTES;
+            self.frame.vram[row.clone()].copy_from_slice(&vram[row]);
+            if let (Some(latched), Some(map)) = (&mut self.frame.color, color) {
+                let entries = color_row(self.line);
+                latched[entries.clone()].copy_from_slice(&map[entries]);
+            }
+            self.line += 1;
+        }
+    }
+
+    // Back to the top for the next frame
+
+    pub fn vblank(&mut self) {
+        self.line = 0;
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod video_test {
+    use super::*;
+
+    #[test]
+    fn rotates_onto_monitor() {
+        let mut vram = vec![0; VRAM_END - VRAM_START];
+        // First byte of memory is the bottom left corner, last byte the top right
+        vram[0] = 0x01;
+        vram[VRAM_END - VRAM_START - 1] = 0x80;
+        let frame = Frame::from_vram(&vram);
+
+        assert!(frame.pixel(0, HEIGHT - 1));
+        assert!(frame.pixel(WIDTH - 1, 0));
+        assert_eq!(frame.to_gray().iter().filter(|&&p| p != 0).count(), 2);
+    }
+
+    #[test]
+    fn flips_for_cocktail() {
+        let mut vram = vec![0; VRAM_END - VRAM_START];
+        vram[0] = 0x01;
+        let mut map = vec![0; COLOR_MAP_SIZE];
+        map[0x80] = 0x01;
+        let mut frame = Frame::with_color(&vram, &map);
+        frame.flipped = true;
+
+        assert!(frame.pixel(WIDTH - 1, 0));
+        assert!(!frame.pixel(0, HEIGHT - 1));
+        assert_eq!(frame.color(WIDTH - 1, 0, Overlay::None), [0xFF, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn beam_latches_lines() {
+        let mut vram = vec![0; VRAM_END - VRAM_START];
+        let mut beam = Beam::new(false);
+        beam.advance(&vram, None, line_cycle(100));
+
+        // Line 50 is already drawn when it changes, line 150 isn't
+        vram[50 * LINE_BYTES] = 0x01;
+        vram[150 * LINE_BYTES] = 0x01;
+        beam.advance(&vram, None, CYCLES_PER_FRAME);
+        assert!(!beam.frame().pixel(50, HEIGHT - 1));
+        assert!(beam.frame().pixel(150, HEIGHT - 1));
+
+        beam.vblank();
+        beam.advance(&vram, None, line_cycle(0) - 1);
+        assert!(!beam.frame().pixel(50, HEIGHT - 1));
+        beam.advance(&vram, None, CYCLES_PER_FRAME);
+        assert!(beam.frame().pixel(50, HEIGHT - 1));
+        assert!(line_cycle(WIDTH - 1) < CYCLES_PER_FRAME);
+    }
+
+    #[test]
+    fn classic_overlay() {
+        let mut vram = vec![0; VRAM_END - VRAM_START];
+        vram.iter_mut().for_each(|b| *b = 0xFF);
+        let rgb = Frame::from_vram(&vram).to_rgb(Overlay::Classic);
+        let at = |x: usize, y: usize| &rgb[(y * WIDTH + x) * 3..(y * WIDTH + x) * 3 + 3];
+
+        assert_eq!(at(100, 40), RED);
+        assert_eq!(at(100, 200), GREEN);
+        assert_eq!(at(0, 250), WHITE);
+        assert_eq!(at(100, 100), WHITE);
+    }
+
+    #[test]
+    fn color_map() {
+        let vram = vec![0xFF; VRAM_END - VRAM_START];
+        let mut map = vec![0x07; COLOR_MAP_SIZE];
+        // The first VRAM row is 4 rows in, red for the 8x8 block at the
+        // bottom left and blue for the one above it
+        map[0x80] = 0x01;
+        map[0x81] = 0x02;
+        let rgb = Frame::with_color(&vram, &map).to_rgb(Overlay::Classic);
+        let at = |x: usize, y: usize| &rgb[(y * WIDTH + x) * 3..(y * WIDTH + x) * 3 + 3];
+
+        assert_eq!(at(0, 255), [0xFF, 0x00, 0x00]);
+        assert_eq!(at(7, 248), [0xFF, 0x00, 0x00]);
+        assert_eq!(at(0, 247), [0x00, 0x00, 0xFF]);
+        assert_eq!(at(8, 255), WHITE);
+        assert_eq!(at(100, 40), WHITE);
+        assert_eq!(color_row(WIDTH - 1), 0x3E0..0x400);
+        assert_eq!(palette(0x04), [0x00, 0xFF, 0x00]);
+    }
+}
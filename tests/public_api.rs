@@ -0,0 +1,61 @@
+// Drives the emulator through the library API only
+
+use space_invaders::cpu::{Cpu, Flags, Registers};
+use space_invaders::platform::io::IO;
+use space_invaders::platform::state::GameMode;
+use space_invaders::platform::{Frame, InvadersCabinet, InvadersInput, InvadersState};
+
+#[test]
+fn cpu_runs_a_program() {
+    // MVI A, 0x42; STA 0x2400; HLT
+    let mut cpu = Cpu::init(0, &[0x3E, 0x42, 0x32, 0x00, 0x24, 0x76]);
+    while !cpu.hlted {
+        cpu.step().unwrap();
+    }
+
+    let regs: Registers = cpu.regs;
+    let flags: Flags = cpu.flags;
+    assert_eq!(regs.a, 0x42);
+    assert_eq!(regs.pc, 0x0006);
+    assert!(!flags.carry);
+    assert_eq!(cpu.memory[0x2400], 0x42);
+}
+
+#[test]
+fn cabinet_frames_and_input() {
+    // JMP 0x0020, both interrupt handlers are EI; RET, and at 0x0020
+    // IN 1; STA 0x2400; EI; spin
+    let mut cabinet = InvadersCabinet::init(None).unwrap();
+    let memory = &mut cabinet.cpu_mut().memory;
+    memory[..3].copy_from_slice(&[0xC3, 0x20, 0x00]);
+    memory[0x08..0x0A].copy_from_slice(&[0xFB, 0xC9]);
+    memory[0x10..0x12].copy_from_slice(&[0xFB, 0xC9]);
+    memory[0x20..0x29].copy_from_slice(&[0xDB, 0x01, 0x32, 0x00, 0x24, 0xFB, 0xC3, 0x25, 0x00]);
+    cabinet.cpu_mut().regs.sp = 0x2400;
+    cabinet.set_input(&InvadersInput { p1_start: true, ..Default::default() });
+
+    cabinet.run_frame().unwrap();
+    cabinet.run_frame().unwrap();
+    assert_eq!(cabinet.frames, 2);
+
+    let frame: Frame = cabinet.frame();
+    // Port 1 read 0x0C, bits 2 and 3 of the bottom left byte
+    assert!(frame.pixel(0, 255 - 2));
+    assert!(frame.pixel(0, 255 - 3));
+    assert!(!frame.pixel(0, 255 - 4));
+    #[allow(deprecated)]
+    let old: Vec<u8> = cabinet.get_frame();
+    assert_eq!(old[..], frame.vram[..frame.vram.len() - 1]);
+
+    let state: InvadersState = cabinet.state();
+    assert_eq!(state.mode, GameMode::Attract);
+}
+
+#[test]
+fn io_ports() {
+    let mut io = IO::default();
+    io.cpu_write(4, 0xFF).unwrap();
+    io.cpu_write(2, 0).unwrap();
+    assert_eq!(io.cpu_read(3), Ok(0xFF));
+    assert!(io.cpu_read(9).is_err());
+}
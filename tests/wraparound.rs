@@ -0,0 +1,115 @@
+// Address and register pair arithmetic at the 0x0000/0xFFFF boundary, all of
+// it wraps like on the chip
+
+use space_invaders::cpu::{Cpu, Variant};
+
+// A cpu with the code written from pc on, wrapping past 0xFFFF
+
+fn cpu_at(pc: u16, code: &[u8]) -> Cpu {
+    let mut cpu = Cpu::init(0, &[]);
+    for (i, byte) in code.iter().enumerate() {
+        cpu.memory[pc.wrapping_add(i as u16) as usize] = *byte;
+    }
+    cpu.regs.pc = pc;
+    cpu
+}
+
+#[test]
+fn pc_wraps_at_the_top() {
+    // NOP at 0xFFFF
+    let mut cpu = cpu_at(0xFFFF, &[0x00]);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs.pc, 0x0000);
+
+    // MVI A, 0x42 at 0xFFFF with the operand at 0x0000
+    let mut cpu = cpu_at(0xFFFF, &[0x3E, 0x42]);
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.a, cpu.regs.pc), (0x42, 0x0001));
+
+    let mut cpu = cpu_at(0xFFFF, &[0x00]);
+    cpu.variant = Variant::Z80;
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs.pc, 0x0000);
+}
+
+#[test]
+fn immediates_straddle_the_top() {
+    // LXI H, 0x1234 at 0xFFFE
+    let mut cpu = cpu_at(0xFFFE, &[0x21, 0x34, 0x12]);
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.h, cpu.regs.l, cpu.regs.pc), (0x12, 0x34, 0x0001));
+
+    // JMP 0x0100 at 0xFFFF
+    let mut cpu = cpu_at(0xFFFF, &[0xC3, 0x00, 0x01]);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs.pc, 0x0100);
+
+    // LHLD 0xFFFF and SHLD 0xFFFF use 0xFFFF and 0x0000
+    let mut cpu = cpu_at(0x0100, &[0x2A, 0xFF, 0xFF, 0x22, 0xFF, 0xFF]);
+    cpu.memory[0xFFFF] = 0xCD;
+    cpu.memory[0x0000] = 0xAB;
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.h, cpu.regs.l), (0xAB, 0xCD));
+    (cpu.regs.h, cpu.regs.l) = (0x56, 0x78);
+    cpu.step().unwrap();
+    assert_eq!((cpu.memory[0xFFFF], cpu.memory[0x0000]), (0x78, 0x56));
+}
+
+#[test]
+fn stack_wraps() {
+    // PUSH B; POP D; PUSH PSW; POP PSW with the stack at 0x0000
+    let mut cpu = cpu_at(0x0100, &[0xC5, 0xD1, 0xF5, 0xF1]);
+    cpu.regs.sp = 0x0000;
+    (cpu.regs.b, cpu.regs.c) = (0xBE, 0xEF);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs.sp, 0xFFFE);
+    assert_eq!((cpu.memory[0xFFFF], cpu.memory[0xFFFE]), (0xBE, 0xEF));
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.d, cpu.regs.e, cpu.regs.sp), (0xBE, 0xEF, 0x0000));
+
+    cpu.regs.a = 0x99;
+    cpu.regs.sp = 0x0001;
+    cpu.step().unwrap();
+    assert_eq!((cpu.memory[0x0000], cpu.regs.sp), (0x99, 0xFFFF));
+    cpu.regs.a = 0;
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.a, cpu.regs.sp), (0x99, 0x0001));
+
+    // CALL 0x0200 with the return address split over the top, then RET
+    let mut cpu = cpu_at(0x0100, &[0xCD, 0x00, 0x02]);
+    cpu.memory[0x0200] = 0xC9;
+    cpu.regs.sp = 0x0001;
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.pc, cpu.regs.sp), (0x0200, 0xFFFF));
+    assert_eq!((cpu.memory[0x0000], cpu.memory[0xFFFF]), (0x01, 0x03));
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.pc, cpu.regs.sp), (0x0103, 0x0001));
+
+    // XTHL with the stack at 0xFFFF
+    let mut cpu = cpu_at(0x0100, &[0xE3]);
+    cpu.regs.sp = 0xFFFF;
+    (cpu.regs.h, cpu.regs.l) = (0x11, 0x22);
+    cpu.memory[0xFFFF] = 0x44;
+    cpu.memory[0x0000] = 0x33;
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.h, cpu.regs.l), (0x33, 0x44));
+    assert_eq!((cpu.memory[0xFFFF], cpu.memory[0x0000]), (0x22, 0x11));
+}
+
+#[test]
+fn register_pairs_wrap() {
+    // INX B; DCX B; INX SP; DCX SP; DCX SP
+    let mut cpu = cpu_at(0x0100, &[0x03, 0x0B, 0x33, 0x3B, 0x3B]);
+    (cpu.regs.b, cpu.regs.c) = (0xFF, 0xFF);
+    cpu.regs.sp = 0xFFFF;
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.b, cpu.regs.c), (0x00, 0x00));
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs.b, cpu.regs.c), (0xFF, 0xFF));
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs.sp, 0x0000);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs.sp, 0xFFFF);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs.sp, 0xFFFE);
+}